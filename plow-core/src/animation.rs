@@ -0,0 +1,382 @@
+use std::collections::HashMap;
+use strum::{EnumIter, IntoStaticStr};
+
+use crate::{
+    buffer::Image,
+    canvas::Canvas,
+    upscale::{upscale_image, upscaled_size},
+};
+
+/// Pixels with lower alpha than this are fully transparent in gifs, and the rest are fully opaque, since gifs only have one transparent color
+const GIF_ALPHA_THRESHOLD: u8 = 128;
+/// Sample factor of NeuQuant when an animation has too many colors for a gif palette, from 1 (best quality) to 30 (fastest)
+const GIF_QUANTIZE_SPEED: i32 = 10;
+
+/// Format animations can be exported as
+#[derive(Clone, Copy, PartialEq, IntoStaticStr, EnumIter)]
+#[strum(serialize_all = "lowercase")]
+pub enum AnimationFormat {
+    Gif,
+    Apng,
+    /// Lossless animated webp
+    WebP,
+}
+
+impl AnimationFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            AnimationFormat::Gif => "gif",
+            AnimationFormat::Apng => "png",
+            AnimationFormat::WebP => "webp",
+        }
+    }
+}
+
+/// What the frames of an exported animation are made of
+#[derive(Clone, Copy, PartialEq, IntoStaticStr, EnumIter)]
+#[strum(serialize_all = "lowercase")]
+pub enum AnimationSource {
+    /// Each frame of the canvas, with all visible layers
+    Frames,
+    /// Each visible layer of the current frame, from bottom to top
+    Layers,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct AnimationOptions {
+    pub format: AnimationFormat,
+    pub source: AnimationSource,
+    /// How many times the animation is played, 0 to loop forever
+    pub plays: u16,
+    /// Duration of each frame in milliseconds when frames are made of layers, since layers don't have their own durations
+    pub layer_duration: u32,
+}
+
+/// Get images of the frames of an animation, with their durations in milliseconds
+pub fn animation_frames(canvas: &Canvas, options: &AnimationOptions) -> Vec<(Image, u32)> {
+    match options.source {
+        AnimationSource::Frames => canvas
+            .frames
+            .iter()
+            .enumerate()
+            .map(|(index, frame)| (canvas.frame_to_image(index), frame.duration))
+            .collect(),
+        AnimationSource::Layers => (0..canvas.layers.len())
+            .rev()
+            .filter(|index| canvas.layers[*index].visible)
+            .map(|index| (canvas.layer_to_image(index), options.layer_duration))
+            .collect(),
+    }
+}
+
+/// Encode canvas as an animation
+pub fn animation_to_bytes(
+    canvas: &Canvas,
+    options: &AnimationOptions,
+) -> Result<Vec<u8>, std::io::Error> {
+    let mut frames = animation_frames(canvas, options);
+    if frames.is_empty() {
+        return Err(std::io::Error::other("animation has no frames"));
+    }
+    upscaled_size(canvas.width, canvas.height, canvas.export_upscale)?;
+    for (image, _) in frames.iter_mut() {
+        *image = upscale_image(image, canvas.export_upscale);
+    }
+    match options.format {
+        AnimationFormat::Gif => encode_gif(&frames, options.plays),
+        AnimationFormat::Apng => encode_apng(&frames, options.plays),
+        AnimationFormat::WebP => encode_webp(&frames, options.plays),
+    }
+}
+
+/// Palette of the opaque colors of a gif
+enum GifPalette {
+    /// Every color fits in the palette, by index
+    Exact(HashMap<[u8; 3], u8>),
+    /// Colors had to be reduced
+    Quantized(color_quant::NeuQuant),
+}
+
+impl GifPalette {
+    /// Build a palette of at most 255 colors for the opaque pixels of every frame, so colors don't flicker between frames
+    fn new(frames: &[(Image, u32)]) -> Self {
+        let mut colors: HashMap<[u8; 3], u8> = HashMap::new();
+        let mut opaque_pixels = Vec::new();
+        let mut too_many_colors = false;
+        for (image, _) in frames {
+            for pixel in image.get_image_data() {
+                if pixel[3] < GIF_ALPHA_THRESHOLD {
+                    continue;
+                }
+                opaque_pixels.extend_from_slice(&[pixel[0], pixel[1], pixel[2], 255]);
+                let color = [pixel[0], pixel[1], pixel[2]];
+                if !too_many_colors && !colors.contains_key(&color) {
+                    if colors.len() == 255 {
+                        too_many_colors = true;
+                    } else {
+                        colors.insert(color, colors.len() as u8);
+                    }
+                }
+            }
+        }
+        if too_many_colors {
+            GifPalette::Quantized(color_quant::NeuQuant::new(
+                GIF_QUANTIZE_SPEED,
+                255,
+                &opaque_pixels,
+            ))
+        } else {
+            GifPalette::Exact(colors)
+        }
+    }
+    /// Get palette as rgb bytes
+    fn to_rgb(&self) -> Vec<u8> {
+        match self {
+            GifPalette::Exact(colors) => {
+                let mut palette = vec![0; colors.len() * 3];
+                for (color, index) in colors {
+                    let index = *index as usize * 3;
+                    palette[index..index + 3].copy_from_slice(color);
+                }
+                palette
+            }
+            GifPalette::Quantized(quantizer) => quantizer.color_map_rgb(),
+        }
+    }
+    /// Get palette index of an opaque color
+    fn index_of(&self, pixel: [u8; 4]) -> u8 {
+        match self {
+            GifPalette::Exact(colors) => colors[&[pixel[0], pixel[1], pixel[2]]],
+            GifPalette::Quantized(quantizer) => {
+                quantizer.index_of(&[pixel[0], pixel[1], pixel[2], 255]) as u8
+            }
+        }
+    }
+}
+
+/// Encode frames as an animated gif, with durations in milliseconds. Plays is how many times the animation is played, 0 to loop forever
+pub fn encode_gif(frames: &[(Image, u32)], plays: u16) -> Result<Vec<u8>, std::io::Error> {
+    let (width, height) = (frames[0].0.width, frames[0].0.height);
+    let gif_palette = GifPalette::new(frames);
+    let mut palette = gif_palette.to_rgb();
+    // the color after the opaque colors is used for transparent pixels
+    let transparent = (palette.len() / 3) as u8;
+    palette.extend_from_slice(&[0, 0, 0]);
+
+    let mut bytes = Vec::new();
+    {
+        let mut encoder = gif::Encoder::new(&mut bytes, width, height, &palette)
+            .map_err(std::io::Error::other)?;
+        // gifs store how many times the animation is repeated after being played once
+        let repeat = match plays {
+            0 => gif::Repeat::Infinite,
+            plays => gif::Repeat::Finite(plays - 1),
+        };
+        encoder.set_repeat(repeat).map_err(std::io::Error::other)?;
+        for (image, duration) in frames {
+            let buffer: Vec<u8> = image
+                .get_image_data()
+                .iter()
+                .map(|pixel| {
+                    if pixel[3] < GIF_ALPHA_THRESHOLD {
+                        transparent
+                    } else {
+                        gif_palette.index_of(*pixel)
+                    }
+                })
+                .collect();
+            let frame = gif::Frame {
+                width,
+                height,
+                // gif delays are in hundredths of a second, and most viewers play delays shorter than 2 slowly
+                delay: (duration.div_ceil(10)).clamp(2, u16::MAX as u32) as u16,
+                // clear each frame before the next, so transparent pixels don't show the previous frame
+                dispose: gif::DisposalMethod::Background,
+                transparent: Some(transparent),
+                buffer: buffer.into(),
+                ..gif::Frame::default()
+            };
+            encoder.write_frame(&frame).map_err(std::io::Error::other)?;
+        }
+    }
+    Ok(bytes)
+}
+
+/// Encode frames as an animated png, with durations in milliseconds. Plays is how many times the animation is played, 0 to loop forever
+pub fn encode_apng(frames: &[(Image, u32)], plays: u16) -> Result<Vec<u8>, std::io::Error> {
+    let (width, height) = (frames[0].0.width, frames[0].0.height);
+    let mut bytes = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut bytes, width as u32, height as u32);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .set_animated(frames.len() as u32, plays as u32)
+            .map_err(std::io::Error::other)?;
+        let mut writer = encoder.write_header().map_err(std::io::Error::other)?;
+        for (image, duration) in frames {
+            writer
+                .set_frame_delay((*duration).min(u16::MAX as u32) as u16, 1000)
+                .map_err(std::io::Error::other)?;
+            writer
+                .set_dispose_op(png::DisposeOp::Background)
+                .map_err(std::io::Error::other)?;
+            writer
+                .write_image_data(&image.bytes)
+                .map_err(std::io::Error::other)?;
+        }
+        writer.finish().map_err(std::io::Error::other)?;
+    }
+    Ok(bytes)
+}
+
+/// Append a riff chunk, padded to an even length
+fn write_riff_chunk(bytes: &mut Vec<u8>, fourcc: &[u8; 4], data: &[u8]) {
+    bytes.extend_from_slice(fourcc);
+    bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        bytes.push(0);
+    }
+}
+
+/// Little endian 24 bit number, as used by the webp container
+fn u24_bytes(value: u32) -> [u8; 3] {
+    let [a, b, c, _] = value.min(0xffffff).to_le_bytes();
+    [a, b, c]
+}
+
+/// Encode frames as a lossless animated webp, with durations in milliseconds. Plays is how many times the animation is played, 0 to loop forever
+pub fn encode_webp(frames: &[(Image, u32)], plays: u16) -> Result<Vec<u8>, std::io::Error> {
+    let (width, height) = (frames[0].0.width as u32, frames[0].0.height as u32);
+
+    // image can only encode still webps, so each frame is encoded alone and its VP8L chunk is moved into the animation
+    let mut chunks = Vec::new();
+    let mut header = vec![0x12, 0, 0, 0]; // has alpha and animation
+    header.extend_from_slice(&u24_bytes(width - 1));
+    header.extend_from_slice(&u24_bytes(height - 1));
+    write_riff_chunk(&mut chunks, b"VP8X", &header);
+    // transparent background color, then loop count
+    let mut animation = vec![0; 4];
+    animation.extend_from_slice(&plays.to_le_bytes());
+    write_riff_chunk(&mut chunks, b"ANIM", &animation);
+    for (image, duration) in frames {
+        let mut still = Vec::new();
+        image::codecs::webp::WebPEncoder::new_lossless(&mut still)
+            .encode(&image.bytes, width, height, image::ColorType::Rgba8)
+            .map_err(std::io::Error::other)?;
+        // offset of 0, 0, then size minus 1 and duration
+        let mut frame = vec![0; 6];
+        frame.extend_from_slice(&u24_bytes(width - 1));
+        frame.extend_from_slice(&u24_bytes(height - 1));
+        frame.extend_from_slice(&u24_bytes(*duration));
+        // replace the previous frame instead of blending with it, so transparent pixels don't show it
+        frame.push(0b10);
+        // skip the RIFF header of the still image, leaving the VP8L chunk
+        frame.extend_from_slice(&still[12..]);
+        write_riff_chunk(&mut chunks, b"ANMF", &frame);
+    }
+
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&(chunks.len() as u32 + 4).to_le_bytes());
+    bytes.extend_from_slice(b"WEBP");
+    bytes.extend_from_slice(&chunks);
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two 2x1 frames, the second half transparent
+    fn frames() -> Vec<(Image, u32)> {
+        let image = |color: [u8; 4]| Image {
+            width: 2,
+            height: 1,
+            bytes: [color, [0; 4]].concat(),
+        };
+        vec![
+            (image([255, 0, 0, 255]), 100),
+            (image([0, 0, 255, 255]), 250),
+        ]
+    }
+
+    #[test]
+    fn gif_keeps_frames_plays_and_delays() {
+        let bytes = encode_gif(&frames(), 3).unwrap();
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::RGBA);
+        let mut decoder = options.read_info(&bytes[..]).unwrap();
+        let mut delays = Vec::new();
+        let mut first_pixels = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            delays.push(frame.delay);
+            first_pixels.push(frame.buffer[..4].to_vec());
+        }
+        // in hundredths of a second
+        assert_eq!(delays, [10, 25]);
+        assert_eq!(first_pixels, [[255, 0, 0, 255], [0, 0, 255, 255]]);
+        // repeated after the first play
+        assert_eq!(decoder.repeat(), gif::Repeat::Finite(2));
+
+        let bytes = encode_gif(&frames(), 0).unwrap();
+        let mut decoder = gif::DecodeOptions::new().read_info(&bytes[..]).unwrap();
+        while decoder.read_next_frame().unwrap().is_some() {}
+        assert_eq!(decoder.repeat(), gif::Repeat::Infinite);
+    }
+
+    #[test]
+    fn apng_keeps_frames_plays_and_delays() {
+        let bytes = encode_apng(&frames(), 3).unwrap();
+        let mut reader = png::Decoder::new(&bytes[..]).read_info().unwrap();
+        let control = reader.info().animation_control().copied().unwrap();
+        assert_eq!((control.num_frames, control.num_plays), (2, 3));
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let mut delays = Vec::new();
+        for _ in 0..control.num_frames {
+            reader.next_frame(&mut buffer).unwrap();
+            let frame_control = reader.info().frame_control.unwrap();
+            delays.push((frame_control.delay_num, frame_control.delay_den));
+        }
+        assert_eq!(delays, [(100, 1000), (250, 1000)]);
+    }
+
+    #[test]
+    fn webp_keeps_frames_plays_and_delays() {
+        let bytes = encode_webp(&frames(), 3).unwrap();
+        assert_eq!(&bytes[..4], b"RIFF");
+        assert_eq!(
+            u32::from_le_bytes(bytes[4..8].try_into().unwrap()) as usize,
+            bytes.len() - 8
+        );
+
+        // walk the chunks for the loop count and frame durations
+        let mut plays = None;
+        let mut durations = Vec::new();
+        let mut offset = 12;
+        while offset < bytes.len() {
+            let fourcc = &bytes[offset..offset + 4];
+            let size = u32::from_le_bytes(bytes[offset + 4..offset + 8].try_into().unwrap());
+            let data = &bytes[offset + 8..offset + 8 + size as usize];
+            match fourcc {
+                b"ANIM" => plays = Some(u16::from_le_bytes([data[4], data[5]])),
+                b"ANMF" => durations.push(u32::from_le_bytes([data[12], data[13], data[14], 0])),
+                _ => {}
+            }
+            offset += 8 + size as usize + size as usize % 2;
+        }
+        assert_eq!(plays, Some(3));
+        assert_eq!(durations, [100, 250]);
+
+        // and check that it decodes to the same frames
+        use image::AnimationDecoder;
+        let decoder = image::codecs::webp::WebPDecoder::new(&bytes[..]).unwrap();
+        let decoded = decoder.into_frames().collect_frames().unwrap();
+        assert_eq!(decoded.len(), 2);
+        for ((image, duration), frame) in frames().iter().zip(&decoded) {
+            assert_eq!(frame.buffer().as_raw(), &image.bytes);
+            assert_eq!(frame.delay().numer_denom_ms(), (*duration, 1));
+        }
+    }
+}
@@ -0,0 +1,69 @@
+/// Rectangle, used for regions of images
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rect {
+    pub x: f32,
+    pub y: f32,
+    pub w: f32,
+    pub h: f32,
+}
+
+impl Rect {
+    pub fn new(x: f32, y: f32, w: f32, h: f32) -> Self {
+        Rect { x, y, w, h }
+    }
+}
+
+/// Rgba image, with 4 bytes per pixel
+#[derive(Clone)]
+pub struct Image {
+    pub width: u16,
+    pub height: u16,
+    pub bytes: Vec<u8>,
+}
+
+impl Image {
+    pub fn width(&self) -> usize {
+        self.width as usize
+    }
+    pub fn height(&self) -> usize {
+        self.height as usize
+    }
+    pub fn get_image_data(&self) -> &[[u8; 4]] {
+        // safe since [u8; 4] has the same alignment as u8, and the length is rounded down to whole pixels
+        unsafe {
+            std::slice::from_raw_parts(self.bytes.as_ptr() as *const [u8; 4], self.bytes.len() / 4)
+        }
+    }
+    pub fn get_image_data_mut(&mut self) -> &mut [[u8; 4]] {
+        unsafe {
+            std::slice::from_raw_parts_mut(
+                self.bytes.as_mut_ptr() as *mut [u8; 4],
+                self.bytes.len() / 4,
+            )
+        }
+    }
+    pub fn get_pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        self.get_image_data()[x as usize + y as usize * self.width()]
+    }
+    pub fn set_pixel(&mut self, x: u32, y: u32, color: [u8; 4]) {
+        let width = self.width();
+        self.get_image_data_mut()[x as usize + y as usize * width] = color;
+    }
+    /// Copy region of image to a new image
+    pub fn sub_image(&self, region: Rect) -> Image {
+        let x = region.x as usize;
+        let y = region.y as usize;
+        let width = region.w as usize;
+        let height = region.h as usize;
+        let mut bytes = Vec::with_capacity(width * height * 4);
+        for row in y..y + height {
+            let start = (row * self.width() + x) * 4;
+            bytes.extend_from_slice(&self.bytes[start..start + width * 4]);
+        }
+        Image {
+            width: width as u16,
+            height: height as u16,
+            bytes,
+        }
+    }
+}
@@ -0,0 +1,2071 @@
+use image::ImageFormat;
+use line_drawing::Bresenham;
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    io::Cursor,
+    path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use strum::{EnumIter, EnumString, IntoStaticStr};
+
+use crate::{
+    animation::{animation_to_bytes, AnimationOptions},
+    buffer::{Image, Rect},
+    consts::DEFAULT_FRAME_DURATION,
+    fill::{flood_region, global_region},
+    filter::{apply_filter, Filter, FilterSettings},
+    indexed::*,
+    ora::{ora_to_bytes, ORA_EXTENSION},
+    palette::{nearest_color, remap_image, Dither, Palette},
+    project::{project_to_bytes, PROJECT_EXTENSION},
+    stroke::Stroke,
+    upscale::{upscale_image, upscaled_size, UpscaleOptions, Upscaler},
+};
+
+pub fn gen_empty_image(width: u16, height: u16) -> Image {
+    let bytes = vec![0; width as usize * height as usize * 4];
+    Image {
+        width,
+        bytes,
+        height,
+    }
+}
+
+/// Placeholder for the slot of the current frame in [Layer::frames]
+fn empty_frame_slot() -> Image {
+    gen_empty_image(0, 0)
+}
+
+/// Place image on a new transparent image of the given size, at x, y
+pub fn place_image(image: &Image, width: u16, height: u16, x: i32, y: i32) -> Image {
+    if image.width == width && image.height == height && x == 0 && y == 0 {
+        return image.clone();
+    }
+    let mut new = gen_empty_image(width, height);
+    for source_y in 0..image.height as i32 {
+        for source_x in 0..image.width as i32 {
+            let (dest_x, dest_y) = (source_x + x, source_y + y);
+            if dest_x < 0 || dest_y < 0 || dest_x >= width as i32 || dest_y >= height as i32 {
+                continue;
+            }
+            let color = image.get_pixel(source_x as u32, source_y as u32);
+            new.set_pixel(dest_x as u32, dest_y as u32, color);
+        }
+    }
+    new
+}
+
+pub fn validate_canvas_size(canvas_width: u16, canvas_height: u16) -> bool {
+    if canvas_width.max(canvas_height) > 32768 {
+        return false;
+    }
+    if canvas_width as u64 * canvas_height as u64 > 1073676289 {
+        return false;
+    }
+    true
+}
+
+pub fn canvas_too_big_error() -> std::io::Error {
+    std::io::Error::other("canvas too big! no dimension may be greater than 32768, and the product of the width and height may not be greater than 1073676289")
+}
+
+/// Resize image to width and height without scaling its contents. Anchor is where the old image is placed, from (0, 0) for top left to (1, 1) for bottom right.
+/// New areas are transparent.
+pub fn resize_image(image: &Image, width: u16, height: u16, anchor: (f32, f32)) -> Image {
+    let x = ((width as f32 - image.width as f32) * anchor.0).round() as i32;
+    let y = ((height as f32 - image.height as f32) * anchor.1).round() as i32;
+    place_image(image, width, height, x, y)
+}
+
+/// Method used when scaling images
+#[derive(Clone, Copy, PartialEq, IntoStaticStr, EnumIter)]
+#[strum(serialize_all = "lowercase")]
+pub enum ScaleMode {
+    /// Keeps pixels sharp, by using the nearest pixel
+    Nearest,
+    /// Smooth, by blending the 4 nearest pixels
+    Bilinear,
+}
+
+/// Scale contents of image to width and height
+pub fn scale_image(image: &Image, width: u16, height: u16, mode: ScaleMode) -> Image {
+    let mut new = gen_empty_image(width, height);
+    if image.width == 0 || image.height == 0 {
+        return new;
+    }
+    let scale_x = image.width as f32 / width as f32;
+    let scale_y = image.height as f32 / height as f32;
+    let pixels = image.get_image_data();
+    let source_width = image.width as usize;
+    for (index, pixel) in new.get_image_data_mut().iter_mut().enumerate() {
+        let x = index % width as usize;
+        let y = index / width as usize;
+        // position of the center of the new pixel, in source pixels
+        let source_x = (x as f32 + 0.5) * scale_x;
+        let source_y = (y as f32 + 0.5) * scale_y;
+        *pixel = match mode {
+            ScaleMode::Nearest => {
+                let source_x = (source_x as usize).min(image.width as usize - 1);
+                let source_y = (source_y as usize).min(image.height as usize - 1);
+                pixels[source_x + source_y * source_width]
+            }
+            ScaleMode::Bilinear => {
+                let source_x = (source_x - 0.5).clamp(0., image.width as f32 - 1.);
+                let source_y = (source_y - 0.5).clamp(0., image.height as f32 - 1.);
+                let (x1, y1) = (source_x as usize, source_y as usize);
+                let x2 = (x1 + 1).min(image.width as usize - 1);
+                let y2 = (y1 + 1).min(image.height as usize - 1);
+                let (fx, fy) = (source_x.fract(), source_y.fract());
+                let samples = [
+                    (pixels[x1 + y1 * source_width], (1. - fx) * (1. - fy)),
+                    (pixels[x2 + y1 * source_width], fx * (1. - fy)),
+                    (pixels[x1 + y2 * source_width], (1. - fx) * fy),
+                    (pixels[x2 + y2 * source_width], fx * fy),
+                ];
+                // blend with premultiplied alpha, so transparent pixels don't darken the edges
+                let mut color = [0.; 4];
+                for (sample, weight) in samples {
+                    let alpha = sample[3] as f32 * weight;
+                    for channel in 0..3 {
+                        color[channel] += sample[channel] as f32 * alpha;
+                    }
+                    color[3] += alpha;
+                }
+                if color[3] > 0. {
+                    [
+                        (color[0] / color[3]).round() as u8,
+                        (color[1] / color[3]).round() as u8,
+                        (color[2] / color[3]).round() as u8,
+                        color[3].round() as u8,
+                    ]
+                } else {
+                    [0; 4]
+                }
+            }
+        };
+    }
+    new
+}
+
+/// Draw line with stroke on layer, painting only pixels of its pattern. If selection is Some, only pixels within it are drawn.
+///
+/// If wrap is true, pixels outside of the layer wrap around to the other side, like in tile mode.
+#[allow(clippy::too_many_arguments)]
+pub fn draw_line_image(
+    layer: &mut Layer,
+    color: [u8; 4],
+    x1: i16,
+    y1: i16,
+    x2: i16,
+    y2: i16,
+    stroke: &Stroke,
+    selection: Option<&Selection>,
+    wrap: bool,
+) {
+    let (width, height) = (layer.width() as i32, layer.height() as i32);
+    for (x, y) in Bresenham::new((x1, y1), (x2, y2)) {
+        for (stroke_x, row) in stroke.pixels.iter().enumerate() {
+            for (stroke_y, value) in row.iter().enumerate() {
+                if !value {
+                    continue;
+                }
+                let mut x = x as i32 + stroke_x as i32 + stroke.pixels_offset as i32;
+                let mut y = y as i32 + stroke_y as i32 + stroke.pixels_offset as i32;
+                if wrap {
+                    (x, y) = (x.rem_euclid(width), y.rem_euclid(height));
+                }
+                let x = x.try_into();
+                let y = y.try_into();
+                if x.is_ok() && y.is_ok() {
+                    let x = x.unwrap();
+                    let y = y.unwrap();
+                    if x < layer.width() as u32
+                        && y < layer.height() as u32
+                        && selection.is_none_or(|s| s.contains(x as usize, y as usize))
+                        && stroke.paints(x as usize, y as usize)
+                    {
+                        layer.set_pixel(x, y, color);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// How a layer's colors are combined with the layers below it
+#[derive(Clone, Copy, PartialEq, IntoStaticStr, EnumString, EnumIter)]
+#[strum(serialize_all = "lowercase")]
+pub enum BlendMode {
+    Normal,
+    Multiply,
+    Screen,
+    Overlay,
+    Add,
+    Darken,
+    Lighten,
+    Difference,
+}
+
+impl BlendMode {
+    /// Blend a single color channel of the source (top) with the backdrop (bottom)
+    fn blend_channel(&self, backdrop: f32, source: f32) -> f32 {
+        match self {
+            BlendMode::Normal => source,
+            BlendMode::Multiply => backdrop * source,
+            BlendMode::Screen => backdrop + source - backdrop * source,
+            BlendMode::Overlay => {
+                if backdrop <= 0.5 {
+                    2. * backdrop * source
+                } else {
+                    let backdrop = 2. * backdrop - 1.;
+                    backdrop + source - backdrop * source
+                }
+            }
+            BlendMode::Add => (backdrop + source).min(1.),
+            BlendMode::Darken => backdrop.min(source),
+            BlendMode::Lighten => backdrop.max(source),
+            BlendMode::Difference => (backdrop - source).abs(),
+        }
+    }
+}
+
+/// Composite source color on top of backdrop color with blend mode and opacity. Colors are non-premultiplied.
+pub fn blend_colors(
+    backdrop: [f32; 4],
+    source: [f32; 4],
+    mode: BlendMode,
+    opacity: f32,
+) -> [f32; 4] {
+    let source_alpha = source[3] * opacity;
+    let backdrop_alpha = backdrop[3];
+    let alpha = source_alpha + backdrop_alpha * (1. - source_alpha);
+    if alpha <= 0. {
+        return [0.; 4];
+    }
+    let mut new = [0., 0., 0., alpha];
+    for channel in 0..3 {
+        // where the backdrop is transparent, the source color is used as is
+        let blended = (1. - backdrop_alpha) * source[channel]
+            + backdrop_alpha * mode.blend_channel(backdrop[channel], source[channel]);
+        new[channel] = (source_alpha * blended
+            + backdrop_alpha * backdrop[channel] * (1. - source_alpha))
+            / alpha;
+    }
+    new
+}
+
+fn color_to_floats(color: [u8; 4]) -> [f32; 4] {
+    [
+        color[0] as f32 / 255.,
+        color[1] as f32 / 255.,
+        color[2] as f32 / 255.,
+        color[3] as f32 / 255.,
+    ]
+}
+
+fn floats_to_color(color: [f32; 4]) -> [u8; 4] {
+    [
+        (color[0] * 255.).round() as u8,
+        (color[1] * 255.).round() as u8,
+        (color[2] * 255.).round() as u8,
+        (color[3] * 255.).round() as u8,
+    ]
+}
+
+/// Keeps track of the largest and smallest coordinates given to it by track(). Can be flushed to generate a Rect for its area, and wipe coordinate data.
+#[derive(Clone)]
+pub struct BoundsTracker {
+    empty: bool,
+    min_x: u32,
+    min_y: u32,
+    max_x: u32,
+    max_y: u32,
+}
+impl Default for BoundsTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl BoundsTracker {
+    pub fn new() -> Self {
+        BoundsTracker {
+            empty: true,
+            min_x: 0,
+            min_y: 0,
+            max_x: 0,
+            max_y: 0,
+        }
+    }
+    pub fn track(&mut self, x: u32, y: u32) {
+        if self.min_x > x || self.empty {
+            self.min_x = x;
+        }
+        if self.min_y > y || self.empty {
+            self.min_y = y;
+        }
+        if self.max_x < x || self.empty {
+            self.max_x = x;
+        }
+        if self.max_y < y || self.empty {
+            self.max_y = y;
+        }
+        self.empty = false;
+    }
+    pub fn to_rect(&self) -> Option<Rect> {
+        if self.empty {
+            return None;
+        }
+        Some(Rect {
+            x: self.min_x as f32,
+            y: self.min_y as f32,
+            w: (self.max_x - self.min_x + 1) as f32,
+            h: (self.max_y - self.min_y + 1) as f32,
+        })
+    }
+    pub fn flush(&mut self) -> Option<Rect> {
+        if self.empty {
+            return None;
+        }
+        self.empty = true;
+        Some(Rect {
+            x: self.min_x as f32,
+            y: self.min_y as f32,
+            w: (self.max_x - self.min_x + 1) as f32,
+            h: (self.max_y - self.min_y + 1) as f32,
+        })
+    }
+}
+
+/// How a new selection is combined with the existing one
+pub enum SelectionMode {
+    Replace,
+    Add,
+    Subtract,
+    Intersect,
+}
+
+/// Mask of which pixels of a canvas are selected
+#[derive(Clone)]
+pub struct Selection {
+    width: usize,
+    height: usize,
+    mask: Vec<bool>,
+    /// Lines along the edges of the selected area, used to draw the marching ants
+    pub borders: Vec<((usize, usize), (usize, usize))>,
+}
+
+impl Selection {
+    pub fn from_mask(width: usize, height: usize, mask: Vec<bool>) -> Self {
+        let borders = Self::generate_borders(width, height, &mask);
+        Selection {
+            width,
+            height,
+            mask,
+            borders,
+        }
+    }
+    /// Select all pixels within rect
+    pub fn from_rect(width: usize, height: usize, rect: Rect) -> Self {
+        let mut mask = vec![false; width * height];
+        for y in rect.y as usize..(rect.y + rect.h) as usize {
+            for x in rect.x as usize..(rect.x + rect.w) as usize {
+                mask[x + y * width] = true;
+            }
+        }
+        Self::from_mask(width, height, mask)
+    }
+    pub fn all(width: usize, height: usize) -> Self {
+        Self::from_mask(width, height, vec![true; width * height])
+    }
+    pub fn inverted(&self) -> Self {
+        let mask = self.mask.iter().map(|value| !value).collect();
+        Self::from_mask(self.width, self.height, mask)
+    }
+    /// Combine with another selection of the same size
+    pub fn combine(&self, other: &Selection, mode: SelectionMode) -> Self {
+        let mask = self
+            .mask
+            .iter()
+            .zip(&other.mask)
+            .map(|(a, b)| match mode {
+                SelectionMode::Replace => *b,
+                SelectionMode::Add => *a || *b,
+                SelectionMode::Subtract => *a && !*b,
+                SelectionMode::Intersect => *a && *b,
+            })
+            .collect();
+        Self::from_mask(self.width, self.height, mask)
+    }
+    /// Check if pixel is selected. Pixels out of bounds are never selected.
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        x < self.width && y < self.height && self.mask[x + y * self.width]
+    }
+    pub fn is_empty(&self) -> bool {
+        !self.mask.contains(&true)
+    }
+    /// Get smallest rect containing all selected pixels
+    pub fn bounds(&self) -> Option<Rect> {
+        let mut bounds_tracker = BoundsTracker::new();
+        for (index, value) in self.mask.iter().enumerate() {
+            if *value {
+                bounds_tracker.track((index % self.width) as u32, (index / self.width) as u32);
+            }
+        }
+        bounds_tracker.to_rect()
+    }
+    /// Generate lines along the edges between selected and unselected pixels. Neighbouring edges are merged into a single line.
+    fn generate_borders(
+        width: usize,
+        height: usize,
+        mask: &[bool],
+    ) -> Vec<((usize, usize), (usize, usize))> {
+        let selected = |x: usize, y: usize| x < width && y < height && mask[x + y * width];
+        let mut new = Vec::new();
+
+        // horizontal lines, along the top edge of row y
+        for y in 0..=height {
+            let mut start = None;
+            for x in 0..=width {
+                let edge = x < width && selected(x, y) != (y > 0 && selected(x, y - 1));
+                match (edge, start) {
+                    (true, None) => start = Some(x),
+                    (false, Some(start_x)) => {
+                        new.push(((start_x, y), (x, y)));
+                        start = None;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        // vertical lines, along the left edge of column x
+        for x in 0..=width {
+            let mut start = None;
+            for y in 0..=height {
+                let edge = y < height && selected(x, y) != (x > 0 && selected(x - 1, y));
+                match (edge, start) {
+                    (true, None) => start = Some(y),
+                    (false, Some(start_y)) => {
+                        new.push(((x, start_y), (x, y)));
+                        start = None;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        new
+    }
+}
+
+/// Id given to the next created layer
+static NEXT_LAYER_ID: AtomicU64 = AtomicU64::new(0);
+
+pub struct Layer {
+    /// Unique id of layer, so renderers can keep track of which textures belong to which layers
+    pub id: u64,
+    pub name: String,
+    pub visible: bool,
+    /// Opacity of layer, from 0 to 1
+    pub opacity: f32,
+    pub blend_mode: BlendMode,
+    /// Image of the layer in the canvas' current frame, which is the one being edited
+    pub image: Image,
+    /// Images of the layer in every frame of the canvas. The slot of the current frame is left empty, since its image is kept in `image`
+    pub frames: Vec<Image>,
+    pub bounds_tracker: BoundsTracker,
+    /// Tracks which region of the layer has been updated since it was last drawn, so renderers only have to update that part
+    pub render_tracker: BoundsTracker,
+    /// Tracks which region of the layer has been updated since the canvas' composite was last updated
+    pub composite_tracker: BoundsTracker,
+    pub modified: bool,
+}
+
+impl Clone for Layer {
+    /// Cloned layers get a new id, since they may be changed independently
+    fn clone(&self) -> Self {
+        let mut layer = Layer::new(self.image.clone(), self.name.clone());
+        layer.visible = self.visible;
+        layer.opacity = self.opacity;
+        layer.blend_mode = self.blend_mode;
+        layer.frames = self.frames.clone();
+        layer.bounds_tracker = self.bounds_tracker.clone();
+        layer.modified = self.modified;
+        layer
+    }
+}
+
+impl Hash for Layer {
+    /// Layers are identified by their id, same as in the composite signature
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+impl Layer {
+    pub fn new(image: Image, name: String) -> Self {
+        Layer {
+            id: NEXT_LAYER_ID.fetch_add(1, Ordering::Relaxed),
+            image,
+            frames: vec![empty_frame_slot()],
+            name,
+            visible: true,
+            opacity: 1.,
+            blend_mode: BlendMode::Normal,
+            bounds_tracker: BoundsTracker::new(),
+            render_tracker: BoundsTracker::new(),
+            composite_tracker: BoundsTracker::new(),
+            modified: false,
+        }
+    }
+    pub fn width(&self) -> usize {
+        self.image.width()
+    }
+    pub fn height(&self) -> usize {
+        self.image.height()
+    }
+    pub fn set_pixel(&mut self, x: u32, y: u32, color: [u8; 4]) {
+        self.bounds_tracker.track(x, y);
+        self.image.set_pixel(x, y, color);
+        self.modified = true;
+    }
+    /// Mark region tracked by bounds_tracker as updated, and reset it
+    pub fn flush_bounds(&mut self) {
+        let bounds = self.bounds_tracker.flush();
+        self.force_update_region(bounds);
+    }
+    pub fn get_image_data_mut(&mut self) -> &mut [[u8; 4]] {
+        self.modified = true;
+        self.image.get_image_data_mut()
+    }
+    /// Mark region of layer as updated, so it is redrawn. If region is None, the entire layer is updated.
+    pub fn force_update_region(&mut self, region: Option<Rect>) {
+        let region = region.unwrap_or(Rect::new(0., 0., self.width() as f32, self.height() as f32));
+        if region.w <= 0. || region.h <= 0. {
+            return;
+        }
+        for tracker in [&mut self.render_tracker, &mut self.composite_tracker] {
+            tracker.track(region.x as u32, region.y as u32);
+            tracker.track(
+                (region.x + region.w) as u32 - 1,
+                (region.y + region.h) as u32 - 1,
+            );
+        }
+    }
+    /// Whether layer is drawn in a way that requires compositing all layers on the cpu
+    fn needs_composite(&self) -> bool {
+        self.visible && (self.opacity < 1. || self.blend_mode != BlendMode::Normal)
+    }
+}
+
+pub fn overlay_colors(c1: [f32; 4], c2: [f32; 4]) -> [f32; 4] {
+    [
+        f32::min(c1[0] + (1. - c1[3]) * c2[0], 1.),
+        f32::min(c1[1] + (1. - c1[3]) * c2[1], 1.),
+        f32::min(c1[2] + (1. - c1[3]) * c2[2], 1.),
+        f32::min(c1[3] + (1. - c1[3]) * c2[3], 1.),
+    ]
+}
+
+pub fn update_image_region(
+    source: &mut Image,
+    region: &Rect,
+    other: &mut Image,
+    overlay: bool,
+    empty_other: bool,
+) {
+    let source_width = source.width();
+    let other_width = other.width();
+    for x in 0..region.w as usize {
+        for y in 0..region.h as usize {
+            let other_index = other_width * y * 4 + x * 4;
+            let other_color = [
+                other.bytes[other_index],
+                other.bytes[other_index + 1],
+                other.bytes[other_index + 2],
+                other.bytes[other_index + 3],
+            ];
+
+            let source_index =
+                4 * source_width * (region.y as usize + y) + x * 4 + region.x as usize * 4;
+            let mut new_color = other_color;
+
+            // if overlay is true, combine colors, otherwise just overwrite
+            if overlay {
+                let other_color = [
+                    other_color[0] as f32 / 255.,
+                    other_color[1] as f32 / 255.,
+                    other_color[2] as f32 / 255.,
+                    other_color[3] as f32 / 255.,
+                ];
+                let source_color = [
+                    source.bytes[source_index] as f32 / 255.,
+                    source.bytes[source_index + 1] as f32 / 255.,
+                    source.bytes[source_index + 2] as f32 / 255.,
+                    source.bytes[source_index + 3] as f32 / 255.,
+                ];
+                new_color = {
+                    let color = overlay_colors(other_color, source_color);
+                    [
+                        (color[0] * 255.) as u8,
+                        (color[1] * 255.) as u8,
+                        (color[2] * 255.) as u8,
+                        (color[3] * 255.) as u8,
+                    ]
+                };
+                //new_color = [255, 255, 255, 255];
+            }
+            // update color
+            source.bytes[source_index] = new_color[0];
+            source.bytes[source_index + 1] = new_color[1];
+            source.bytes[source_index + 2] = new_color[2];
+            source.bytes[source_index + 3] = new_color[3];
+
+            // if empty_other is true, set others color to 0,0,0,0
+            if empty_other {
+                other.bytes[other_index] = 0;
+                other.bytes[other_index + 1] = 0;
+                other.bytes[other_index + 2] = 0;
+                other.bytes[other_index + 3] = 0;
+            }
+        }
+    }
+}
+
+/// Action to undo a specific type of change
+///
+/// Applying an action returns an action of the same kind which reverts it, so the same type is used for both undo and redo history
+#[derive(IntoStaticStr)]
+pub enum UndoAction {
+    /// For changes of entire layer, track entire layer image data (index, image)
+    LayerFull(usize, Image),
+    /// For changes in a region of layer, track the data of that region (index, region, subimage)
+    LayerRegion(usize, Rect, Image),
+    /// When a layer is created, track its index to know what to remove to undo it. Holds the layer itself once it has been removed (index, layer)
+    CreateLayer(usize, Option<Layer>),
+    /// When a layer is deleted, track where to insert it and its value. Holds None once it has been inserted again (index, layer)
+    DeleteLayer(usize, Option<Layer>),
+    /// When layers are merged down, track index of the destination layer and its images of every frame to swap in, and the top layers old data if it is to be restored (index, images, layer)
+    MergeLayersDown(usize, Vec<Image>, Option<Layer>),
+    /// When layer is renamed, track its index and old name
+    RenameLayer(usize, String),
+    /// When layer opacity or blend mode is changed, track its index and old values (index, opacity, blend mode)
+    LayerProperties(usize, f32, BlendMode),
+    /// When the canvas is resized or scaled, track the old size and image data of every frame of every layer (width, height, images)
+    CanvasSize(u16, u16, Vec<Vec<Image>>),
+    /// When a frame is created, track its index to know what to remove to undo it. Holds the frame and its layer images once it has been removed (index, frame)
+    CreateFrame(usize, Option<(Frame, Vec<Image>)>),
+    /// When a frame is deleted, track where to insert it and its layer images. Holds None once it has been inserted again (index, frame)
+    DeleteFrame(usize, Option<(Frame, Vec<Image>)>),
+    /// When a frame is moved, track where it was moved from and to (from, to)
+    MoveFrame(usize, usize),
+    /// When the duration of a frame is changed, track its index and old duration (index, duration)
+    FrameDuration(usize, u32),
+    /// When colors of the palette of indexed color mode are changed without changing pixels, track the old palette.
+    /// It is an Option like [Canvas::palette], so applying it never turns a canvas without a palette into one with an empty palette
+    PaletteColors(Option<Palette>),
+    /// When the color mode is changed, or pixels are changed to match a changed palette, track the old palette and the images of every frame of every layer (palette, images)
+    ColorMode(Option<Palette>, Vec<Vec<Image>>),
+}
+
+/// Frame of an animation. The images of each frame are stored in the layers, see [Layer::frames]
+#[derive(Clone, Copy, PartialEq)]
+pub struct Frame {
+    /// How long the frame is shown, in milliseconds
+    pub duration: u32,
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Frame {
+            duration: DEFAULT_FRAME_DURATION,
+        }
+    }
+}
+
+/// Format a canvas is saved as
+#[derive(Clone, Copy, PartialEq)]
+pub enum FileFormat {
+    /// Flattened image of all layers
+    Image(ImageFormat),
+    /// Plow project, which keeps all layers
+    Project,
+    /// OpenRaster, for exchanging layered images with other editors
+    OpenRaster,
+    /// Animated gif or png, made of frames or layers
+    Animation(AnimationOptions),
+}
+
+impl FileFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            FileFormat::Image(format) => format.extensions_str()[0],
+            FileFormat::Project => PROJECT_EXTENSION,
+            FileFormat::OpenRaster => ORA_EXTENSION,
+            FileFormat::Animation(options) => options.format.extension(),
+        }
+    }
+    /// Get format from file extension, like "png" or "plow"
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        let extension = extension.to_lowercase();
+        match extension.as_str() {
+            PROJECT_EXTENSION => Some(FileFormat::Project),
+            ORA_EXTENSION => Some(FileFormat::OpenRaster),
+            _ => ImageFormat::from_extension(extension).map(FileFormat::Image),
+        }
+    }
+}
+
+/// Filter shown on a layer before it is applied, see [Canvas::preview_filter]
+struct FilterPreview {
+    /// Id of the layer being previewed, see [Layer::id]
+    layer_id: u64,
+    /// Image of the layer before the filter
+    original: Image,
+    /// Whether the layer was modified before the filter
+    modified: bool,
+    filter: Filter,
+    settings: FilterSettings,
+}
+
+/// Pasted image which hasn't been placed yet, and can be moved around before it is committed to a new layer
+pub struct FloatingImage {
+    pub layer: Layer,
+    pub x: i16,
+    pub y: i16,
+}
+
+pub struct Canvas {
+    pub width: u16,
+    pub height: u16,
+    pub current_layer: usize,
+    pub layers: Vec<Layer>,
+    pub name: String,
+    /// Zoom level of camera. 0 until camera has been fit to the screen, since canvases can be created without one
+    pub camera_grid_size: f32,
+    pub camera_x: f32,
+    pub camera_y: f32,
+    pub preffered_file_format: FileFormat,
+    pub save_path: Option<PathBuf>,
+    /// How images and animations are upscaled when exported, so several sizes can be exported from one canvas
+    pub export_upscale: UpscaleOptions,
+    /// Frames of the animation. There is always at least one
+    pub frames: Vec<Frame>,
+    pub current_frame: usize,
+    /// Whether strokes and fills wrap around the edges, for painting tiles that repeat seamlessly
+    pub tile_mode: bool,
+    /// Palette of indexed color mode. If Some, layers store indices of this palette instead of colors, see [crate::indexed]
+    pub palette: Option<Palette>,
+    /// Actions are stored with the frame that was current when they were made, since they apply to the images of that frame
+    pub undo_history: Vec<(usize, UndoAction)>,
+    pub redo_history: Vec<(usize, UndoAction)>,
+    pub current_changes: Layer,
+    /// Currently selected pixels. If None, everything may be edited.
+    pub selection: Option<Selection>,
+    pub floating: Option<FloatingImage>,
+    /// All visible layers composited together, used for drawing when layers use opacity or blend modes
+    pub composite: Option<Layer>,
+    /// Layer properties the composite was made with, to know when it has to be fully redone
+    composite_signature: Vec<(u64, bool, f32, BlendMode)>,
+    /// Palette colors the composite was made with, in indexed color mode
+    composite_colors: Vec<[u8; 3]>,
+    /// Composites of other frames by frame index, used for onion skins
+    onion_skins: HashMap<usize, Layer>,
+    /// Layer properties the onion skins were made with, to know when they are outdated
+    onion_skins_signature: Vec<(u64, bool, f32, BlendMode)>,
+    filter_preview: Option<FilterPreview>,
+    modified: bool,
+}
+
+impl Canvas {
+    pub fn from_image(
+        image: Image,
+        name: String,
+        preffered_file_format: ImageFormat,
+    ) -> Result<Self, std::io::Error> {
+        let width: u16 = image.width;
+        let height: u16 = image.height;
+        if !validate_canvas_size(width, height) {
+            return Err(canvas_too_big_error());
+        }
+        let layers = vec![Layer::new(image, String::from("background"))];
+        Self::from_layers(layers, name, FileFormat::Image(preffered_file_format))
+    }
+    /// Create canvas from layers. All layers must be the same size, and there must be at least one layer.
+    pub fn from_layers(
+        layers: Vec<Layer>,
+        name: String,
+        preffered_file_format: FileFormat,
+    ) -> Result<Self, std::io::Error> {
+        let width: u16 = layers[0].image.width;
+        let height: u16 = layers[0].image.height;
+        if !validate_canvas_size(width, height) {
+            return Err(canvas_too_big_error());
+        }
+
+        Ok(Canvas {
+            width,
+            height,
+            current_layer: 0,
+            layers,
+            name,
+            camera_grid_size: 0.,
+            camera_x: 0.,
+            camera_y: 0.,
+            preffered_file_format,
+            save_path: None,
+            export_upscale: UpscaleOptions::default(),
+            frames: vec![Frame::default()],
+            current_frame: 0,
+            tile_mode: false,
+            palette: None,
+            undo_history: Vec::new(),
+            redo_history: Vec::new(),
+            current_changes: Layer::new(gen_empty_image(width, height), String::new()),
+            selection: None,
+            floating: None,
+            composite: None,
+            composite_signature: Vec::new(),
+            composite_colors: Vec::new(),
+            onion_skins: HashMap::new(),
+            onion_skins_signature: Vec::new(),
+            filter_preview: None,
+            modified: false,
+        })
+    }
+    /// Create canvas with a single layer, and a frame for each image. All images must be the same size, and there must be at least one.
+    pub fn from_frames(
+        images: Vec<Image>,
+        name: String,
+        preffered_file_format: FileFormat,
+    ) -> Result<Self, std::io::Error> {
+        let frame_count = images.len();
+        let mut images = images.into_iter();
+        let mut layer = Layer::new(images.next().unwrap(), String::from("background"));
+        layer.frames.extend(images);
+        let mut canvas = Self::from_layers(vec![layer], name, preffered_file_format)?;
+        canvas.frames = vec![Frame::default(); frame_count];
+        Ok(canvas)
+    }
+    pub fn is_modified(&self) -> bool {
+        let mut modified = self.modified;
+        for layer in &self.layers {
+            modified |= layer.modified;
+        }
+        modified
+    }
+    pub fn new(width: u16, height: u16, name: String) -> Result<Self, std::io::Error> {
+        let image = gen_empty_image(width, height);
+        Canvas::from_image(image, name, ImageFormat::Png)
+    }
+    /// Composite the pixel at index of all visible layers in frame, with their opacity and blend modes.
+    /// If include_changes is true, current_changes is drawn on top of the current layer.
+    fn composite_pixel(&self, frame: usize, index: usize, include_changes: bool) -> [u8; 4] {
+        let mut color = [0.; 4];
+        for (layer_index, layer) in self.layers.iter().enumerate().rev() {
+            if !layer.visible {
+                continue;
+            }
+            let image = self.frame_image(layer_index, frame);
+            let mut source = color_to_floats(self.decode_color(image.get_image_data()[index]));
+            if include_changes && layer_index == self.current_layer {
+                let changes = self.current_changes.image.get_image_data()[index];
+                let changes = color_to_floats(self.decode_color(changes));
+                source = blend_colors(source, changes, BlendMode::Normal, 1.);
+            }
+            color = blend_colors(color, source, layer.blend_mode, layer.opacity);
+        }
+        floats_to_color(color)
+    }
+    /// Composite all visible layers of the current frame
+    pub fn to_image(&self) -> Image {
+        self.frame_to_image(self.current_frame)
+    }
+    /// Composite all visible layers of frame
+    pub fn frame_to_image(&self, frame: usize) -> Image {
+        let mut image = gen_empty_image(self.width, self.height);
+        for (index, pixel) in image.get_image_data_mut().iter_mut().enumerate() {
+            *pixel = self.composite_pixel(frame, index, false);
+        }
+        image
+    }
+    /// Get image of layer at index in the current frame, with its opacity applied
+    pub fn layer_to_image(&self, index: usize) -> Image {
+        let layer = &self.layers[index];
+        let mut image = self.decode_image(&layer.image);
+        for pixel in image.get_image_data_mut() {
+            *pixel = floats_to_color(blend_colors(
+                [0.; 4],
+                color_to_floats(*pixel),
+                layer.blend_mode,
+                layer.opacity,
+            ));
+        }
+        image
+    }
+    /// Get image of layer at index in frame
+    pub fn frame_image(&self, layer: usize, frame: usize) -> &Image {
+        let layer = &self.layers[layer];
+        if frame == self.current_frame {
+            &layer.image
+        } else {
+            &layer.frames[frame]
+        }
+    }
+    /// Update the composite of all layers, which is used for drawing when any layer has opacity or a blend mode.
+    ///
+    /// Only regions of layers that have changed since last call are recomposited, unless layers have been added, removed, moved or had their properties changed.
+    pub fn update_composite(&mut self) {
+        let mut changed = BoundsTracker::new();
+        for layer in self
+            .layers
+            .iter_mut()
+            .chain(std::iter::once(&mut self.current_changes))
+        {
+            if let Some(region) = layer.composite_tracker.flush() {
+                changed.track(region.x as u32, region.y as u32);
+                changed.track(
+                    (region.x + region.w) as u32 - 1,
+                    (region.y + region.h) as u32 - 1,
+                );
+            }
+        }
+
+        // indexed layers can't be drawn directly, since they have to be decoded with the palette
+        if self.palette.is_none() && !self.layers.iter().any(|layer| layer.needs_composite()) {
+            self.composite = None;
+            return;
+        }
+
+        let signature: Vec<(u64, bool, f32, BlendMode)> = self
+            .layers
+            .iter()
+            .map(|layer| (layer.id, layer.visible, layer.opacity, layer.blend_mode))
+            .collect();
+        let full = self.composite.as_ref().is_none_or(|composite| {
+            composite.width() != self.width as usize || composite.height() != self.height as usize
+        }) || signature != self.composite_signature;
+        self.composite_signature = signature;
+        // when palette colors change, every pixel using them changes
+        let colors = self
+            .palette
+            .as_ref()
+            .map(|palette| palette.colors.clone())
+            .unwrap_or_default();
+        let full = full || colors != self.composite_colors;
+        self.composite_colors = colors;
+
+        let region = if full {
+            Rect::new(0., 0., self.width as f32, self.height as f32)
+        } else if let Some(region) = changed.to_rect() {
+            region
+        } else {
+            return;
+        };
+
+        let mut composite = match self.composite.take() {
+            Some(composite) if !full => composite,
+            _ => Layer::new(gen_empty_image(self.width, self.height), String::new()),
+        };
+        let width = self.width as usize;
+        for y in region.y as usize..(region.y + region.h) as usize {
+            for x in region.x as usize..(region.x + region.w) as usize {
+                let color = self.composite_pixel(self.current_frame, x + y * width, true);
+                composite.image.get_image_data_mut()[x + y * width] = color;
+            }
+        }
+        composite.force_update_region(Some(region));
+        self.composite = Some(composite);
+    }
+    /// Get composite of frame, used to draw onion skins of frames other than the current one.
+    ///
+    /// Composites are kept until frames or layers change, since only the current frame can be edited.
+    pub fn onion_skin(&mut self, frame: usize) -> &mut Layer {
+        let signature: Vec<(u64, bool, f32, BlendMode)> = self
+            .layers
+            .iter()
+            .map(|layer| (layer.id, layer.visible, layer.opacity, layer.blend_mode))
+            .collect();
+        if signature != self.onion_skins_signature {
+            self.onion_skins.clear();
+            self.onion_skins_signature = signature;
+        }
+        if !self.onion_skins.contains_key(&frame) {
+            let image = self.frame_to_image(frame);
+            self.onion_skins
+                .insert(frame, Layer::new(image, String::new()));
+        }
+        self.onion_skins.get_mut(&frame).unwrap()
+    }
+    /// Encode canvas in format
+    pub fn to_bytes(&self, format: FileFormat) -> Result<Vec<u8>, std::io::Error> {
+        match format {
+            FileFormat::Image(format) => {
+                upscaled_size(self.width, self.height, self.export_upscale)?;
+                let image = upscale_image(&self.to_image(), self.export_upscale);
+                if let Some(palette) = &self.palette {
+                    if supports_indexed(format) {
+                        let image = encode_image(&palette.colors, &image);
+                        return encode_indexed(&palette.colors, &image, format);
+                    }
+                }
+
+                // buffer to store image data in
+                let mut buffered_writer = Cursor::new(Vec::new());
+
+                // convert image to format and write to the buffer
+                image::write_buffer_with_format(
+                    &mut buffered_writer,
+                    &image.bytes,
+                    image.width as u32,
+                    image.height as u32,
+                    image::ColorType::Rgba8,
+                    format,
+                )
+                .map_err(std::io::Error::other)?;
+                Ok(buffered_writer.into_inner())
+            }
+            FileFormat::Project => project_to_bytes(self),
+            FileFormat::OpenRaster => ora_to_bytes(self),
+            FileFormat::Animation(options) => animation_to_bytes(self, &options),
+        }
+    }
+    /// Mark canvas and all layers as unmodified, like after saving
+    pub fn mark_saved(&mut self) {
+        for layer in self.layers.iter_mut() {
+            layer.modified = false;
+        }
+        self.modified = false;
+    }
+    pub fn rename_layer(&mut self, new_name: String) {
+        let layer = &mut self.layers[self.current_layer];
+
+        // replace layer.name with new_name, and assign the old value of layer.name to old_name
+        let old_name = std::mem::replace(&mut layer.name, new_name);
+
+        self.push_undo_action(UndoAction::RenameLayer(self.current_layer, old_name));
+    }
+    /// Record that current layer's opacity or blend mode has been changed from the old values, so it can be undone
+    pub fn layer_properties_changed(&mut self, old_opacity: f32, old_blend_mode: BlendMode) {
+        let layer = &self.layers[self.current_layer];
+        if layer.opacity == old_opacity && layer.blend_mode == old_blend_mode {
+            return;
+        }
+        self.modified = true;
+        self.push_undo_action(UndoAction::LayerProperties(
+            self.current_layer,
+            old_opacity,
+            old_blend_mode,
+        ));
+    }
+    /// Write the contents of current_changes onto the current layer, and clear current_changes
+    pub fn commit_current_changes(&mut self) {
+        let bounds = self.current_changes.bounds_tracker.flush();
+        if let Some(bounds) = bounds {
+            // save old image in history
+            let sub_image = self.layers[self.current_layer].image.sub_image(bounds);
+            self.push_undo_action(UndoAction::LayerRegion(
+                self.current_layer,
+                bounds,
+                sub_image,
+            ));
+            let mut sub_image_stroke = self.current_changes.image.sub_image(bounds);
+
+            // write brush stroke data on image
+            update_image_region(
+                &mut self.layers[self.current_layer].image,
+                &bounds,
+                &mut sub_image_stroke,
+                true,
+                true,
+            );
+
+            // update current_changes to reset it
+            update_image_region(
+                &mut self.current_changes.image,
+                &bounds,
+                &mut sub_image_stroke,
+                false,
+                false,
+            );
+
+            self.layers[self.current_layer].modified = true;
+            self.layers[self.current_layer].force_update_region(Some(bounds));
+            self.current_changes.force_update_region(Some(bounds));
+        }
+    }
+    /// Clear current_changes without writing them to the current layer, like when redrawing a preview
+    pub fn clear_current_changes(&mut self) {
+        let bounds = self.current_changes.bounds_tracker.flush();
+        if let Some(bounds) = bounds {
+            let width = self.current_changes.width();
+            let bytes = &mut self.current_changes.image.bytes;
+            for y in bounds.y as usize..(bounds.y + bounds.h) as usize {
+                let start = (y * width + bounds.x as usize) * 4;
+                bytes[start..start + bounds.w as usize * 4].fill(0);
+            }
+            self.current_changes.force_update_region(Some(bounds));
+        }
+    }
+    /// Add action to undo history. Since this is a new change, the redo history is cleared
+    pub fn push_undo_action(&mut self, action: UndoAction) {
+        self.undo_history.push((self.current_frame, action));
+        self.redo_history.clear();
+    }
+    pub fn undo(&mut self) {
+        if let Some((frame, action)) = self.undo_history.pop() {
+            let inverse = self.apply_history_action(frame, action);
+            self.redo_history.push((frame, inverse));
+        }
+    }
+    pub fn redo(&mut self) {
+        if let Some((frame, action)) = self.redo_history.pop() {
+            let inverse = self.apply_history_action(frame, action);
+            self.undo_history.push((frame, inverse));
+        }
+    }
+    /// Apply an action from the undo or redo history, and return the action that reverts it.
+    /// The frame the action was made in becomes current first, so it is applied to the right images
+    fn apply_history_action(&mut self, frame: usize, action: UndoAction) -> UndoAction {
+        self.cancel_filter_preview();
+        // frame may be past the end if it was the last frame and has been deleted
+        self.switch_frame(frame.min(self.frames.len() - 1));
+        self.onion_skins.clear();
+        match action {
+            UndoAction::CreateLayer(index, None) => {
+                let layer = self.layers.remove(index);
+                self.current_layer = index.min(self.layers.len() - 1);
+                UndoAction::CreateLayer(index, Some(layer))
+            }
+            UndoAction::CreateLayer(index, Some(layer)) => {
+                self.layers.insert(index, layer);
+                self.current_layer = index;
+                UndoAction::CreateLayer(index, None)
+            }
+            UndoAction::MergeLayersDown(index, dest, Some(layer)) => {
+                let dest = self.set_layer_frames(index, dest);
+                self.layers.insert(index, layer);
+                self.current_layer = index;
+                UndoAction::MergeLayersDown(index, dest, None)
+            }
+            UndoAction::MergeLayersDown(index, dest, None) => {
+                let layer = self.layers.remove(index);
+                let dest = self.set_layer_frames(index, dest);
+                self.current_layer = index;
+                UndoAction::MergeLayersDown(index, dest, Some(layer))
+            }
+            UndoAction::DeleteLayer(index, Some(layer)) => {
+                self.layers.insert(index, layer);
+                self.current_layer = index;
+                UndoAction::DeleteLayer(index, None)
+            }
+            UndoAction::DeleteLayer(index, None) => {
+                let layer = self.layers.remove(index);
+                self.current_layer = index.min(self.layers.len() - 1);
+                UndoAction::DeleteLayer(index, Some(layer))
+            }
+            UndoAction::RenameLayer(index, name) => {
+                let old_name = std::mem::replace(&mut self.layers[index].name, name);
+                UndoAction::RenameLayer(index, old_name)
+            }
+            UndoAction::LayerProperties(index, opacity, blend_mode) => {
+                let layer = &mut self.layers[index];
+                let old_opacity = std::mem::replace(&mut layer.opacity, opacity);
+                let old_blend_mode = std::mem::replace(&mut layer.blend_mode, blend_mode);
+                UndoAction::LayerProperties(index, old_opacity, old_blend_mode)
+            }
+            UndoAction::CanvasSize(width, height, images) => {
+                let old_size = (self.width, self.height);
+                let old_images = self.set_layer_images(width, height, images);
+                UndoAction::CanvasSize(old_size.0, old_size.1, old_images)
+            }
+            UndoAction::LayerFull(index, mut data) => {
+                std::mem::swap(&mut self.layers[index].image, &mut data);
+                self.layers[index].force_update_region(None);
+                UndoAction::LayerFull(index, data)
+            }
+            UndoAction::LayerRegion(index, region, mut data) => {
+                let old_data = self.layers[index].image.sub_image(region);
+                update_image_region(
+                    &mut self.layers[index].image,
+                    &region,
+                    &mut data,
+                    false,
+                    false,
+                );
+                self.layers[index].force_update_region(Some(region));
+                UndoAction::LayerRegion(index, region, old_data)
+            }
+            UndoAction::CreateFrame(index, None) => {
+                let removed = self.remove_frame(index);
+                UndoAction::CreateFrame(index, Some(removed))
+            }
+            UndoAction::CreateFrame(index, Some((frame, images))) => {
+                self.insert_frame(index, frame, images);
+                UndoAction::CreateFrame(index, None)
+            }
+            UndoAction::DeleteFrame(index, Some((frame, images))) => {
+                self.insert_frame(index, frame, images);
+                UndoAction::DeleteFrame(index, None)
+            }
+            UndoAction::DeleteFrame(index, None) => {
+                let removed = self.remove_frame(index);
+                UndoAction::DeleteFrame(index, Some(removed))
+            }
+            UndoAction::MoveFrame(from, to) => {
+                self.move_frame_to(from, to);
+                UndoAction::MoveFrame(to, from)
+            }
+            UndoAction::FrameDuration(index, duration) => {
+                let old_duration = std::mem::replace(&mut self.frames[index].duration, duration);
+                UndoAction::FrameDuration(index, old_duration)
+            }
+            UndoAction::PaletteColors(palette) => {
+                let old_palette = std::mem::replace(&mut self.palette, palette);
+                UndoAction::PaletteColors(old_palette)
+            }
+            UndoAction::ColorMode(palette, images) => {
+                let old_palette = std::mem::replace(&mut self.palette, palette);
+                let old_images = self.set_layer_images(self.width, self.height, images);
+                UndoAction::ColorMode(old_palette, old_images)
+            }
+        }
+    }
+    /// Replace the images of every frame of every layer, and change the canvas size to match. Returns the old images.
+    fn set_layer_images(
+        &mut self,
+        width: u16,
+        height: u16,
+        images: Vec<Vec<Image>>,
+    ) -> Vec<Vec<Image>> {
+        let mut old_images = Vec::new();
+        for (index, images) in images.into_iter().enumerate() {
+            old_images.push(self.set_layer_frames(index, images));
+            self.layers[index].bounds_tracker = BoundsTracker::new();
+        }
+        self.onion_skins.clear();
+        self.width = width;
+        self.height = height;
+        self.current_changes = Layer::new(gen_empty_image(width, height), String::new());
+        // selection can't be kept when size changes, since it covers the entire canvas
+        self.selection = None;
+        old_images
+    }
+    /// Change canvas size, applying f to the image of each frame of each layer to get the new image
+    fn change_size(
+        &mut self,
+        width: u16,
+        height: u16,
+        f: impl Fn(&Image) -> Image,
+    ) -> Result<(), std::io::Error> {
+        if width == 0 || height == 0 {
+            return Err(std::io::Error::other("canvas size can't be 0"));
+        }
+        if !validate_canvas_size(width, height) {
+            return Err(canvas_too_big_error());
+        }
+        if width == self.width && height == self.height {
+            return Ok(());
+        }
+        self.modified = true;
+        let images = (0..self.layers.len())
+            .map(|layer| {
+                (0..self.frames.len())
+                    .map(|frame| f(self.frame_image(layer, frame)))
+                    .collect()
+            })
+            .collect();
+        let (old_width, old_height) = (self.width, self.height);
+        let old_images = self.set_layer_images(width, height, images);
+        self.push_undo_action(UndoAction::CanvasSize(old_width, old_height, old_images));
+        Ok(())
+    }
+    /// Resize canvas without scaling its contents. See [resize_image] for anchor.
+    pub fn resize_canvas(
+        &mut self,
+        width: u16,
+        height: u16,
+        anchor: (f32, f32),
+    ) -> Result<(), std::io::Error> {
+        self.change_size(width, height, |image| {
+            resize_image(image, width, height, anchor)
+        })
+    }
+    /// Scale canvas and the contents of all layers
+    pub fn scale_image(
+        &mut self,
+        width: u16,
+        height: u16,
+        mode: ScaleMode,
+    ) -> Result<(), std::io::Error> {
+        // interpolating indices would give unrelated colors
+        let mode = if self.palette.is_some() {
+            ScaleMode::Nearest
+        } else {
+            mode
+        };
+        self.change_size(width, height, |image| {
+            scale_image(image, width, height, mode)
+        })
+    }
+    /// Upscale canvas and the contents of all layers by a whole factor
+    pub fn upscale(&mut self, options: UpscaleOptions) -> Result<(), std::io::Error> {
+        // xbr compares colors, which indices don't have
+        let colors = match &self.palette {
+            Some(palette) if options.upscaler == Upscaler::Xbr => Some(palette.colors.clone()),
+            _ => None,
+        };
+        let (width, height) = upscaled_size(self.width, self.height, options)?;
+        self.change_size(width, height, |image| match &colors {
+            Some(colors) => encode_image(
+                colors,
+                &upscale_image(&decode_image(colors, image), options),
+            ),
+            None => upscale_image(image, options),
+        })
+    }
+    /// Replace each pixel of the current layer with the nearest palette color, within the selection if there is one
+    pub fn remap_to_palette(&mut self, colors: &[[u8; 3]], dither: Dither) {
+        // indexed layers already only use palette colors
+        if colors.is_empty() || self.palette.is_some() {
+            return;
+        }
+        let layer = &mut self.layers[self.current_layer];
+        let old_image = layer.image.clone();
+        remap_image(&mut layer.image, colors, dither, self.selection.as_ref());
+        layer.modified = true;
+        layer.force_update_region(None);
+        self.push_undo_action(UndoAction::LayerFull(self.current_layer, old_image));
+    }
+    pub fn select_all(&mut self) {
+        self.selection = Some(Selection::all(self.width as usize, self.height as usize));
+    }
+    pub fn deselect(&mut self) {
+        self.selection = None;
+    }
+    /// Combine new selection with the current one. If the result is empty, the selection is removed.
+    pub fn combine_selection(&mut self, selection: Selection, mode: SelectionMode) {
+        let combined = match (&self.selection, mode) {
+            (Some(current), mode) => current.combine(&selection, mode),
+            // with nothing selected, only replacing or adding selects anything
+            (None, SelectionMode::Replace | SelectionMode::Add) => selection,
+            (None, _) => {
+                self.selection = None;
+                return;
+            }
+        };
+        self.selection = Some(combined).filter(|s| !s.is_empty());
+    }
+    /// Invert selection. If nothing is selected, everything becomes selected, and if everything is selected, the selection is removed.
+    pub fn invert_selection(&mut self) {
+        self.selection = match &self.selection {
+            Some(selection) => Some(selection.inverted()).filter(|s| !s.is_empty()),
+            None => Some(Selection::all(self.width as usize, self.height as usize)),
+        };
+    }
+    /// Get region of current layer which is selected (or the entire layer if nothing is selected). Unselected pixels within the region are transparent.
+    ///
+    /// Returns None if the selection is empty.
+    fn get_selected_image(&self) -> Option<(Rect, Image)> {
+        let region = match &self.selection {
+            Some(selection) => selection.bounds()?,
+            None => Rect::new(0., 0., self.width as f32, self.height as f32),
+        };
+        let mut image = self.layers[self.current_layer].image.sub_image(region);
+        if let Some(selection) = &self.selection {
+            for y in 0..region.h as usize {
+                for x in 0..region.w as usize {
+                    if !selection.contains(region.x as usize + x, region.y as usize + y) {
+                        image.set_pixel(x as u32, y as u32, [0; 4]);
+                    }
+                }
+            }
+        }
+        Some((region, self.decode_image(&image)))
+    }
+    /// Copy selected pixels of current layer
+    pub fn copy(&self) -> Option<Image> {
+        self.get_selected_image().map(|(_, image)| image)
+    }
+    /// Copy selected pixels of current layer, and clear them
+    pub fn cut(&mut self) -> Option<Image> {
+        let (region, image) = self.get_selected_image()?;
+        let layer = &mut self.layers[self.current_layer];
+        let old = layer.image.sub_image(region);
+        for y in region.y as u32..(region.y + region.h) as u32 {
+            for x in region.x as u32..(region.x + region.w) as u32 {
+                if self
+                    .selection
+                    .as_ref()
+                    .is_none_or(|s| s.contains(x as usize, y as usize))
+                {
+                    layer.set_pixel(x, y, [0; 4]);
+                }
+            }
+        }
+        layer.bounds_tracker.flush();
+        layer.force_update_region(Some(region));
+        self.push_undo_action(UndoAction::LayerRegion(self.current_layer, region, old));
+        Some(image)
+    }
+    /// Paste image as a floating image, placed at the selection if there is one. Any previous floating image is committed first.
+    pub fn paste(&mut self, image: Image) {
+        self.commit_floating();
+        let (x, y) = self
+            .selection
+            .as_ref()
+            .and_then(|selection| selection.bounds())
+            .map(|bounds| (bounds.x as i16, bounds.y as i16))
+            .unwrap_or((0, 0));
+        self.floating = Some(FloatingImage {
+            layer: Layer::new(image, String::new()),
+            x,
+            y,
+        });
+    }
+    /// Place floating image on a new layer above the current one
+    pub fn commit_floating(&mut self) {
+        let Some(floating) = self.floating.take() else {
+            return;
+        };
+        self.modified = true;
+
+        self.push_undo_action(UndoAction::CreateLayer(self.current_layer, None));
+
+        let name = self.get_new_layer_name();
+        // floating images are always in color, so they can be drawn directly
+        let image = place_image(
+            &self.encode_image(&floating.layer.image),
+            self.width,
+            self.height,
+            floating.x as i32,
+            floating.y as i32,
+        );
+        let mut layer = Layer::new(image, name);
+        layer.frames = self.empty_layer_frames();
+        layer.modified = true;
+        self.layers.insert(self.current_layer, layer);
+    }
+    pub fn cancel_floating(&mut self) {
+        self.floating = None;
+    }
+    fn get_new_layer_name(&self) -> String {
+        // get a name for the new layer (that isnt already used!!!!!)
+        let mut layer_name_index = self.layers.len() + 1;
+        let mut name = format!("layer {}", layer_name_index);
+        let names = self
+            .layers
+            .iter()
+            .map(|f| &f.name)
+            .collect::<Vec<&String>>();
+        while names.contains(&&name) {
+            layer_name_index += 1;
+            name = format!("layer {}", layer_name_index);
+        }
+        name
+    }
+    pub fn new_layer(&mut self) {
+        self.modified = true;
+
+        self.push_undo_action(UndoAction::CreateLayer(self.current_layer, None));
+
+        let name = self.get_new_layer_name();
+        let image = gen_empty_image(self.width, self.height);
+        let mut layer = Layer::new(image, name);
+        layer.frames = self.empty_layer_frames();
+
+        self.layers.insert(self.current_layer, layer);
+    }
+    pub fn merge_layers_down(&mut self) {
+        self.modified = true;
+
+        if self.current_layer != self.layers.len() - 1 {
+            // add to history
+            self.push_undo_action(UndoAction::MergeLayersDown(
+                self.current_layer,
+                self.layer_frames(self.current_layer + 1),
+                Some(self.layers[self.current_layer].clone()),
+            ));
+            // merge down every frame, using the top layer's opacity and blend mode
+            let old_layer = self.layers.remove(self.current_layer);
+            let dest = &mut self.layers[self.current_layer];
+            for frame in 0..self.frames.len() {
+                let (dest_image, source_image) = if frame == self.current_frame {
+                    (&mut dest.image, &old_layer.image)
+                } else {
+                    (&mut dest.frames[frame], &old_layer.frames[frame])
+                };
+                let colors = self.palette.as_ref().map(|palette| &palette.colors);
+                for (pixel, source) in dest_image
+                    .get_image_data_mut()
+                    .iter_mut()
+                    .zip(source_image.get_image_data())
+                {
+                    // indexed pixels are blended as colors, then converted back to the nearest index
+                    let (backdrop, source) = match colors {
+                        Some(colors) => {
+                            (decode_pixel(colors, *pixel), decode_pixel(colors, *source))
+                        }
+                        None => (*pixel, *source),
+                    };
+                    let color = floats_to_color(blend_colors(
+                        color_to_floats(backdrop),
+                        color_to_floats(source),
+                        old_layer.blend_mode,
+                        old_layer.opacity,
+                    ));
+                    *pixel = match colors {
+                        Some(colors) => encode_pixel(colors, color),
+                        None => color,
+                    };
+                }
+            }
+
+            self.layers[self.current_layer].force_update_region(None);
+            self.layers[self.current_layer].modified =
+                self.layers[self.current_layer].modified || old_layer.modified;
+        }
+    }
+    pub fn duplicate_layer(&mut self) {
+        self.modified = true;
+
+        self.push_undo_action(UndoAction::CreateLayer(self.current_layer, None));
+
+        let name = self.get_new_layer_name();
+        let source = &self.layers[self.current_layer];
+        let image = source.image.clone();
+        let mut new = Layer::new(image, name);
+        new.frames = source.frames.clone();
+        new.modified = source.modified;
+        self.layers.insert(self.current_layer, new);
+    }
+    pub fn delete_layer(&mut self) {
+        self.modified = true;
+
+        if self.layers.len() > 1 {
+            // add to history
+            self.push_undo_action(UndoAction::DeleteLayer(
+                self.current_layer,
+                Some(self.layers[self.current_layer].clone()),
+            ));
+            // remove layer
+            self.layers.remove(self.current_layer);
+            if self.current_layer == self.layers.len() {
+                self.current_layer = self.current_layer.saturating_sub(1);
+            }
+        }
+    }
+    /// Empty images for every frame of a new layer, with the slot of the current frame left empty for [Layer::image]
+    fn empty_layer_frames(&self) -> Vec<Image> {
+        (0..self.frames.len())
+            .map(|frame| {
+                if frame == self.current_frame {
+                    empty_frame_slot()
+                } else {
+                    gen_empty_image(self.width, self.height)
+                }
+            })
+            .collect()
+    }
+    /// Clone images of every frame of layer at index, in frame order
+    fn layer_frames(&self, index: usize) -> Vec<Image> {
+        (0..self.frames.len())
+            .map(|frame| self.frame_image(index, frame).clone())
+            .collect()
+    }
+    /// Replace images of every frame of layer at index, in frame order. Returns the old images
+    fn set_layer_frames(&mut self, index: usize, mut images: Vec<Image>) -> Vec<Image> {
+        let layer = &mut self.layers[index];
+        let image = std::mem::replace(&mut images[self.current_frame], empty_frame_slot());
+        let mut old_images = std::mem::replace(&mut layer.frames, images);
+        old_images[self.current_frame] = std::mem::replace(&mut layer.image, image);
+        layer.force_update_region(None);
+        old_images
+    }
+    /// Move the images of the current frame into [Layer::frames], so every frame is stored the same way while frames are changed
+    fn stash_current_frame(&mut self) {
+        for layer in self.layers.iter_mut() {
+            std::mem::swap(&mut layer.image, &mut layer.frames[self.current_frame]);
+        }
+    }
+    /// Take the images of frame out of [Layer::frames] and make it current. The current frame must be stashed first
+    fn unstash_frame(&mut self, frame: usize) {
+        self.current_frame = frame;
+        for layer in self.layers.iter_mut() {
+            std::mem::swap(&mut layer.image, &mut layer.frames[frame]);
+            layer.bounds_tracker = BoundsTracker::new();
+            layer.force_update_region(None);
+        }
+        self.onion_skins.clear();
+    }
+    /// Make frame current without committing changes
+    fn switch_frame(&mut self, frame: usize) {
+        if frame != self.current_frame {
+            self.stash_current_frame();
+            self.unstash_frame(frame);
+        }
+    }
+    /// Make frame current, so it is shown and edited. Changes in progress are committed to the old frame first
+    pub fn set_frame(&mut self, frame: usize) {
+        self.cancel_filter_preview();
+        self.commit_current_changes();
+        self.switch_frame(frame);
+    }
+    /// Insert frame with an image for each layer at index, and make it current
+    fn insert_frame(&mut self, index: usize, frame: Frame, images: Vec<Image>) {
+        self.stash_current_frame();
+        self.frames.insert(index, frame);
+        for (layer, image) in self.layers.iter_mut().zip(images) {
+            layer.frames.insert(index, image);
+        }
+        self.unstash_frame(index);
+    }
+    /// Remove frame at index, returning it with the image of each layer. The frame before it becomes current
+    fn remove_frame(&mut self, index: usize) -> (Frame, Vec<Image>) {
+        self.stash_current_frame();
+        let frame = self.frames.remove(index);
+        let images = self
+            .layers
+            .iter_mut()
+            .map(|layer| layer.frames.remove(index))
+            .collect();
+        self.unstash_frame(index.saturating_sub(1).min(self.frames.len() - 1));
+        (frame, images)
+    }
+    /// Move frame from one index to another, and make it current
+    fn move_frame_to(&mut self, from: usize, to: usize) {
+        self.stash_current_frame();
+        let frame = self.frames.remove(from);
+        self.frames.insert(to, frame);
+        for layer in self.layers.iter_mut() {
+            let image = layer.frames.remove(from);
+            layer.frames.insert(to, image);
+        }
+        self.unstash_frame(to);
+    }
+    /// Add empty frame after the current one
+    pub fn new_frame(&mut self) {
+        self.commit_current_changes();
+        self.modified = true;
+        let index = self.current_frame + 1;
+        self.push_undo_action(UndoAction::CreateFrame(index, None));
+        let images = self
+            .layers
+            .iter()
+            .map(|_| gen_empty_image(self.width, self.height))
+            .collect();
+        self.insert_frame(index, Frame::default(), images);
+    }
+    /// Add copy of the current frame after it
+    pub fn duplicate_frame(&mut self) {
+        self.commit_current_changes();
+        self.modified = true;
+        let index = self.current_frame + 1;
+        self.push_undo_action(UndoAction::CreateFrame(index, None));
+        let images = self
+            .layers
+            .iter()
+            .map(|layer| layer.image.clone())
+            .collect();
+        self.insert_frame(index, self.frames[self.current_frame], images);
+    }
+    pub fn delete_frame(&mut self) {
+        if self.frames.len() > 1 {
+            self.commit_current_changes();
+            self.modified = true;
+            let index = self.current_frame;
+            let images = self
+                .layers
+                .iter()
+                .map(|layer| layer.image.clone())
+                .collect();
+            self.push_undo_action(UndoAction::DeleteFrame(
+                index,
+                Some((self.frames[index], images)),
+            ));
+            self.remove_frame(index);
+        }
+    }
+    /// Move current frame to index
+    pub fn move_frame(&mut self, to: usize) {
+        let from = self.current_frame;
+        if from != to && to < self.frames.len() {
+            self.commit_current_changes();
+            self.modified = true;
+            self.push_undo_action(UndoAction::MoveFrame(to, from));
+            self.move_frame_to(from, to);
+        }
+    }
+    /// Record that current frame's duration has been changed from the old value, so it can be undone
+    pub fn frame_duration_changed(&mut self, old_duration: u32) {
+        if self.frames[self.current_frame].duration == old_duration {
+            return;
+        }
+        self.modified = true;
+        self.push_undo_action(UndoAction::FrameDuration(self.current_frame, old_duration));
+    }
+    /// Get the pixel at x, y. In tile mode positions outside of the canvas wrap around to the other side, otherwise they are None
+    pub fn wrap_position(&self, x: i16, y: i16) -> Option<(usize, usize)> {
+        let (width, height) = (self.width as i32, self.height as i32);
+        let (x, y) = if self.tile_mode {
+            ((x as i32).rem_euclid(width), (y as i32).rem_euclid(height))
+        } else {
+            (x as i32, y as i32)
+        };
+        ((0..width).contains(&x) && (0..height).contains(&y)).then_some((x as usize, y as usize))
+    }
+    /// Find area of similar color on the current layer starting at x, y, the contiguous one like [flood_region] if continuous, otherwise all of it like [global_region]. Returns a mask of which pixels are in the area.
+    ///
+    /// Colors are compared, so in indexed color mode tolerance matches similar palette colors rather than close index numbers. In tile mode contiguous areas spread across edges. If selection is Some, the area stays within it
+    pub fn find_region(
+        &self,
+        x: usize,
+        y: usize,
+        tolerance: u16,
+        continuous: bool,
+        selection: Option<&Selection>,
+    ) -> Vec<bool> {
+        let image = self.decode_image(&self.layers[self.current_layer].image);
+        let (width, height) = (image.width(), image.height());
+        let pixels = image.get_image_data();
+        if continuous {
+            flood_region(
+                width,
+                height,
+                pixels,
+                x,
+                y,
+                tolerance,
+                selection,
+                self.tile_mode,
+            )
+        } else {
+            global_region(width, pixels, x, y, tolerance, selection)
+        }
+    }
+    /// Get color of a stored pixel. In indexed color mode this is the color of its palette index, otherwise the pixel itself
+    pub fn decode_color(&self, pixel: [u8; 4]) -> [u8; 4] {
+        match &self.palette {
+            Some(palette) => decode_pixel(&palette.colors, pixel),
+            None => pixel,
+        }
+    }
+    /// Get the pixel to store for color. In indexed color mode this is the index of the nearest palette color, otherwise the color itself
+    pub fn encode_color(&self, color: [u8; 4]) -> [u8; 4] {
+        match &self.palette {
+            Some(palette) => encode_pixel(&palette.colors, color),
+            None => color,
+        }
+    }
+    /// Get colors of a stored image, see [Canvas::decode_color]
+    pub fn decode_image(&self, image: &Image) -> Image {
+        match &self.palette {
+            Some(palette) => decode_image(&palette.colors, image),
+            None => image.clone(),
+        }
+    }
+    /// Get the image to store for an image of colors, see [Canvas::encode_color]
+    pub fn encode_image(&self, image: &Image) -> Image {
+        match &self.palette {
+            Some(palette) => encode_image(&palette.colors, image),
+            None => image.clone(),
+        }
+    }
+    /// Change color mode. With a palette, every pixel becomes the index of its nearest palette color. Without one, indices become their colors.
+    ///
+    /// Palettes with more than [INDEXED_MAX_COLORS] colors are cut off. Empty palettes are an error, since every pixel would be erased
+    pub fn set_color_mode(&mut self, palette: Option<Palette>) -> Result<(), std::io::Error> {
+        let mut palette = palette;
+        if let Some(palette) = &mut palette {
+            if palette.colors.is_empty() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "palette has no colors",
+                ));
+            }
+            palette.colors.truncate(INDEXED_MAX_COLORS);
+        }
+        self.commit_current_changes();
+        let images = (0..self.layers.len())
+            .map(|layer| {
+                (0..self.frames.len())
+                    .map(|frame| {
+                        let image = self.decode_image(self.frame_image(layer, frame));
+                        match &palette {
+                            Some(palette) => encode_image(&palette.colors, &image),
+                            None => image,
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        self.set_pixels_and_palette(palette, images);
+        Ok(())
+    }
+    /// Replace the images of every layer and the palette of indexed color mode, recording both for undo
+    fn set_pixels_and_palette(&mut self, palette: Option<Palette>, images: Vec<Vec<Image>>) {
+        self.modified = true;
+        let old_palette = std::mem::replace(&mut self.palette, palette);
+        let old_images = self.set_layer_images(self.width, self.height, images);
+        self.push_undo_action(UndoAction::ColorMode(old_palette, old_images));
+    }
+    /// Record that colors of the palette of indexed color mode have been changed from the old palette, so it can be undone. Pixels keep their indices, so they change color with the palette
+    pub fn palette_changed(&mut self, old_palette: Palette) {
+        if self.palette.as_ref() == Some(&old_palette) {
+            return;
+        }
+        self.modified = true;
+        self.onion_skins.clear();
+        self.push_undo_action(UndoAction::PaletteColors(Some(old_palette)));
+    }
+    /// Change the indices of every pixel with map, and replace the palette of indexed color mode with palette
+    fn remap_indices(&mut self, palette: Palette, map: impl Fn(u8) -> u8) {
+        self.commit_current_changes();
+        let images = (0..self.layers.len())
+            .map(|layer| {
+                (0..self.frames.len())
+                    .map(|frame| {
+                        let mut image = self.frame_image(layer, frame).clone();
+                        for pixel in image.get_image_data_mut() {
+                            if let Some(index) = pixel_index(*pixel) {
+                                *pixel = index_pixel(map(index));
+                            }
+                        }
+                        image
+                    })
+                    .collect()
+            })
+            .collect();
+        self.set_pixels_and_palette(Some(palette), images);
+    }
+    /// Remove color at index from the palette of indexed color mode. Pixels using it become the nearest remaining color
+    pub fn remove_palette_color(&mut self, index: usize) {
+        let Some(mut palette) = self.palette.clone() else {
+            return;
+        };
+        if index >= palette.colors.len() || palette.colors.len() == 1 {
+            return;
+        }
+        let removed = palette.colors.remove(index);
+        let removed = [removed[0] as f32, removed[1] as f32, removed[2] as f32];
+        let replacement = nearest_color(&palette.colors, removed) as u8;
+        let index = index as u8;
+        self.remap_indices(palette, |old| match old {
+            old if old == index => replacement,
+            old if old > index => old - 1,
+            old => old,
+        });
+    }
+    /// Move color of the palette of indexed color mode from one index to another, keeping the colors of pixels
+    pub fn move_palette_color(&mut self, from: usize, to: usize) {
+        let Some(mut palette) = self.palette.clone() else {
+            return;
+        };
+        if from >= palette.colors.len() || to >= palette.colors.len() || from == to {
+            return;
+        }
+        let color = palette.colors.remove(from);
+        palette.colors.insert(to, color);
+        let (from, to) = (from as u8, to as u8);
+        self.remap_indices(palette, |old| match old {
+            old if old == from => to,
+            // colors between shift towards where the moved color was
+            old if from < to && old > from && old <= to => old - 1,
+            old if to < from && old >= to && old < from => old + 1,
+            old => old,
+        });
+    }
+    /// Apply f to the colors of image, and get the image to store. In indexed color mode, pixels changed by f become the nearest palette color
+    pub fn edit_colors(&self, image: &Image, f: impl FnOnce(&mut Image)) -> Image {
+        let Some(palette) = &self.palette else {
+            let mut image = image.clone();
+            f(&mut image);
+            return image;
+        };
+        let decoded = decode_image(&palette.colors, image);
+        let mut edited = decoded.clone();
+        f(&mut edited);
+        let mut stored = image.clone();
+        for ((pixel, old), new) in stored
+            .get_image_data_mut()
+            .iter_mut()
+            .zip(decoded.get_image_data())
+            .zip(edited.get_image_data())
+        {
+            if old != new {
+                *pixel = encode_pixel(&palette.colors, *new);
+            }
+        }
+        stored
+    }
+    /// Show filter on the current layer, within the selection if there is one, without adding it to the undo history.
+    /// The layer is restored by [Canvas::cancel_filter_preview], or the filter is kept by [Canvas::apply_filter]
+    pub fn preview_filter(&mut self, filter: Filter, settings: FilterSettings) {
+        let layer_id = self.layers[self.current_layer].id;
+        if let Some(preview) = &self.filter_preview {
+            if preview.layer_id == layer_id
+                && preview.filter == filter
+                && preview.settings == settings
+            {
+                return;
+            }
+            if preview.layer_id != layer_id {
+                self.cancel_filter_preview();
+            }
+        }
+        let layer = &self.layers[self.current_layer];
+        let (original, modified) = match self.filter_preview.take() {
+            Some(preview) => (preview.original, preview.modified),
+            None => (layer.image.clone(), layer.modified),
+        };
+        let filtered = self.edit_colors(&original, |image| {
+            apply_filter(image, filter, &settings, self.selection.as_ref())
+        });
+        let layer = &mut self.layers[self.current_layer];
+        layer
+            .get_image_data_mut()
+            .copy_from_slice(filtered.get_image_data());
+        layer.force_update_region(None);
+        self.filter_preview = Some(FilterPreview {
+            layer_id,
+            original,
+            modified,
+            filter,
+            settings,
+        });
+    }
+    /// Restore the layer of the filter preview, if there is one
+    pub fn cancel_filter_preview(&mut self) {
+        let Some(preview) = self.filter_preview.take() else {
+            return;
+        };
+        // the layer may have been deleted meanwhile
+        if let Some(layer) = self
+            .layers
+            .iter_mut()
+            .find(|layer| layer.id == preview.layer_id)
+        {
+            layer.image = preview.original;
+            layer.modified = preview.modified;
+            layer.force_update_region(None);
+        }
+    }
+    /// Apply filter to the current layer, within the selection if there is one
+    pub fn apply_filter(&mut self, filter: Filter, settings: FilterSettings) {
+        self.commit_current_changes();
+        self.preview_filter(filter, settings);
+        if let Some(preview) = self.filter_preview.take() {
+            self.push_undo_action(UndoAction::LayerFull(self.current_layer, preview.original));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fill::tolerance_from_percent;
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+
+    fn pixel(canvas: &Canvas, x: u32, y: u32) -> [u8; 4] {
+        canvas.layers[canvas.current_layer].image.get_pixel(x, y)
+    }
+
+    #[test]
+    fn undo_and_redo_round_trip() {
+        let mut canvas = Canvas::new(4, 4, String::from("test")).unwrap();
+        canvas.current_changes.set_pixel(1, 2, RED);
+        canvas.commit_current_changes();
+        assert_eq!(pixel(&canvas, 1, 2), RED);
+
+        canvas.undo();
+        assert_eq!(pixel(&canvas, 1, 2), [0; 4]);
+        canvas.redo();
+        assert_eq!(pixel(&canvas, 1, 2), RED);
+        canvas.undo();
+        assert_eq!(pixel(&canvas, 1, 2), [0; 4]);
+    }
+
+    #[test]
+    fn new_action_clears_redo() {
+        let mut canvas = Canvas::new(4, 4, String::from("test")).unwrap();
+        canvas.current_changes.set_pixel(0, 0, RED);
+        canvas.commit_current_changes();
+        canvas.undo();
+
+        canvas.rename_layer(String::from("renamed"));
+        canvas.redo();
+        // the undone stroke can't come back after a new change
+        assert_eq!(pixel(&canvas, 0, 0), [0; 4]);
+        canvas.undo();
+        assert_ne!(canvas.layers[0].name, "renamed");
+        assert_eq!(pixel(&canvas, 0, 0), [0; 4]);
+    }
+
+    #[test]
+    fn layers_hash_by_id() {
+        use std::hash::{BuildHasher, RandomState};
+        let layer = Layer::new(gen_empty_image(1, 1), String::from("layer"));
+        let duplicate = layer.clone();
+        let hasher = RandomState::new();
+        assert_eq!(duplicate.name, layer.name);
+        assert_ne!(hasher.hash_one(&duplicate), hasher.hash_one(&layer));
+        assert_eq!(hasher.hash_one(&layer), hasher.hash_one(layer.id));
+    }
+
+    /// 3x1 canvas of black, white and near black, in indexed color mode with a palette of those colors
+    fn indexed_canvas() -> Canvas {
+        let mut canvas = Canvas::new(3, 1, String::from("test")).unwrap();
+        let pixels = [[0, 0, 0, 255], [255; 4], [10, 10, 10, 255]];
+        for (x, pixel) in pixels.into_iter().enumerate() {
+            canvas.layers[0].set_pixel(x as u32, 0, pixel);
+        }
+        let palette = Palette::new(String::new(), vec![[0; 3], [255; 3], [10; 3]]);
+        canvas.set_color_mode(Some(palette)).unwrap();
+        canvas
+    }
+
+    #[test]
+    fn color_mode_round_trip_keeps_colors() {
+        let mut canvas = indexed_canvas();
+        // stored as indices
+        assert_eq!(pixel(&canvas, 1, 0), [1, 0, 0, 255]);
+        assert_eq!(canvas.to_image().get_pixel(2, 0), [10, 10, 10, 255]);
+        canvas.set_color_mode(None).unwrap();
+        assert_eq!(pixel(&canvas, 2, 0), [10, 10, 10, 255]);
+        canvas.undo();
+        assert_eq!(pixel(&canvas, 2, 0), [2, 0, 0, 255]);
+
+        let Err(_) = canvas.set_color_mode(Some(Palette::new(String::new(), Vec::new()))) else {
+            panic!("empty palette set");
+        };
+        assert!(canvas.palette.as_ref().is_some_and(|p| p.colors.len() == 3));
+    }
+
+    #[test]
+    fn palette_colors_undo_keeps_palette_optional() {
+        let mut canvas = indexed_canvas();
+        let old_palette = canvas.palette.clone().unwrap();
+        canvas.palette.as_mut().unwrap().colors[1] = [255, 0, 0];
+        canvas.palette_changed(old_palette);
+        assert_eq!(canvas.to_image().get_pixel(1, 0), [255, 0, 0, 255]);
+        canvas.undo();
+        assert_eq!(canvas.to_image().get_pixel(1, 0), [255; 4]);
+        canvas.redo();
+        assert_eq!(canvas.to_image().get_pixel(1, 0), [255, 0, 0, 255]);
+
+        // an action recorded without a palette restores no palette, rather than an empty one
+        canvas.push_undo_action(UndoAction::PaletteColors(None));
+        canvas.undo();
+        assert!(canvas.palette.is_none());
+        canvas.redo();
+        assert!(canvas.palette.as_ref().is_some_and(|p| p.colors.len() == 3));
+    }
+
+    #[test]
+    fn indexed_regions_compare_colors() {
+        let canvas = indexed_canvas();
+        // black is near the near black at index 2, not the white at index 1
+        let region = canvas.find_region(0, 0, tolerance_from_percent(40), false, None);
+        assert_eq!(region, [true, false, true]);
+    }
+}
@@ -0,0 +1,5 @@
+pub const DIRECTIONS: [[isize; 2]; 4] = [[0, 1], [0, -1], [1, 0], [-1, 0]];
+/// Duration of new frames in milliseconds
+pub const DEFAULT_FRAME_DURATION: u32 = 100;
+/// Size of the bayer matrix used for ordered dithering
+pub const ORDERED_DITHER_SIZE: usize = 4;
@@ -0,0 +1,345 @@
+use strum::{EnumIter, IntoStaticStr};
+
+use crate::buffer::Image;
+
+/// How pixels past the edges of an image are sampled by spatial filters
+#[derive(Clone, Copy, PartialEq, IntoStaticStr, EnumIter)]
+#[strum(serialize_all = "lowercase")]
+pub enum EdgeMode {
+    /// Use the nearest edge pixel
+    Clamp,
+    /// Use the pixel from the opposite edge, for tiling images
+    Wrap,
+}
+
+impl EdgeMode {
+    /// Get the index of position in a row or column of length size
+    fn sample(&self, position: isize, size: usize) -> usize {
+        match self {
+            EdgeMode::Clamp => position.clamp(0, size as isize - 1) as usize,
+            EdgeMode::Wrap => position.rem_euclid(size as isize) as usize,
+        }
+    }
+}
+
+/// Pixels as premultiplied colors from 0 to 1, the same way [crate::canvas::overlay_colors] blends them.
+///
+/// Filtering premultiplied colors keeps the color of transparent pixels from bleeding into their neighbors
+pub struct Pixels {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<[f32; 4]>,
+}
+
+impl Pixels {
+    pub fn from_image(image: &Image) -> Self {
+        let data = image
+            .get_image_data()
+            .iter()
+            .map(|pixel| {
+                let alpha = pixel[3] as f32 / 255.;
+                [
+                    pixel[0] as f32 / 255. * alpha,
+                    pixel[1] as f32 / 255. * alpha,
+                    pixel[2] as f32 / 255. * alpha,
+                    alpha,
+                ]
+            })
+            .collect();
+        Pixels {
+            width: image.width(),
+            height: image.height(),
+            data,
+        }
+    }
+    pub fn to_image(&self) -> Image {
+        let mut bytes = vec![0; self.data.len() * 4];
+        for (pixel, target) in self.data.iter().zip(bytes.chunks_exact_mut(4)) {
+            let alpha = pixel[3].clamp(0., 1.);
+            if alpha <= 0. {
+                continue;
+            }
+            let scale = 255. / alpha;
+            for (target, channel) in target.iter_mut().zip(pixel) {
+                *target = (channel * scale + 0.5).clamp(0., 255.) as u8;
+            }
+            target[3] = (alpha * 255. + 0.5) as u8;
+        }
+        Image {
+            width: self.width as u16,
+            height: self.height as u16,
+            bytes,
+        }
+    }
+    /// Get pixels of the same size with new data
+    fn with_data(&self, data: Vec<[f32; 4]>) -> Self {
+        Pixels {
+            width: self.width,
+            height: self.height,
+            data,
+        }
+    }
+    /// Whether there are no pixels, which rows and columns can't be sampled from
+    fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+}
+
+/// Average each row of pixels in place over radius, using a running sum so it takes the same time for any radius
+fn box_blur_rows(data: &mut [[f32; 4]], width: usize, radius: usize, edge: EdgeMode) {
+    let radius = radius as isize;
+    let scale = 1. / (radius * 2 + 1) as f32;
+    let mut source = vec![[0.; 4]; width];
+    for row in data.chunks_exact_mut(width) {
+        source.copy_from_slice(row);
+        let sample = |x: isize| source[edge.sample(x, width)];
+        let mut sum = [0.; 4];
+        for x in -radius..=radius {
+            add_pixel(&mut sum, sample(x), 1.);
+        }
+        for (x, target) in row.iter_mut().enumerate() {
+            *target = sum.map(|channel| channel * scale);
+            add_pixel(&mut sum, sample(x as isize + radius + 1), 1.);
+            add_pixel(&mut sum, sample(x as isize - radius), -1.);
+        }
+    }
+}
+
+/// Average each column of source over radius into target. Whole rows are summed at once, since reading down columns is slow
+fn box_blur_columns(
+    source: &[[f32; 4]],
+    target: &mut [[f32; 4]],
+    width: usize,
+    height: usize,
+    radius: usize,
+    edge: EdgeMode,
+) {
+    let radius = radius as isize;
+    let scale = 1. / (radius * 2 + 1) as f32;
+    let row = |y: isize| {
+        let y = edge.sample(y, height);
+        &source[y * width..(y + 1) * width]
+    };
+    let mut sums = vec![[0.; 4]; width];
+    for y in -radius..=radius {
+        for (sum, pixel) in sums.iter_mut().zip(row(y)) {
+            add_pixel(sum, *pixel, 1.);
+        }
+    }
+    for (y, target) in target.chunks_exact_mut(width).enumerate() {
+        let y = y as isize;
+        for (target, sum) in target.iter_mut().zip(&sums) {
+            *target = sum.map(|channel| channel * scale);
+        }
+        for ((sum, added), removed) in sums
+            .iter_mut()
+            .zip(row(y + radius + 1))
+            .zip(row(y - radius))
+        {
+            add_pixel(sum, *added, 1.);
+            add_pixel(sum, *removed, -1.);
+        }
+    }
+}
+
+fn add_pixel(sum: &mut [f32; 4], pixel: [f32; 4], weight: f32) {
+    for (sum, channel) in sum.iter_mut().zip(pixel) {
+        *sum += channel * weight;
+    }
+}
+
+/// Apply a box blur of each radius in turn. Only one extra buffer is used, since images can be large
+fn box_blurs(pixels: &Pixels, radiuses: &[usize], edge: EdgeMode) -> Pixels {
+    if pixels.is_empty() {
+        return pixels.with_data(Vec::new());
+    }
+    let (width, height) = (pixels.width, pixels.height);
+    let mut data = pixels.data.clone();
+    // rows and columns are independent, so all rows can be blurred first
+    for radius in radiuses {
+        box_blur_rows(&mut data, width, *radius, edge);
+    }
+    let mut target = vec![[0.; 4]; data.len()];
+    for radius in radiuses {
+        box_blur_columns(&data, &mut target, width, height, *radius, edge);
+        std::mem::swap(&mut data, &mut target);
+    }
+    pixels.with_data(data)
+}
+
+/// Average each pixel with the (radius * 2 + 1)² square around it
+pub fn box_blur(pixels: &Pixels, radius: usize, edge: EdgeMode) -> Pixels {
+    box_blurs(pixels, &[radius], edge)
+}
+
+/// Blur with a gaussian of about radius, where radius is three standard deviations.
+///
+/// Approximated by three box blurs, so it takes the same time for any radius
+pub fn gaussian_blur(pixels: &Pixels, radius: usize, edge: EdgeMode) -> Pixels {
+    if radius == 0 {
+        return pixels.with_data(pixels.data.clone());
+    }
+    let sigma = radius as f32 / 3.;
+    // sizes of the boxes, whose combined variance is closest to sigma², see "Fast Almost-Gaussian Filtering" by Kovesi
+    let passes = 3.;
+    let ideal = (12. * sigma * sigma / passes + 1.).sqrt();
+    let mut lower = ideal.floor() as i32;
+    if lower % 2 == 0 {
+        lower -= 1;
+    }
+    let lower_f = lower as f32;
+    let lower_count =
+        ((12. * sigma * sigma - passes * lower_f * lower_f - 4. * passes * lower_f - 3. * passes)
+            / (-4. * lower_f - 4.))
+            .round() as i32;
+    let radiuses = [0, 1, 2].map(|pass| {
+        let size = if pass < lower_count { lower } else { lower + 2 };
+        (size.max(1) as usize - 1) / 2
+    });
+    box_blurs(pixels, &radiuses, edge)
+}
+
+/// Convolve pixels with a size × size kernel in row-major order, centered on each pixel
+pub fn convolve(pixels: &Pixels, kernel: &[f32], size: usize, edge: EdgeMode) -> Pixels {
+    if pixels.is_empty() {
+        return pixels.with_data(Vec::new());
+    }
+    let (width, height) = (pixels.width, pixels.height);
+    let half = (size / 2) as isize;
+    // skip zero weights, since most kernels have several
+    let weights: Vec<(isize, isize, f32)> = kernel
+        .iter()
+        .enumerate()
+        .filter(|(_, weight)| **weight != 0.)
+        .map(|(index, weight)| {
+            (
+                (index % size) as isize - half,
+                (index / size) as isize - half,
+                *weight,
+            )
+        })
+        .collect();
+    // the sampled column of each column for every horizontal offset, so edges are only handled once
+    let columns: Vec<Vec<usize>> = (-half..=half)
+        .map(|offset| {
+            (0..width as isize)
+                .map(|x| edge.sample(x + offset, width))
+                .collect()
+        })
+        .collect();
+    let mut data = vec![[0.; 4]; pixels.data.len()];
+    for (y, target) in data.chunks_exact_mut(width).enumerate() {
+        // each weight is added to the whole row at once, which reads the source rows in order
+        for (offset_x, offset_y, weight) in &weights {
+            let sample_y = edge.sample(y as isize + offset_y, height);
+            let row = &pixels.data[sample_y * width..(sample_y + 1) * width];
+            let columns = &columns[(offset_x + half) as usize];
+            for (sum, column) in target.iter_mut().zip(columns) {
+                add_pixel(sum, row[*column], *weight);
+            }
+        }
+    }
+    pixels.with_data(data)
+}
+
+/// Find edges with the sobel operator. Each color channel becomes the strength of its edges, and alpha is kept
+pub fn sobel(pixels: &Pixels, edge: EdgeMode) -> Pixels {
+    const HORIZONTAL: [f32; 9] = [-1., 0., 1., -2., 0., 2., -1., 0., 1.];
+    const VERTICAL: [f32; 9] = [-1., -2., -1., 0., 0., 0., 1., 2., 1.];
+    let horizontal = convolve(pixels, &HORIZONTAL, 3, edge);
+    let vertical = convolve(pixels, &VERTICAL, 3, edge);
+    let data = pixels
+        .data
+        .iter()
+        .zip(horizontal.data.iter().zip(&vertical.data))
+        .map(|(pixel, (horizontal, vertical))| {
+            let alpha = pixel[3];
+            let mut edges = [0.; 4];
+            for channel in 0..3 {
+                let strength = (horizontal[channel].powi(2) + vertical[channel].powi(2)).sqrt();
+                edges[channel] = strength.min(1.) * alpha;
+            }
+            edges[3] = alpha;
+            edges
+        })
+        .collect();
+    pixels.with_data(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use strum::IntoEnumIterator;
+
+    /// 3x3 opaque black pixels, with white at x, y
+    fn dot(x: usize, y: usize) -> Pixels {
+        let mut data = vec![[0., 0., 0., 1.]; 9];
+        data[x + y * 3] = [1.; 4];
+        Pixels {
+            width: 3,
+            height: 3,
+            data,
+        }
+    }
+
+    /// Red channel of each pixel, in ninths
+    fn ninths(pixels: &Pixels) -> Vec<f32> {
+        pixels
+            .data
+            .iter()
+            .map(|pixel| (pixel[0] * 9.).round())
+            .collect()
+    }
+
+    #[test]
+    fn box_blur_edges() {
+        let clamped = box_blur(&dot(0, 0), 1, EdgeMode::Clamp);
+        // the corner is sampled again for each position past the edges
+        assert_eq!(ninths(&clamped), [4., 2., 0., 2., 1., 0., 0., 0., 0.]);
+        let wrapped = box_blur(&dot(0, 0), 1, EdgeMode::Wrap);
+        // every pixel of a 3x3 image is next to every other one when wrapping
+        assert_eq!(ninths(&wrapped), [1.; 9]);
+        for edge in EdgeMode::iter() {
+            let blurred = box_blur(&dot(1, 1), 1, edge);
+            assert_eq!(ninths(&blurred), [1.; 9]);
+            assert!(blurred
+                .data
+                .iter()
+                .all(|pixel| (pixel[3] - 1.).abs() < 1e-5));
+        }
+    }
+
+    #[test]
+    fn outline_kernel() {
+        let outline = [-1., -1., -1., -1., 8., -1., -1., -1., -1.];
+        for edge in EdgeMode::iter() {
+            let outlined = convolve(&dot(1, 1), &outline, 3, edge);
+            let red: Vec<f32> = outlined.data.iter().map(|pixel| pixel[0]).collect();
+            assert_eq!(red, [-1., -1., -1., -1., 8., -1., -1., -1., -1.]);
+            // alpha is the same everywhere, so it has no outline
+            assert!(outlined.data.iter().all(|pixel| pixel[3] == 0.));
+        }
+        // flat images have no outline either
+        let flat = Image {
+            width: 3,
+            height: 3,
+            bytes: [[50, 100, 150, 255]; 9].concat(),
+        };
+        let outlined = convolve(&Pixels::from_image(&flat), &outline, 3, EdgeMode::Clamp);
+        assert_eq!(outlined.to_image().bytes, [0; 36]);
+    }
+
+    #[test]
+    fn empty_images() {
+        let empty = Pixels {
+            width: 0,
+            height: 4,
+            data: Vec::new(),
+        };
+        for edge in EdgeMode::iter() {
+            assert!(box_blur(&empty, 2, edge).data.is_empty());
+            assert!(gaussian_blur(&empty, 2, edge).data.is_empty());
+            assert!(sobel(&empty, edge).data.is_empty());
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use image::ImageFormat;
+
+use crate::{buffer::Image, canvas::Canvas, ora::*, project::*};
+
+/// Decode image of any supported format
+///
+/// Also returns which [ImageFormat] was used
+pub fn image_from_bytes(bytes: &[u8]) -> Result<(Image, ImageFormat), image::ImageError> {
+    let format_guess = image::guess_format(bytes)?;
+    let dynamic_image = image::load_from_memory_with_format(bytes, format_guess)?;
+    // sizes that don't fit would be cut off, leaving the bytes for a different size
+    let too_big = |_| {
+        image::ImageError::Limits(image::error::LimitError::from_kind(
+            image::error::LimitErrorKind::DimensionError,
+        ))
+    };
+    let width = u16::try_from(dynamic_image.width()).map_err(too_big)?;
+    let height = u16::try_from(dynamic_image.height()).map_err(too_big)?;
+    let image_bytes = dynamic_image.to_rgba8().into_raw();
+    Ok((
+        Image {
+            bytes: image_bytes,
+            width,
+            height,
+        },
+        format_guess,
+    ))
+}
+
+/// Load canvas from file bytes, detecting whether it is a plow project, an OpenRaster file or a regular image
+pub fn canvas_from_bytes(bytes: &[u8], name: String) -> Result<Canvas, std::io::Error> {
+    if is_project(bytes) {
+        canvas_from_project(bytes, name)
+    } else if is_ora(bytes) {
+        canvas_from_ora(bytes, name)
+    } else {
+        let (image, format) = image_from_bytes(bytes).map_err(std::io::Error::other)?;
+        Canvas::from_image(image, name, format)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn image_from_bytes_rejects_too_tall_images() {
+        let mut bytes = std::io::Cursor::new(Vec::new());
+        let height = u16::MAX as u32 + 2;
+        image::write_buffer_with_format(
+            &mut bytes,
+            &vec![0; height as usize * 4],
+            1,
+            height,
+            image::ColorType::Rgba8,
+            ImageFormat::Png,
+        )
+        .unwrap();
+        let Err(err) = image_from_bytes(bytes.get_ref()) else {
+            panic!("image taller than u16::MAX decoded");
+        };
+        assert!(matches!(err, image::ImageError::Limits(_)));
+    }
+}
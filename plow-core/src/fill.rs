@@ -0,0 +1,201 @@
+use crate::{
+    canvas::{BoundsTracker, Selection},
+    consts::DIRECTIONS,
+};
+
+/// Sum of the differences of each channel of two colors, from 0 to 1020 (255*4)
+pub fn compare_colors(color_a: [u8; 4], color_b: [u8; 4]) -> u16 {
+    let mut diffs = 0;
+    for part in 0..4 {
+        diffs += (color_a[part] as i16 - color_b[part] as i16).unsigned_abs()
+    }
+    diffs
+}
+
+/// Find contiguous area of similar color, starting at x, y. Returns a mask of which pixels are in the area.
+///
+/// If selection is Some, the area doesn't spread outside of it. If wrap is true, the area spreads across edges to the other side, like in tile mode.
+#[allow(clippy::too_many_arguments)]
+pub fn flood_region(
+    width: usize,
+    height: usize,
+    pixels: &[[u8; 4]],
+    x: usize,
+    y: usize,
+    tolerance: u16,
+    selection: Option<&Selection>,
+    wrap: bool,
+) -> Vec<bool> {
+    let mut visited = vec![false; width * height];
+    let mut buf: Vec<(usize, usize)> = vec![(x, y)];
+    visited[x + y * width] = true;
+    while let Some(item) = buf.pop() {
+        let x = item.0;
+        let y = item.1;
+        let old_color = pixels[x + y * width];
+        for dir in DIRECTIONS {
+            let (mut x, mut y) = (x as isize + dir[0], y as isize + dir[1]);
+            if wrap {
+                (x, y) = (x.rem_euclid(width as isize), y.rem_euclid(height as isize));
+            }
+            let x = x.try_into();
+            let y = y.try_into();
+            if let (Ok(x), Ok(y)) = (x, y) {
+                let x: usize = x;
+                let y: usize = y;
+                if x >= width || y >= height {
+                    continue;
+                }
+                let has_been_visited = visited[x + y * width];
+                if !has_been_visited
+                    && selection.is_none_or(|s| s.contains(x, y))
+                    && compare_colors(pixels[x + y * width], old_color) <= tolerance
+                {
+                    buf.push((x, y));
+                    visited[x + y * width] = true;
+                }
+            }
+        }
+    }
+    visited
+}
+
+/// Find all pixels of similar color to the one at x, y. Returns a mask of which pixels were found.
+///
+/// If selection is Some, only pixels within it are found.
+pub fn global_region(
+    width: usize,
+    pixels: &[[u8; 4]],
+    x: usize,
+    y: usize,
+    tolerance: u16,
+    selection: Option<&Selection>,
+) -> Vec<bool> {
+    // start color
+    let start_color = pixels[x + y * width];
+
+    pixels
+        .iter()
+        .enumerate()
+        .map(|(index, pixel)| {
+            selection.is_none_or(|s| s.contains(index % width, index / width))
+                && compare_colors(*pixel, start_color) <= tolerance
+        })
+        .collect()
+}
+
+/// Set all pixels in region mask to target color
+pub fn fill_region(
+    width: usize,
+    pixels: &mut [[u8; 4]],
+    region: &[bool],
+    target_color: [f32; 4],
+) -> BoundsTracker {
+    // convert target_color to u8
+    let target_color: [u8; 4] = [
+        (target_color[0] * 255.).floor() as u8,
+        (target_color[1] * 255.).floor() as u8,
+        (target_color[2] * 255.).floor() as u8,
+        (target_color[3] * 255.).floor() as u8,
+    ];
+    let mut bounds_tracker = BoundsTracker::new();
+    for (index, (pixel, in_region)) in pixels.iter_mut().zip(region).enumerate() {
+        if *in_region {
+            *pixel = target_color;
+            bounds_tracker.track((index % width) as u32, (index / width) as u32);
+        }
+    }
+    bounds_tracker
+}
+
+/// Fill contiguous area of similar color, starting at x, y. If selection is Some, the fill doesn't spread outside of it. If wrap is true, the fill spreads across edges, see [flood_region]
+#[allow(clippy::too_many_arguments)]
+pub fn flood_fill(
+    width: usize,
+    height: usize,
+    pixels: &mut [[u8; 4]],
+    x: usize,
+    y: usize,
+    target_color: [f32; 4],
+    tolerance: u16,
+    selection: Option<&Selection>,
+    wrap: bool,
+) -> BoundsTracker {
+    let region = flood_region(width, height, pixels, x, y, tolerance, selection, wrap);
+    fill_region(width, pixels, &region, target_color)
+}
+
+/// Fill all pixels of similar color to the one at x, y. If selection is Some, only pixels within it are filled.
+pub fn global_fill(
+    width: usize,
+    pixels: &mut [[u8; 4]],
+    x: usize,
+    y: usize,
+    target_color: [f32; 4],
+    tolerance: u16,
+    selection: Option<&Selection>,
+) -> BoundsTracker {
+    let region = global_region(width, pixels, x, y, tolerance, selection);
+    fill_region(width, pixels, &region, target_color)
+}
+
+/// Convert a 0-100 scale tolerance to a 0-1020 scale tolerance (1020=255*4), as used by [compare_colors]
+pub fn tolerance_from_percent(color_tolerance: u8) -> u16 {
+    // idek
+    // but not in a linear function
+    // i hate this but idk how to actually do this sort of color comparison
+    (1.04_f32.powf(color_tolerance as f32) / (4. / color_tolerance as f32)) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::Rect;
+
+    const CLEAR: [u8; 4] = [0; 4];
+    const WALL: [u8; 4] = [0, 0, 0, 255];
+
+    /// 4x2 image with a wall in the second column
+    fn walled() -> Vec<[u8; 4]> {
+        vec![CLEAR, WALL, CLEAR, CLEAR, CLEAR, WALL, CLEAR, CLEAR]
+    }
+
+    #[test]
+    fn flood_region_stops_at_walls() {
+        let region = flood_region(4, 2, &walled(), 2, 0, 0, None, false);
+        assert_eq!(region, [false, false, true, true, false, false, true, true]);
+    }
+
+    #[test]
+    fn flood_region_wraps_across_edges() {
+        // wrapping reaches the right side through the left edge
+        let region = flood_region(4, 2, &walled(), 0, 0, 0, None, true);
+        assert_eq!(region, [true, false, true, true, true, false, true, true]);
+    }
+
+    #[test]
+    fn global_region_finds_separate_areas() {
+        let region = global_region(4, &walled(), 2, 0, 0, None);
+        assert_eq!(region, [true, false, true, true, true, false, true, true]);
+    }
+
+    #[test]
+    fn flood_fill_stays_in_selection() {
+        let mut pixels = vec![CLEAR; 8];
+        let selection = Selection::from_rect(4, 2, Rect::new(0., 0., 2., 2.));
+        let mut bounds = flood_fill(
+            4,
+            2,
+            &mut pixels,
+            1,
+            1,
+            [1., 0., 0., 1.],
+            0,
+            Some(&selection),
+            true,
+        );
+        let red = [255, 0, 0, 255];
+        assert_eq!(pixels, [red, red, CLEAR, CLEAR, red, red, CLEAR, CLEAR]);
+        assert_eq!(bounds.flush(), Some(Rect::new(0., 0., 2., 2.)));
+    }
+}
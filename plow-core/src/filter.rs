@@ -0,0 +1,413 @@
+use strum::{EnumIter, IntoStaticStr};
+
+use crate::{buffer::Image, canvas::Selection, convolution::*};
+
+/// Number of points of the curves filter, evenly spaced from input 0 to 255
+pub const CURVE_POINTS: usize = 5;
+/// Largest size of custom kernels
+pub const MAX_KERNEL_SIZE: usize = 7;
+const SHARPEN_KERNEL: [f32; 9] = [0., -1., 0., -1., 5., -1., 0., -1., 0.];
+const EMBOSS_KERNEL: [f32; 9] = [-2., -1., 0., -1., 1., 1., 0., 1., 2.];
+
+/// Color adjustment or spatial filter applied to a layer
+#[derive(Clone, Copy, PartialEq, IntoStaticStr, EnumIter)]
+pub enum Filter {
+    #[strum(serialize = "invert")]
+    Invert,
+    #[strum(serialize = "grayscale")]
+    Grayscale,
+    #[strum(serialize = "brightness/contrast")]
+    BrightnessContrast,
+    #[strum(serialize = "hue/saturation/lightness")]
+    HueSaturationLightness,
+    #[strum(serialize = "posterize")]
+    Posterize,
+    #[strum(serialize = "threshold")]
+    Threshold,
+    #[strum(serialize = "levels")]
+    Levels,
+    #[strum(serialize = "curves")]
+    Curves,
+    #[strum(serialize = "gaussian blur")]
+    GaussianBlur,
+    #[strum(serialize = "box blur")]
+    BoxBlur,
+    #[strum(serialize = "unsharp mask")]
+    UnsharpMask,
+    #[strum(serialize = "sharpen")]
+    Sharpen,
+    #[strum(serialize = "emboss")]
+    Emboss,
+    #[strum(serialize = "edge detect")]
+    EdgeDetect,
+    #[strum(serialize = "custom kernel")]
+    CustomKernel,
+}
+
+/// Parameters of all filters. Each filter only uses its own
+#[derive(Clone, Copy, PartialEq)]
+pub struct FilterSettings {
+    /// From -1 to 1
+    pub brightness: f32,
+    /// From -1 to 1
+    pub contrast: f32,
+    /// Rotation of hue in degrees, from -180 to 180
+    pub hue: f32,
+    /// From -1 (gray) to 1
+    pub saturation: f32,
+    /// From -1 (black) to 1 (white)
+    pub lightness: f32,
+    /// Levels of each channel, at least 2
+    pub posterize_levels: u8,
+    /// Pixels at least this bright become white, and the rest black
+    pub threshold: u8,
+    /// Input value that becomes black
+    pub levels_black: u8,
+    /// Input value that becomes white
+    pub levels_white: u8,
+    /// Gamma of the midtones, where higher is brighter
+    pub levels_gamma: f32,
+    /// Output value of each point from 0 to 1, see [CURVE_POINTS]
+    pub curve: [f32; CURVE_POINTS],
+    /// Radius of blurs and unsharp mask, in pixels
+    pub blur_radius: usize,
+    /// How much unsharp mask increases the difference to the blurred image
+    pub unsharp_amount: f32,
+    /// How pixels past the edges are sampled by spatial filters
+    pub edge_mode: EdgeMode,
+    /// Size of custom kernel, which is odd
+    pub kernel_size: usize,
+    /// Weights of custom kernel in row-major order. Kernels smaller than [MAX_KERNEL_SIZE] use the center of it
+    pub kernel: [f32; MAX_KERNEL_SIZE * MAX_KERNEL_SIZE],
+    /// Whether custom kernel is divided by the sum of its weights, so it keeps brightness
+    pub kernel_normalize: bool,
+}
+
+impl Default for FilterSettings {
+    fn default() -> Self {
+        let mut curve = [0.; CURVE_POINTS];
+        for (index, point) in curve.iter_mut().enumerate() {
+            *point = index as f32 / (CURVE_POINTS - 1) as f32;
+        }
+        // a single weight in the center keeps the image as is
+        let mut kernel = [0.; MAX_KERNEL_SIZE * MAX_KERNEL_SIZE];
+        kernel[kernel.len() / 2] = 1.;
+        FilterSettings {
+            brightness: 0.,
+            contrast: 0.,
+            hue: 0.,
+            saturation: 0.,
+            lightness: 0.,
+            posterize_levels: 4,
+            threshold: 128,
+            levels_black: 0,
+            levels_white: 255,
+            levels_gamma: 1.,
+            curve,
+            blur_radius: 2,
+            unsharp_amount: 1.,
+            edge_mode: EdgeMode::Clamp,
+            kernel_size: 3,
+            kernel,
+            kernel_normalize: true,
+        }
+    }
+}
+
+impl FilterSettings {
+    /// Get index of the weight at x, y of custom kernel in [FilterSettings::kernel]
+    pub fn kernel_index(&self, x: usize, y: usize) -> usize {
+        let offset = (MAX_KERNEL_SIZE - self.kernel_size) / 2;
+        (x + offset) + (y + offset) * MAX_KERNEL_SIZE
+    }
+    /// Get custom kernel of kernel_size × kernel_size weights
+    fn custom_kernel(&self) -> Vec<f32> {
+        let mut kernel: Vec<f32> = (0..self.kernel_size * self.kernel_size)
+            .map(|index| {
+                self.kernel[self.kernel_index(index % self.kernel_size, index / self.kernel_size)]
+            })
+            .collect();
+        let sum: f32 = kernel.iter().sum();
+        if self.kernel_normalize && sum != 0. {
+            for weight in kernel.iter_mut() {
+                *weight /= sum;
+            }
+        }
+        kernel
+    }
+}
+
+/// Perceived brightness of a color, from 0 to 1
+fn luminance(color: [f32; 3]) -> f32 {
+    0.2126 * color[0] + 0.7152 * color[1] + 0.0722 * color[2]
+}
+
+fn rgb_to_hsl(color: [f32; 3]) -> [f32; 3] {
+    let max = color[0].max(color[1]).max(color[2]);
+    let min = color[0].min(color[1]).min(color[2]);
+    let lightness = (max + min) / 2.;
+    let delta = max - min;
+    if delta == 0. {
+        return [0., 0., lightness];
+    }
+    let saturation = delta / (1. - (2. * lightness - 1.).abs());
+    let hue = if max == color[0] {
+        ((color[1] - color[2]) / delta).rem_euclid(6.)
+    } else if max == color[1] {
+        (color[2] - color[0]) / delta + 2.
+    } else {
+        (color[0] - color[1]) / delta + 4.
+    };
+    [hue * 60., saturation, lightness]
+}
+
+fn hsl_to_rgb(color: [f32; 3]) -> [f32; 3] {
+    let [hue, saturation, lightness] = color;
+    let chroma = (1. - (2. * lightness - 1.).abs()) * saturation;
+    let hue = hue.rem_euclid(360.) / 60.;
+    let x = chroma * (1. - (hue % 2. - 1.).abs());
+    let (r, g, b) = match hue as u32 {
+        0 => (chroma, x, 0.),
+        1 => (x, chroma, 0.),
+        2 => (0., chroma, x),
+        3 => (0., x, chroma),
+        4 => (x, 0., chroma),
+        _ => (chroma, 0., x),
+    };
+    let m = lightness - chroma / 2.;
+    [r + m, g + m, b + m]
+}
+
+impl Filter {
+    /// Get table of the new value of each channel value, for filters that change channels independently
+    fn lookup_table(&self, settings: &FilterSettings) -> Option<[u8; 256]> {
+        let map: Box<dyn Fn(f32) -> f32> = match self {
+            Filter::Invert => Box::new(|value| 1. - value),
+            Filter::BrightnessContrast => {
+                // contrast of -1 flattens everything to gray, and 1 is a hard threshold.
+                // abs, since at 1 the angle rounds to just past a right angle, where tan is negative
+                let factor = ((settings.contrast.clamp(-1., 1.) + 1.)
+                    * std::f32::consts::FRAC_PI_4)
+                    .tan()
+                    .abs();
+                Box::new(move |value| (value - 0.5) * factor + 0.5 + settings.brightness)
+            }
+            Filter::Posterize => {
+                let steps = settings.posterize_levels.max(2) as f32 - 1.;
+                Box::new(move |value| (value * steps).round() / steps)
+            }
+            Filter::Levels => {
+                let black = settings.levels_black as f32 / 255.;
+                let white = (settings.levels_white as f32 / 255.).max(black + 1. / 255.);
+                let gamma = settings.levels_gamma.max(0.01);
+                Box::new(move |value| {
+                    ((value - black) / (white - black))
+                        .clamp(0., 1.)
+                        .powf(1. / gamma)
+                })
+            }
+            Filter::Curves => Box::new(|value| {
+                // interpolate linearly between the two points around value
+                let position = value * (CURVE_POINTS - 1) as f32;
+                let index = (position.floor() as usize).min(CURVE_POINTS - 2);
+                let t = position - index as f32;
+                let (a, b) = (settings.curve[index], settings.curve[index + 1]);
+                a + (b - a) * t
+            }),
+            _ => return None,
+        };
+        let mut table = [0; 256];
+        for (value, entry) in table.iter_mut().enumerate() {
+            *entry = (map(value as f32 / 255.).clamp(0., 1.) * 255.).round() as u8;
+        }
+        Some(table)
+    }
+    /// Whether filter uses the pixels around each pixel, instead of only the pixel itself
+    pub fn is_spatial(&self) -> bool {
+        matches!(
+            self,
+            Filter::GaussianBlur
+                | Filter::BoxBlur
+                | Filter::UnsharpMask
+                | Filter::Sharpen
+                | Filter::Emboss
+                | Filter::EdgeDetect
+                | Filter::CustomKernel
+        )
+    }
+    /// Get filtered image, for spatial filters
+    fn apply_spatial(&self, image: &Image, settings: &FilterSettings) -> Option<Image> {
+        let pixels = Pixels::from_image(image);
+        let edge = settings.edge_mode;
+        let filtered = match self {
+            Filter::GaussianBlur => gaussian_blur(&pixels, settings.blur_radius, edge),
+            Filter::BoxBlur => box_blur(&pixels, settings.blur_radius, edge),
+            Filter::UnsharpMask => {
+                let mut blurred = gaussian_blur(&pixels, settings.blur_radius, edge);
+                for (blurred, pixel) in blurred.data.iter_mut().zip(&pixels.data) {
+                    for channel in 0..4 {
+                        blurred[channel] = pixel[channel]
+                            + (pixel[channel] - blurred[channel]) * settings.unsharp_amount;
+                    }
+                }
+                blurred
+            }
+            Filter::Sharpen => convolve(&pixels, &SHARPEN_KERNEL, 3, edge),
+            Filter::Emboss => convolve(&pixels, &EMBOSS_KERNEL, 3, edge),
+            Filter::EdgeDetect => sobel(&pixels, edge),
+            Filter::CustomKernel => convolve(
+                &pixels,
+                &settings.custom_kernel(),
+                settings.kernel_size,
+                edge,
+            ),
+            _ => return None,
+        };
+        Some(filtered.to_image())
+    }
+    /// Apply filter to a color, for filters that don't change channels independently
+    fn apply_color(&self, color: [f32; 3], settings: &FilterSettings) -> [f32; 3] {
+        match self {
+            Filter::Grayscale => [luminance(color); 3],
+            Filter::Threshold => {
+                let white = luminance(color) * 255. >= settings.threshold as f32;
+                [if white { 1. } else { 0. }; 3]
+            }
+            Filter::HueSaturationLightness => {
+                let [hue, saturation, lightness] = rgb_to_hsl(color);
+                let saturation = (saturation * (1. + settings.saturation)).clamp(0., 1.);
+                // move lightness towards white or black
+                let lightness = if settings.lightness >= 0. {
+                    lightness + (1. - lightness) * settings.lightness
+                } else {
+                    lightness * (1. + settings.lightness)
+                };
+                hsl_to_rgb([hue + settings.hue, saturation, lightness.clamp(0., 1.)])
+            }
+            _ => color,
+        }
+    }
+}
+
+/// Apply filter to image. Color adjustments change every non transparent pixel and keep alpha. If selection is Some, only pixels within it are changed
+pub fn apply_filter(
+    image: &mut Image,
+    filter: Filter,
+    settings: &FilterSettings,
+    selection: Option<&Selection>,
+) {
+    let width = image.width();
+    // spatial filters can make transparent pixels visible, like blurs do at the edges of shapes
+    if let Some(filtered) = filter.apply_spatial(image, settings) {
+        for (index, (pixel, filtered)) in image
+            .get_image_data_mut()
+            .iter_mut()
+            .zip(filtered.get_image_data())
+            .enumerate()
+        {
+            if selection.is_none_or(|s| s.contains(index % width, index / width)) {
+                *pixel = *filtered;
+            }
+        }
+        return;
+    }
+    let table = filter.lookup_table(settings);
+    for (index, pixel) in image.get_image_data_mut().iter_mut().enumerate() {
+        if pixel[3] == 0 || selection.is_some_and(|s| !s.contains(index % width, index / width)) {
+            continue;
+        }
+        match &table {
+            Some(table) => {
+                for channel in 0..3 {
+                    pixel[channel] = table[pixel[channel] as usize];
+                }
+            }
+            None => {
+                let color = [
+                    pixel[0] as f32 / 255.,
+                    pixel[1] as f32 / 255.,
+                    pixel[2] as f32 / 255.,
+                ];
+                let color = filter.apply_color(color, settings);
+                for channel in 0..3 {
+                    pixel[channel] = (color[channel].clamp(0., 1.) * 255.).round() as u8;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Apply filter with settings to each color, as a row of pixels
+    fn filter_colors(filter: Filter, settings: FilterSettings, colors: &[[u8; 4]]) -> Vec<[u8; 4]> {
+        let mut image = Image {
+            width: colors.len() as u16,
+            height: 1,
+            bytes: colors.concat(),
+        };
+        apply_filter(&mut image, filter, &settings, None);
+        image.get_image_data().to_vec()
+    }
+
+    #[test]
+    fn invert_keeps_alpha_and_transparent_pixels() {
+        let colors = [[255, 0, 100, 255], [10, 20, 30, 128], [10, 20, 30, 0]];
+        let inverted = filter_colors(Filter::Invert, FilterSettings::default(), &colors);
+        assert_eq!(
+            inverted,
+            [[0, 255, 155, 255], [245, 235, 225, 128], [10, 20, 30, 0]]
+        );
+    }
+
+    #[test]
+    fn brightness_and_contrast() {
+        let colors = [[0, 102, 153, 255]];
+        let settings = FilterSettings {
+            brightness: 0.2,
+            ..Default::default()
+        };
+        let brighter = filter_colors(Filter::BrightnessContrast, settings, &colors);
+        assert_eq!(brighter, [[51, 153, 204, 255]]);
+        // lowest contrast is flat gray, and the highest a threshold at the middle
+        let settings = FilterSettings {
+            contrast: -1.,
+            ..Default::default()
+        };
+        let flat = filter_colors(Filter::BrightnessContrast, settings, &colors);
+        assert_eq!(flat, [[128, 128, 128, 255]]);
+        let settings = FilterSettings {
+            contrast: 1.,
+            ..Default::default()
+        };
+        let threshold = filter_colors(Filter::BrightnessContrast, settings, &colors);
+        assert_eq!(threshold, [[0, 0, 255, 255]]);
+    }
+
+    #[test]
+    fn hue_saturation_lightness() {
+        let red = [[255, 0, 0, 255]];
+        let rotate = |hue: f32| FilterSettings {
+            hue,
+            ..Default::default()
+        };
+        let rotated = filter_colors(Filter::HueSaturationLightness, rotate(120.), &red);
+        assert_eq!(rotated, [[0, 255, 0, 255]]);
+        let rotated = filter_colors(Filter::HueSaturationLightness, rotate(-120.), &red);
+        assert_eq!(rotated, [[0, 0, 255, 255]]);
+        let settings = FilterSettings {
+            saturation: -1.,
+            ..Default::default()
+        };
+        let gray = filter_colors(Filter::HueSaturationLightness, settings, &red);
+        assert_eq!(gray, [[128, 128, 128, 255]]);
+        let settings = FilterSettings {
+            lightness: 1.,
+            ..Default::default()
+        };
+        let white = filter_colors(Filter::HueSaturationLightness, settings, &red);
+        assert_eq!(white, [[255, 255, 255, 255]]);
+    }
+}
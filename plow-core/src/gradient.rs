@@ -0,0 +1,288 @@
+//! Gradients, which fill an area with colors changing from a start point to an end point.
+
+use std::f32::consts::TAU;
+use strum::{EnumIter, IntoStaticStr};
+
+use crate::{canvas::Layer, indexed::encode_pixel, palette::bayer_threshold};
+
+/// How the position along a gradient is measured from its start and end
+#[derive(Clone, Copy, PartialEq, IntoStaticStr, EnumIter)]
+#[strum(serialize_all = "lowercase")]
+pub enum GradientShape {
+    /// Along the line from start to end
+    Linear,
+    /// By distance from start, reaching the end at the distance of end
+    Radial,
+    /// By angle around start, going around once clockwise from the direction of end
+    Angular,
+    /// Like radial, but distance is measured along and across the direction of end, making a diamond
+    Diamond,
+}
+
+/// Color space colors are interpolated in
+#[derive(Clone, Copy, PartialEq, IntoStaticStr, EnumIter)]
+pub enum ColorSpace {
+    /// Interpolate stored values, which is how most image editors do it
+    #[strum(serialize = "srgb")]
+    Srgb,
+    /// Interpolate light intensity, which keeps mixes of bright colors bright
+    #[strum(serialize = "linear")]
+    Linear,
+    /// Interpolate perceived lightness, chroma and hue, which changes evenly to the eye
+    #[strum(serialize = "oklab")]
+    Oklab,
+}
+
+impl ColorSpace {
+    /// Convert srgb color from 0 to 1 to this space
+    fn encode(self, color: [f32; 3]) -> [f32; 3] {
+        match self {
+            ColorSpace::Srgb => color,
+            ColorSpace::Linear => color.map(srgb_to_linear),
+            ColorSpace::Oklab => linear_to_oklab(color.map(srgb_to_linear)),
+        }
+    }
+    /// Convert color in this space to srgb from 0 to 1
+    fn decode(self, color: [f32; 3]) -> [f32; 3] {
+        let color = match self {
+            ColorSpace::Srgb => color,
+            ColorSpace::Linear => color.map(linear_to_srgb),
+            ColorSpace::Oklab => oklab_to_linear(color).map(linear_to_srgb),
+        };
+        color.map(|channel| channel.clamp(0., 1.))
+    }
+}
+
+fn srgb_to_linear(channel: f32) -> f32 {
+    if channel <= 0.04045 {
+        channel / 12.92
+    } else {
+        ((channel + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(channel: f32) -> f32 {
+    if channel <= 0.0031308 {
+        channel * 12.92
+    } else {
+        1.055 * channel.max(0.).powf(1. / 2.4) - 0.055
+    }
+}
+
+/// See https://bottosson.github.io/posts/oklab/
+fn linear_to_oklab([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let l = (0.41222147 * r + 0.53633254 * g + 0.05144599 * b).cbrt();
+    let m = (0.211_903_5 * r + 0.680_699_5 * g + 0.10739696 * b).cbrt();
+    let s = (0.08830246 * r + 0.28171884 * g + 0.629_978_7 * b).cbrt();
+    [
+        0.21045426 * l + 0.793_617_8 * m - 0.00407205 * s,
+        1.977_998_5 * l - 2.428_592_2 * m + 0.450_593_7 * s,
+        0.02590404 * l + 0.78277177 * m - 0.80867577 * s,
+    ]
+}
+
+fn oklab_to_linear([lightness, a, b]: [f32; 3]) -> [f32; 3] {
+    let l = (lightness + 0.39633778 * a + 0.21580376 * b).powi(3);
+    let m = (lightness - 0.10556135 * a - 0.06385417 * b).powi(3);
+    let s = (lightness - 0.08948418 * a - 1.291_485_5 * b).powi(3);
+    [
+        4.076_741_7 * l - 3.307_711_6 * m + 0.23096993 * s,
+        -1.268_438 * l + 2.609_757_4 * m - 0.341_319_4 * s,
+        -0.00419609 * l - 0.703_418_6 * m + 1.707_614_7 * s,
+    ]
+}
+
+/// Color of a gradient at a position
+#[derive(Clone, Copy, PartialEq)]
+pub struct GradientStop {
+    /// From 0 (start) to 1 (end)
+    pub position: f32,
+    /// Rgba color from 0 to 1, not premultiplied
+    pub color: [f32; 4],
+}
+
+pub struct Gradient {
+    pub shape: GradientShape,
+    pub space: ColorSpace,
+    /// Size of the bayer matrix to dither between neighboring stops with, instead of interpolating their colors. The matrix repeats from the top left corner of the canvas, like stroke patterns
+    pub dither: Option<usize>,
+    /// Stops sorted by position. There must be at least one
+    pub stops: Vec<GradientStop>,
+    pub start: (f32, f32),
+    pub end: (f32, f32),
+}
+
+impl Gradient {
+    /// Get how far x, y is along the gradient, from 0 at start to 1 at end. Points beyond either end are clamped
+    pub fn position(&self, x: f32, y: f32) -> f32 {
+        let (dx, dy) = (self.end.0 - self.start.0, self.end.1 - self.start.1);
+        let (x, y) = (x - self.start.0, y - self.start.1);
+        let length_squared = dx * dx + dy * dy;
+        if self.shape == GradientShape::Angular {
+            return ((y.atan2(x) - dy.atan2(dx)) / TAU).rem_euclid(1.);
+        }
+        if length_squared == 0. {
+            return 1.;
+        }
+        let length = length_squared.sqrt();
+        let position = match self.shape {
+            GradientShape::Linear => (x * dx + y * dy) / length_squared,
+            GradientShape::Radial => (x * x + y * y).sqrt() / length,
+            GradientShape::Diamond => {
+                let along = (x * dx + y * dy) / length;
+                let across = (x * dy - y * dx) / length;
+                (along.abs() + across.abs()) / length
+            }
+            GradientShape::Angular => unreachable!(),
+        };
+        position.clamp(0., 1.)
+    }
+    /// Get color of the gradient at pixel x, y of the canvas
+    pub fn color_at(&self, x: usize, y: usize) -> [u8; 4] {
+        let position = self.position(x as f32, y as f32);
+        let next = self
+            .stops
+            .iter()
+            .position(|stop| stop.position > position)
+            .unwrap_or(self.stops.len());
+        let color = if next == 0 || next == self.stops.len() {
+            self.stops[next.min(self.stops.len() - 1)].color
+        } else {
+            let (from, to) = (self.stops[next - 1], self.stops[next]);
+            let amount = (position - from.position) / (to.position - from.position);
+            match self.dither {
+                Some(size) if bayer_threshold(x, y, size) < amount => to.color,
+                Some(_) => from.color,
+                None => self.interpolate(from.color, to.color, amount),
+            }
+        };
+        color.map(|channel| (channel * 255.).round() as u8)
+    }
+    /// Mix colors by amount from 0 (from) to 1 (to) in the color space of the gradient, with premultiplied alpha so transparent colors don't darken their neighbors
+    fn interpolate(&self, from: [f32; 4], to: [f32; 4], amount: f32) -> [f32; 4] {
+        let alpha = from[3] + (to[3] - from[3]) * amount;
+        if alpha <= 0. {
+            return [0.; 4];
+        }
+        let from_color = self.space.encode([from[0], from[1], from[2]]);
+        let to_color = self.space.encode([to[0], to[1], to[2]]);
+        let mut color = [0.; 3];
+        for channel in 0..3 {
+            let from = from_color[channel] * from[3];
+            let to = to_color[channel] * to[3];
+            color[channel] = (from + (to - from) * amount) / alpha;
+        }
+        let [r, g, b] = self.space.decode(color);
+        [r, g, b, alpha]
+    }
+}
+
+/// Fill pixels of layer where inside is true with gradient. In indexed color mode, colors is the palette and pixels are stored as indices of the nearest colors
+pub fn draw_gradient(
+    layer: &mut Layer,
+    gradient: &Gradient,
+    colors: Option<&[[u8; 3]]>,
+    inside: impl Fn(usize, usize) -> bool,
+) {
+    for y in 0..layer.height() {
+        for x in 0..layer.width() {
+            if !inside(x, y) {
+                continue;
+            }
+            let color = gradient.color_at(x, y);
+            let color = match colors {
+                Some(colors) => encode_pixel(colors, color),
+                None => color,
+            };
+            layer.set_pixel(x as u32, y as u32, color);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: [f32; 4] = [0., 0., 0., 1.];
+    const WHITE: [f32; 4] = [1.; 4];
+
+    /// Black to white gradient
+    fn gradient(shape: GradientShape, start: (f32, f32), end: (f32, f32)) -> Gradient {
+        Gradient {
+            shape,
+            space: ColorSpace::Srgb,
+            dither: None,
+            stops: vec![
+                GradientStop {
+                    position: 0.,
+                    color: BLACK,
+                },
+                GradientStop {
+                    position: 1.,
+                    color: WHITE,
+                },
+            ],
+            start,
+            end,
+        }
+    }
+
+    #[test]
+    fn dithering_only_uses_stop_colors() {
+        for size in [2, 4, 8] {
+            let mut gradient = gradient(GradientShape::Linear, (0., 0.), (15., 0.));
+            gradient.dither = Some(size);
+            let mut whites = Vec::new();
+            for x in 0..16 {
+                let mut count = 0;
+                for y in 0..8 {
+                    let color = gradient.color_at(x, y);
+                    assert!(color == [0, 0, 0, 255] || color == [255; 4]);
+                    count += (color == [255; 4]) as usize;
+                }
+                whites.push(count);
+            }
+            assert_eq!(whites[0], 0);
+            assert_eq!(whites[15], 8);
+            // about half of the pixels are white
+            let total: usize = whites.iter().sum();
+            assert!((48..=80).contains(&total), "{total}");
+        }
+    }
+
+    #[test]
+    fn linear_and_radial_reach_their_ends() {
+        let linear = gradient(GradientShape::Linear, (2., 0.), (12., 0.));
+        assert_eq!(linear.color_at(2, 5), [0, 0, 0, 255]);
+        assert_eq!(linear.color_at(12, 5), [255; 4]);
+        assert_eq!(linear.color_at(7, 5), [128, 128, 128, 255]);
+        // beyond the ends colors are clamped
+        assert_eq!(linear.color_at(0, 0), [0, 0, 0, 255]);
+        assert_eq!(linear.color_at(20, 0), [255; 4]);
+
+        let radial = gradient(GradientShape::Radial, (5., 5.), (5., 9.));
+        assert_eq!(radial.color_at(5, 5), [0, 0, 0, 255]);
+        for (x, y) in [(5, 9), (9, 5), (1, 5), (5, 1)] {
+            assert_eq!(radial.color_at(x, y), [255; 4]);
+        }
+        assert_eq!(radial.color_at(0, 0), [255; 4]);
+        assert_eq!(radial.color_at(7, 5), [128, 128, 128, 255]);
+    }
+
+    #[test]
+    fn angular_wraps_around() {
+        let angular = gradient(GradientShape::Angular, (5., 5.), (9., 5.));
+        assert_eq!(angular.position(9., 5.), 0.);
+        // just clockwise of the end it starts over, just counterclockwise it is nearly done
+        assert!(angular.position(9., 6.) < 0.05);
+        assert!(angular.position(9., 4.) > 0.95);
+        assert_eq!(angular.position(5., 9.), 0.25);
+        assert_eq!(angular.position(1., 5.), 0.5);
+        assert_eq!(angular.position(5., 1.), 0.75);
+        for y in 0..10 {
+            for x in 0..10 {
+                assert!((0. ..1.).contains(&angular.position(x as f32, y as f32)));
+            }
+        }
+    }
+}
@@ -0,0 +1,178 @@
+//! Indexed color, where pixels are indices into a palette instead of colors.
+//!
+//! Indexed images are stored like rgba images, so layers, frames and undo history work the same in both modes.
+//! The palette index is in the red channel, and alpha is either 0 (transparent) or 255. Such images have to be decoded with their palette before being shown.
+
+use image::ImageFormat;
+
+use crate::{buffer::Image, palette::nearest_color};
+
+/// Most colors a palette of indexed color mode can have, since indices are a byte
+pub const INDEXED_MAX_COLORS: usize = 256;
+/// Pixels with lower alpha than this become transparent when converted to indices
+const INDEXED_ALPHA_THRESHOLD: u8 = 128;
+
+/// Get the stored pixel of palette index
+pub fn index_pixel(index: u8) -> [u8; 4] {
+    [index, 0, 0, 255]
+}
+
+/// Get index of stored pixel, or None if it is transparent
+pub fn pixel_index(pixel: [u8; 4]) -> Option<u8> {
+    (pixel[3] != 0).then_some(pixel[0])
+}
+
+/// Get color of stored pixel. Indices outside of the palette are transparent
+pub fn decode_pixel(colors: &[[u8; 3]], pixel: [u8; 4]) -> [u8; 4] {
+    match pixel_index(pixel).and_then(|index| colors.get(index as usize)) {
+        Some(color) => [color[0], color[1], color[2], 255],
+        None => [0; 4],
+    }
+}
+
+/// Get stored pixel of the palette color nearest to color
+pub fn encode_pixel(colors: &[[u8; 3]], color: [u8; 4]) -> [u8; 4] {
+    if color[3] < INDEXED_ALPHA_THRESHOLD || colors.is_empty() {
+        return [0; 4];
+    }
+    let color = [color[0] as f32, color[1] as f32, color[2] as f32];
+    index_pixel(nearest_color(colors, color) as u8)
+}
+
+/// Get colors of indexed image
+pub fn decode_image(colors: &[[u8; 3]], image: &Image) -> Image {
+    let mut image = image.clone();
+    for pixel in image.get_image_data_mut() {
+        *pixel = decode_pixel(colors, *pixel);
+    }
+    image
+}
+
+/// Convert image to indices of the nearest palette colors
+pub fn encode_image(colors: &[[u8; 3]], image: &Image) -> Image {
+    let mut image = image.clone();
+    for pixel in image.get_image_data_mut() {
+        *pixel = encode_pixel(colors, *pixel);
+    }
+    image
+}
+
+/// Whether format can be saved with a palette
+pub fn supports_indexed(format: ImageFormat) -> bool {
+    matches!(
+        format,
+        ImageFormat::Png | ImageFormat::Gif | ImageFormat::Bmp
+    )
+}
+
+/// Encode indexed image with its palette, in a format that [supports_indexed].
+///
+/// Transparent pixels use an extra palette color after the others, if there is room for one. Otherwise, and in bmp which has no transparency, they use the first color
+pub fn encode_indexed(
+    colors: &[[u8; 3]],
+    image: &Image,
+    format: ImageFormat,
+) -> Result<Vec<u8>, std::io::Error> {
+    let (width, height) = (image.width, image.height);
+    let has_transparency = image.get_image_data().iter().any(|pixel| pixel[3] == 0);
+    let transparent =
+        (has_transparency && colors.len() < INDEXED_MAX_COLORS && format != ImageFormat::Bmp)
+            .then_some(colors.len() as u8);
+    let indices: Vec<u8> = image
+        .get_image_data()
+        .iter()
+        .map(|pixel| pixel_index(*pixel).or(transparent).unwrap_or(0))
+        .collect();
+    let mut palette: Vec<u8> = colors.iter().flatten().copied().collect();
+    if transparent.is_some() {
+        palette.extend_from_slice(&[0, 0, 0]);
+    }
+
+    let mut bytes = Vec::new();
+    match format {
+        ImageFormat::Png => {
+            let mut encoder = png::Encoder::new(&mut bytes, width as u32, height as u32);
+            encoder.set_color(png::ColorType::Indexed);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder.set_palette(palette);
+            if let Some(transparent) = transparent {
+                // only the transparent color, which is last, has alpha other than 255
+                let mut alphas = vec![255; transparent as usize];
+                alphas.push(0);
+                encoder.set_trns(alphas);
+            }
+            let mut writer = encoder.write_header().map_err(std::io::Error::other)?;
+            writer
+                .write_image_data(&indices)
+                .map_err(std::io::Error::other)?;
+        }
+        ImageFormat::Gif => {
+            let mut encoder = gif::Encoder::new(&mut bytes, width, height, &palette)
+                .map_err(std::io::Error::other)?;
+            let frame = gif::Frame {
+                width,
+                height,
+                transparent,
+                buffer: indices.into(),
+                ..gif::Frame::default()
+            };
+            encoder.write_frame(&frame).map_err(std::io::Error::other)?;
+        }
+        ImageFormat::Bmp => {
+            let mut encoder = image::codecs::bmp::BmpEncoder::new(&mut bytes);
+            encoder
+                .encode_with_palette(
+                    &indices,
+                    width as u32,
+                    height as u32,
+                    image::ColorType::L8,
+                    Some(colors),
+                )
+                .map_err(std::io::Error::other)?;
+        }
+        _ => {
+            return Err(std::io::Error::other(
+                "format can't be saved with a palette",
+            ))
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COLORS: [[u8; 3]; 3] = [[0, 0, 0], [255, 255, 255], [200, 0, 0]];
+
+    #[test]
+    fn pixels_round_trip() {
+        for (index, color) in COLORS.iter().enumerate() {
+            let rgba = [color[0], color[1], color[2], 255];
+            let stored = encode_pixel(&COLORS, rgba);
+            assert_eq!(pixel_index(stored), Some(index as u8));
+            assert_eq!(decode_pixel(&COLORS, stored), rgba);
+        }
+        // other colors become the nearest one, and transparent stays transparent
+        assert_eq!(encode_pixel(&COLORS, [180, 20, 10, 200]), index_pixel(2));
+        assert_eq!(encode_pixel(&COLORS, [200, 0, 0, 100]), [0; 4]);
+        assert_eq!(decode_pixel(&COLORS, [0; 4]), [0; 4]);
+        // indices past the end of the palette are transparent
+        assert_eq!(decode_pixel(&COLORS, index_pixel(3)), [0; 4]);
+    }
+
+    #[test]
+    fn images_round_trip() {
+        let image = Image {
+            width: 2,
+            height: 2,
+            bytes: [[255; 4], [0; 4], [200, 0, 0, 255], [0, 0, 0, 255]].concat(),
+        };
+        let stored = encode_image(&COLORS, &image);
+        assert_eq!(
+            stored.bytes,
+            [index_pixel(1), [0; 4], index_pixel(2), index_pixel(0)].concat()
+        );
+        assert_eq!(decode_image(&COLORS, &stored).bytes, image.bytes);
+    }
+}
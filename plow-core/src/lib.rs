@@ -0,0 +1,22 @@
+//! Pixel model of plow: images, layers, canvases with undo history, and the operations on them.
+//!
+//! Doesn't depend on any renderer or window, so it can be used headless and tested.
+pub mod animation;
+pub mod buffer;
+pub mod canvas;
+pub mod consts;
+pub mod convolution;
+pub mod file;
+pub mod fill;
+pub mod filter;
+pub mod gradient;
+pub mod indexed;
+pub mod ora;
+pub mod palette;
+pub mod project;
+pub mod spritesheet;
+pub mod stroke;
+pub mod symmetry;
+pub mod upscale;
+
+pub use image::ImageFormat;
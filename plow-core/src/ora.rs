@@ -0,0 +1,348 @@
+use std::{
+    collections::HashMap,
+    io::{Cursor, Write},
+};
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::{buffer::Image, canvas::*, project::*};
+
+/// Mimetype of OpenRaster files, stored as the first file of the archive
+pub const ORA_MIMETYPE: &str = "image/openraster";
+/// File extension of OpenRaster files
+pub const ORA_EXTENSION: &str = "ora";
+/// Max size of the thumbnail stored in OpenRaster files
+const THUMBNAIL_SIZE: u32 = 256;
+
+/// Check if bytes are an OpenRaster file
+pub fn is_ora(bytes: &[u8]) -> bool {
+    has_zip_mimetype(bytes, ORA_MIMETYPE)
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn unescape_xml(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// A single tag of an xml document, like `<layer name="a"/>` or `</stack>`
+struct XmlTag {
+    name: String,
+    attributes: HashMap<String, String>,
+    /// Whether tag is a closing tag, like `</stack>`
+    closing: bool,
+    /// Whether tag closes itself, like `<layer/>`
+    self_closing: bool,
+}
+
+/// Very basic xml parser, that only cares about tags and their attributes (which is all stack.xml needs). Skips comments, declarations and text.
+fn parse_xml_tags(xml: &str) -> Vec<XmlTag> {
+    let mut tags = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        // skip comments
+        if let Some(comment) = rest.strip_prefix("!--") {
+            rest = comment.split_once("-->").map(|(_, r)| r).unwrap_or("");
+            continue;
+        }
+        let Some(end) = rest.find('>') else {
+            break;
+        };
+        let mut inner = &rest[..end];
+        rest = &rest[end + 1..];
+        // skip declarations like <?xml ... ?> and <!DOCTYPE ...>
+        if inner.starts_with('?') || inner.starts_with('!') {
+            continue;
+        }
+        let closing = inner.starts_with('/');
+        if closing {
+            inner = &inner[1..];
+        }
+        let self_closing = inner.ends_with('/');
+        if self_closing {
+            inner = &inner[..inner.len() - 1];
+        }
+        let name_end = inner
+            .find(|c: char| c.is_whitespace())
+            .unwrap_or(inner.len());
+        let name = inner[..name_end].to_string();
+
+        // parse attributes, in the form key="value" or key='value'
+        let mut attributes = HashMap::new();
+        let mut attrs = &inner[name_end..];
+        while let Some(eq) = attrs.find('=') {
+            let key = attrs[..eq].trim().to_string();
+            let value_part = attrs[eq + 1..].trim_start();
+            let Some(quote) = value_part.chars().next() else {
+                break;
+            };
+            if quote != '"' && quote != '\'' {
+                break;
+            }
+            let Some(value_end) = value_part[1..].find(quote) else {
+                break;
+            };
+            attributes.insert(key, unescape_xml(&value_part[1..value_end + 1]));
+            attrs = &value_part[value_end + 2..];
+        }
+        tags.push(XmlTag {
+            name,
+            attributes,
+            closing,
+            self_closing,
+        });
+    }
+    tags
+}
+
+/// Get the OpenRaster composite-op of blend mode
+fn blend_mode_to_composite_op(blend_mode: BlendMode) -> &'static str {
+    match blend_mode {
+        BlendMode::Normal => "svg:src-over",
+        BlendMode::Multiply => "svg:multiply",
+        BlendMode::Screen => "svg:screen",
+        BlendMode::Overlay => "svg:overlay",
+        BlendMode::Add => "svg:plus",
+        BlendMode::Darken => "svg:darken",
+        BlendMode::Lighten => "svg:lighten",
+        BlendMode::Difference => "svg:difference",
+    }
+}
+
+/// Get blend mode of OpenRaster composite-op. Unsupported ops fall back to normal
+fn composite_op_to_blend_mode(composite_op: &str) -> BlendMode {
+    match composite_op {
+        "svg:multiply" => BlendMode::Multiply,
+        "svg:screen" => BlendMode::Screen,
+        "svg:overlay" => BlendMode::Overlay,
+        "svg:plus" => BlendMode::Add,
+        "svg:darken" => BlendMode::Darken,
+        "svg:lighten" => BlendMode::Lighten,
+        "svg:difference" => BlendMode::Difference,
+        _ => BlendMode::Normal,
+    }
+}
+
+/// Multiply alpha of every pixel by opacity
+fn apply_opacity(image: &mut Image, opacity: f32) {
+    if opacity >= 1. {
+        return;
+    }
+    for pixel in image.get_image_data_mut() {
+        pixel[3] = (pixel[3] as f32 * opacity).round() as u8;
+    }
+}
+
+/// Serialize canvas to OpenRaster. Layer name, visibility, opacity and blend mode are kept.
+///
+/// OpenRaster has no animation, so only the current frame is saved
+pub fn ora_to_bytes(canvas: &Canvas) -> Result<Vec<u8>, std::io::Error> {
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    write_zip_mimetype(&mut writer, ORA_MIMETYPE)?;
+
+    // pngs are already compressed, so store them as is
+    let png_options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+
+    // the first layer in the stack is the topmost, same as in [Canvas::layers]
+    let mut stack = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<image version=\"0.0.3\" w=\"{}\" h=\"{}\">\n<stack>\n",
+        canvas.width, canvas.height
+    );
+    for (index, layer) in canvas.layers.iter().enumerate() {
+        let path = format!("data/layer{}.png", index);
+        stack += &format!(
+            "<layer name=\"{}\" src=\"{}\" visibility=\"{}\" opacity=\"{}\" composite-op=\"{}\" x=\"0\" y=\"0\"/>\n",
+            escape_xml(&layer.name),
+            path,
+            if layer.visible { "visible" } else { "hidden" },
+            layer.opacity,
+            blend_mode_to_composite_op(layer.blend_mode),
+        );
+        writer
+            .start_file(path, png_options)
+            .map_err(std::io::Error::other)?;
+        writer.write_all(&encode_png(&canvas.decode_image(&layer.image))?)?;
+    }
+    stack += "</stack>\n</image>\n";
+
+    writer
+        .start_file("stack.xml", SimpleFileOptions::default())
+        .map_err(std::io::Error::other)?;
+    writer.write_all(stack.as_bytes())?;
+
+    // merged image and thumbnail are required by the spec
+    let merged = canvas.to_image();
+    writer
+        .start_file("mergedimage.png", png_options)
+        .map_err(std::io::Error::other)?;
+    writer.write_all(&encode_png(&merged)?)?;
+
+    let merged =
+        image::RgbaImage::from_raw(merged.width as u32, merged.height as u32, merged.bytes)
+            .ok_or_else(|| std::io::Error::other("invalid image"))?;
+    let scale = (THUMBNAIL_SIZE as f32 / merged.width().max(merged.height()) as f32).min(1.);
+    let thumbnail = image::imageops::thumbnail(
+        &merged,
+        ((merged.width() as f32 * scale) as u32).max(1),
+        ((merged.height() as f32 * scale) as u32).max(1),
+    );
+    let thumbnail = Image {
+        width: thumbnail.width() as u16,
+        height: thumbnail.height() as u16,
+        bytes: thumbnail.into_raw(),
+    };
+    writer
+        .start_file("Thumbnails/thumbnail.png", png_options)
+        .map_err(std::io::Error::other)?;
+    writer.write_all(&encode_png(&thumbnail)?)?;
+
+    let cursor = writer.finish().map_err(std::io::Error::other)?;
+    Ok(cursor.into_inner())
+}
+
+fn invalid_stack(reason: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("invalid openraster stack: {}", reason),
+    )
+}
+
+/// Load canvas from bytes of OpenRaster file.
+///
+/// Nested stacks are flattened into a single list of layers. The opacity of stacks is baked into the alpha of their layers, and layer offsets are applied.
+pub fn canvas_from_ora(bytes: &[u8], name: String) -> Result<Canvas, std::io::Error> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes)).map_err(std::io::Error::other)?;
+    let stack = read_zip_file(&mut archive, "stack.xml")?;
+    let stack = String::from_utf8(stack).map_err(|_| invalid_stack("not utf-8"))?;
+
+    let mut size = None;
+    let mut layers: Vec<Layer> = Vec::new();
+    // opacity and visibility of each stack we are inside of, since they apply to all their children
+    let mut stacks: Vec<(f32, bool)> = vec![(1., true)];
+
+    for tag in parse_xml_tags(&stack) {
+        let opacity = tag
+            .attributes
+            .get("opacity")
+            .and_then(|opacity| opacity.parse().ok())
+            .unwrap_or(1.0_f32)
+            .clamp(0., 1.);
+        let visible = tag.attributes.get("visibility").map(|v| v.as_str()) != Some("hidden");
+        let (parent_opacity, parent_visible) = *stacks.last().unwrap();
+
+        match tag.name.as_str() {
+            "image" if !tag.closing => {
+                let width: u16 = tag
+                    .attributes
+                    .get("w")
+                    .and_then(|w| w.parse().ok())
+                    .ok_or_else(|| invalid_stack("missing width"))?;
+                let height: u16 = tag
+                    .attributes
+                    .get("h")
+                    .and_then(|h| h.parse().ok())
+                    .ok_or_else(|| invalid_stack("missing height"))?;
+                // checked before any layer is placed on an image of this size
+                if !validate_canvas_size(width, height) {
+                    return Err(canvas_too_big_error());
+                }
+                size = Some((width, height));
+            }
+            "stack" => {
+                if tag.closing {
+                    if stacks.len() > 1 {
+                        stacks.pop();
+                    }
+                } else if !tag.self_closing {
+                    stacks.push((parent_opacity * opacity, parent_visible && visible));
+                }
+            }
+            "layer" if !tag.closing => {
+                let Some((width, height)) = size else {
+                    return Err(invalid_stack("layer before image size"));
+                };
+                let Some(src) = tag.attributes.get("src") else {
+                    return Err(invalid_stack("layer without src"));
+                };
+                let x = tag
+                    .attributes
+                    .get("x")
+                    .and_then(|x| x.parse().ok())
+                    .unwrap_or(0);
+                let y = tag
+                    .attributes
+                    .get("y")
+                    .and_then(|y| y.parse().ok())
+                    .unwrap_or(0);
+                let image = decode_png(&read_zip_file(&mut archive, src)?)?;
+                let mut image = place_image(&image, width, height, x, y);
+                apply_opacity(&mut image, parent_opacity);
+
+                // keep layer names unique, so layers can be told apart in the layers panel
+                let base_name = tag
+                    .attributes
+                    .get("name")
+                    .cloned()
+                    .unwrap_or_else(|| format!("layer {}", layers.len() + 1));
+                let mut layer_name = base_name.clone();
+                let mut index = 2;
+                while layers.iter().any(|layer| layer.name == layer_name) {
+                    layer_name = format!("{} {}", base_name, index);
+                    index += 1;
+                }
+
+                let mut layer = Layer::new(image, layer_name);
+                layer.visible = parent_visible && visible;
+                layer.opacity = opacity;
+                if let Some(composite_op) = tag.attributes.get("composite-op") {
+                    layer.blend_mode = composite_op_to_blend_mode(composite_op);
+                }
+                layers.push(layer);
+            }
+            _ => {}
+        }
+    }
+
+    if layers.is_empty() {
+        return Err(invalid_stack("no layers"));
+    }
+    Canvas::from_layers(layers, name, FileFormat::OpenRaster)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ora_round_trip_keeps_layers() {
+        let mut canvas = Canvas::new(2, 1, String::from("test")).unwrap();
+        canvas.layers[0].set_pixel(0, 0, [255, 0, 0, 255]);
+        canvas.new_layer();
+        let top = &mut canvas.layers[0];
+        top.name = String::from("top & \"quoted\"");
+        top.opacity = 0.5;
+        top.visible = false;
+        top.set_pixel(1, 0, [0, 0, 255, 255]);
+        let bottom_name = canvas.layers[1].name.clone();
+
+        let loaded = canvas_from_ora(&ora_to_bytes(&canvas).unwrap(), String::new()).unwrap();
+        assert_eq!(loaded.layers.len(), 2);
+        let (top, bottom) = (&loaded.layers[0], &loaded.layers[1]);
+        assert_eq!(top.name, "top & \"quoted\"");
+        assert_eq!((top.opacity, top.visible), (0.5, false));
+        assert_eq!(top.image.get_pixel(1, 0), [0, 0, 255, 255]);
+        assert_eq!(bottom.name, bottom_name);
+        assert_eq!((bottom.opacity, bottom.visible), (1., true));
+        assert_eq!(bottom.image.get_pixel(0, 0), [255, 0, 0, 255]);
+    }
+}
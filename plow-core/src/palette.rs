@@ -0,0 +1,562 @@
+use std::collections::HashSet;
+use strum::{EnumIter, IntoStaticStr};
+
+use crate::{buffer::Image, canvas::Selection, consts::ORDERED_DITHER_SIZE};
+
+/// List of colors to paint with
+#[derive(Clone, PartialEq)]
+pub struct Palette {
+    pub name: String,
+    pub colors: Vec<[u8; 3]>,
+}
+
+/// File format of palettes
+#[derive(Clone, Copy, PartialEq, IntoStaticStr, EnumIter)]
+#[strum(serialize_all = "lowercase")]
+pub enum PaletteFormat {
+    /// GIMP palette
+    Gpl,
+    /// JASC (Paint Shop Pro) palette
+    Pal,
+    /// List of hex colors, like the ones on lospec
+    Hex,
+    /// Adobe Swatch Exchange
+    Ase,
+}
+
+impl PaletteFormat {
+    pub fn extension(&self) -> &'static str {
+        self.into()
+    }
+    /// Get format from file extension, like "gpl" or "ase"
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_lowercase().as_str() {
+            "gpl" => Some(PaletteFormat::Gpl),
+            "pal" => Some(PaletteFormat::Pal),
+            "hex" | "txt" => Some(PaletteFormat::Hex),
+            "ase" => Some(PaletteFormat::Ase),
+            _ => None,
+        }
+    }
+}
+
+fn invalid_palette(reason: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("invalid palette: {}", reason),
+    )
+}
+
+/// Parse hex color like `ff0000` or `#ff0000`. Alpha is ignored if there is any
+pub fn parse_hex_color(text: &str) -> Option<[u8; 3]> {
+    let hex = text.trim().trim_start_matches('#');
+    if (hex.len() != 6 && hex.len() != 8) || !hex.is_ascii() {
+        return None;
+    }
+    let mut color = [0; 3];
+    for (index, channel) in color.iter_mut().enumerate() {
+        *channel = u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16).ok()?;
+    }
+    Some(color)
+}
+
+pub fn to_hex_color(color: [u8; 3]) -> String {
+    format!("{:02x}{:02x}{:02x}", color[0], color[1], color[2])
+}
+
+/// Parse whitespace separated rgb values at the start of a line, like `255 0 0`
+fn parse_rgb_line(line: &str) -> Option<[u8; 3]> {
+    let mut words = line.split_whitespace();
+    let mut color = [0; 3];
+    for channel in color.iter_mut() {
+        *channel = words.next()?.parse().ok()?;
+    }
+    Some(color)
+}
+
+/// Read N bytes at offset of ase data, to be converted from big endian
+fn read_be<const N: usize>(bytes: &[u8], offset: usize) -> Result<[u8; N], std::io::Error> {
+    bytes
+        .get(offset..offset + N)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| invalid_palette("ase file ends early"))
+}
+
+impl Palette {
+    pub fn new(name: String, colors: Vec<[u8; 3]>) -> Self {
+        Palette { name, colors }
+    }
+    /// Load palette from file bytes of format. Name is used if the file doesn't have one
+    pub fn from_bytes(
+        bytes: &[u8],
+        format: PaletteFormat,
+        name: String,
+    ) -> Result<Self, std::io::Error> {
+        if format == PaletteFormat::Ase {
+            return Self::from_ase(bytes, name);
+        }
+        let text = String::from_utf8_lossy(bytes);
+        let mut palette = Palette::new(name, Vec::new());
+        let mut lines = text.lines().map(|line| line.trim());
+        match format {
+            PaletteFormat::Gpl => {
+                if lines.next() != Some("GIMP Palette") {
+                    return Err(invalid_palette("missing gimp palette header"));
+                }
+                for line in lines {
+                    if let Some(name) = line.strip_prefix("Name:") {
+                        palette.name = name.trim().to_string();
+                    } else if line.is_empty() || line.starts_with('#') || line.contains(':') {
+                        // skip comments and other keys, like "Columns:"
+                        continue;
+                    } else {
+                        let color = parse_rgb_line(line).ok_or_else(|| invalid_palette(line))?;
+                        palette.colors.push(color);
+                    }
+                }
+            }
+            PaletteFormat::Pal => {
+                if lines.next() != Some("JASC-PAL") {
+                    return Err(invalid_palette("missing jasc palette header"));
+                }
+                // skip version
+                lines.next();
+                let count: usize = lines
+                    .next()
+                    .and_then(|count| count.parse().ok())
+                    .ok_or_else(|| invalid_palette("missing color count"))?;
+                for line in lines.filter(|line| !line.is_empty()).take(count) {
+                    let color = parse_rgb_line(line).ok_or_else(|| invalid_palette(line))?;
+                    palette.colors.push(color);
+                }
+            }
+            PaletteFormat::Hex => {
+                for line in lines.filter(|line| !line.is_empty()) {
+                    let color = parse_hex_color(line).ok_or_else(|| invalid_palette(line))?;
+                    palette.colors.push(color);
+                }
+            }
+            PaletteFormat::Ase => unreachable!(),
+        }
+        Ok(palette)
+    }
+    /// Load Adobe Swatch Exchange palette. Groups are flattened, and lab colors are skipped
+    fn from_ase(bytes: &[u8], name: String) -> Result<Self, std::io::Error> {
+        if bytes.get(0..4) != Some(b"ASEF") {
+            return Err(invalid_palette("missing ase header"));
+        }
+        let block_count = u32::from_be_bytes(read_be(bytes, 8)?);
+        let mut palette = Palette::new(name, Vec::new());
+        let mut offset = 12;
+        for _ in 0..block_count {
+            let block_type = u16::from_be_bytes(read_be(bytes, offset)?);
+            let length = u32::from_be_bytes(read_be(bytes, offset + 2)?) as usize;
+            let block = bytes
+                .get(offset + 6..offset + 6 + length)
+                .ok_or_else(|| invalid_palette("ase file ends early"))?;
+            offset += 6 + length;
+            // only color entries matter, not group starts and ends
+            if block_type != 0x0001 {
+                continue;
+            }
+            // skip name, which is a length in utf-16 units followed by the text
+            let name_length = u16::from_be_bytes(read_be(block, 0)?) as usize;
+            let model_offset = 2 + name_length * 2;
+            let model: [u8; 4] = read_be(block, model_offset)?;
+            let value = |index: usize| -> Result<f32, std::io::Error> {
+                Ok(f32::from_be_bytes(read_be(
+                    block,
+                    model_offset + 4 + index * 4,
+                )?))
+            };
+            let to_byte = |value: f32| (value.clamp(0., 1.) * 255.).round() as u8;
+            let color = match &model {
+                b"RGB " => [to_byte(value(0)?), to_byte(value(1)?), to_byte(value(2)?)],
+                b"Gray" => [to_byte(value(0)?); 3],
+                b"CMYK" => {
+                    let k = 1. - value(3)?;
+                    [
+                        to_byte((1. - value(0)?) * k),
+                        to_byte((1. - value(1)?) * k),
+                        to_byte((1. - value(2)?) * k),
+                    ]
+                }
+                _ => continue,
+            };
+            palette.colors.push(color);
+        }
+        Ok(palette)
+    }
+    /// Encode palette in format
+    pub fn to_bytes(&self, format: PaletteFormat) -> Vec<u8> {
+        match format {
+            PaletteFormat::Gpl => {
+                let mut text = format!("GIMP Palette\nName: {}\nColumns: 0\n#\n", self.name);
+                for color in &self.colors {
+                    text += &format!(
+                        "{:3} {:3} {:3}\t#{}\n",
+                        color[0],
+                        color[1],
+                        color[2],
+                        to_hex_color(*color)
+                    );
+                }
+                text.into_bytes()
+            }
+            PaletteFormat::Pal => {
+                let mut text = format!("JASC-PAL\r\n0100\r\n{}\r\n", self.colors.len());
+                for color in &self.colors {
+                    text += &format!("{} {} {}\r\n", color[0], color[1], color[2]);
+                }
+                text.into_bytes()
+            }
+            PaletteFormat::Hex => {
+                let mut text = String::new();
+                for color in &self.colors {
+                    text += &to_hex_color(*color);
+                    text += "\n";
+                }
+                text.into_bytes()
+            }
+            PaletteFormat::Ase => {
+                let mut bytes = Vec::new();
+                bytes.extend_from_slice(b"ASEF");
+                // version 1.0
+                bytes.extend_from_slice(&1u16.to_be_bytes());
+                bytes.extend_from_slice(&0u16.to_be_bytes());
+                bytes.extend_from_slice(&(self.colors.len() as u32).to_be_bytes());
+                for color in &self.colors {
+                    // name each color by its hex code, as a null terminated utf-16 string
+                    let name: Vec<u16> = format!("#{}", to_hex_color(*color))
+                        .encode_utf16()
+                        .chain(std::iter::once(0))
+                        .collect();
+                    let length = 2 + name.len() * 2 + 4 + 3 * 4 + 2;
+                    bytes.extend_from_slice(&0x0001u16.to_be_bytes());
+                    bytes.extend_from_slice(&(length as u32).to_be_bytes());
+                    bytes.extend_from_slice(&(name.len() as u16).to_be_bytes());
+                    for unit in name {
+                        bytes.extend_from_slice(&unit.to_be_bytes());
+                    }
+                    bytes.extend_from_slice(b"RGB ");
+                    for channel in color {
+                        bytes.extend_from_slice(&(*channel as f32 / 255.).to_be_bytes());
+                    }
+                    // normal color, rather than global or spot
+                    bytes.extend_from_slice(&2u16.to_be_bytes());
+                }
+                bytes
+            }
+        }
+    }
+}
+
+/// How colors between palette colors are approximated when remapping an image
+#[derive(Clone, Copy, PartialEq, IntoStaticStr, EnumIter)]
+#[strum(serialize_all = "kebab-case")]
+pub enum Dither {
+    /// Each pixel becomes the nearest palette color
+    None,
+    /// The difference to the nearest color is spread to the pixels to the right and below
+    FloydSteinberg,
+    /// Colors are offset by a bayer matrix before finding the nearest one
+    Ordered,
+}
+
+/// Get the colors of the non transparent pixels of image, in the order they first appear
+pub fn unique_colors(image: &Image) -> Vec<[u8; 3]> {
+    let mut found = HashSet::new();
+    let mut colors = Vec::new();
+    for pixel in image.get_image_data() {
+        let color = [pixel[0], pixel[1], pixel[2]];
+        if pixel[3] > 0 && found.insert(color) {
+            colors.push(color);
+        }
+    }
+    colors
+}
+
+/// Reduce colors to at most count colors with median cut. Colors can be repeated, so common colors weigh more
+pub fn median_cut(colors: &[[u8; 3]], count: usize) -> Vec<[u8; 3]> {
+    if colors.is_empty() || count == 0 {
+        return Vec::new();
+    }
+    // get the channel with the widest range of a box, and that range
+    let widest_channel = |colors: &[[u8; 3]]| {
+        (0..3)
+            .map(|channel| {
+                let min = colors.iter().map(|color| color[channel]).min().unwrap_or(0);
+                let max = colors.iter().map(|color| color[channel]).max().unwrap_or(0);
+                (channel, max - min)
+            })
+            .max_by_key(|(_, range)| *range)
+            .unwrap_or((0, 0))
+    };
+    let mut boxes = vec![colors.to_vec()];
+    while boxes.len() < count {
+        // split the box with the widest range at the median of that range
+        let Some((index, (channel, _))) = boxes
+            .iter()
+            .map(|colors| widest_channel(colors))
+            .enumerate()
+            .filter(|(_, (_, range))| *range > 0)
+            .max_by_key(|(_, (_, range))| *range)
+        else {
+            // every box only has one color left
+            break;
+        };
+        let mut colors = boxes.swap_remove(index);
+        colors.sort_unstable_by_key(|color| color[channel]);
+        // split between different values nearest to the median, so repeated colors don't end up in both boxes
+        let median = colors.len() / 2;
+        let split = (1..colors.len())
+            .filter(|index| colors[index - 1][channel] != colors[*index][channel])
+            .min_by_key(|index| index.abs_diff(median))
+            .unwrap_or(median);
+        let upper = colors.split_off(split);
+        boxes.push(colors);
+        boxes.push(upper);
+    }
+    boxes
+        .iter()
+        .map(|colors| {
+            let mut sum = [0; 3];
+            for color in colors {
+                for channel in 0..3 {
+                    sum[channel] += color[channel] as usize;
+                }
+            }
+            sum.map(|sum| ((sum + colors.len() / 2) / colors.len()) as u8)
+        })
+        .collect()
+}
+
+/// Get a palette of the colors of image. If it has more than max_colors colors, they are reduced with median cut. A max_colors of 0 keeps every color
+pub fn extract_colors(image: &Image, max_colors: usize) -> Vec<[u8; 3]> {
+    let colors = unique_colors(image);
+    if max_colors == 0 || colors.len() <= max_colors {
+        return colors;
+    }
+    let pixels: Vec<[u8; 3]> = image
+        .get_image_data()
+        .iter()
+        .filter(|pixel| pixel[3] > 0)
+        .map(|pixel| [pixel[0], pixel[1], pixel[2]])
+        .collect();
+    median_cut(&pixels, max_colors)
+}
+
+/// Get index of the palette color closest to color
+pub fn nearest_color(colors: &[[u8; 3]], color: [f32; 3]) -> usize {
+    let distance = |other: &[u8; 3]| -> f32 {
+        (0..3)
+            .map(|channel| (other[channel] as f32 - color[channel]).powi(2))
+            .sum()
+    };
+    let mut nearest = 0;
+    let mut nearest_distance = f32::MAX;
+    for (index, other) in colors.iter().enumerate() {
+        let distance = distance(other);
+        if distance < nearest_distance {
+            nearest = index;
+            nearest_distance = distance;
+        }
+    }
+    nearest
+}
+
+/// Get the threshold of a bayer matrix of size (a power of 2) at x, y, from 0 to 1. The matrix repeats across the image
+pub fn bayer_threshold(x: usize, y: usize, size: usize) -> f32 {
+    let (mut x, mut y) = (x % size, y % size);
+    let mut value = 0;
+    // the bits of x ^ y and y are interleaved in reverse order
+    for _ in 0..size.trailing_zeros() {
+        value = (value << 2) | (((x ^ y) & 1) << 1) | (y & 1);
+        x >>= 1;
+        y >>= 1;
+    }
+    (value as f32 + 0.5) / (size * size) as f32
+}
+
+/// Replace each non transparent pixel of image with the nearest palette color, keeping its alpha. If selection is Some, only pixels within it are changed
+pub fn remap_image(
+    image: &mut Image,
+    colors: &[[u8; 3]],
+    dither: Dither,
+    selection: Option<&Selection>,
+) {
+    if colors.is_empty() {
+        return;
+    }
+    let width = image.width();
+    let height = image.height();
+    // roughly the distance between colors of an evenly spread palette, which ordered dithering offsets colors by
+    let spread = 255. / (colors.len() as f32).cbrt();
+    // error spread to each pixel by floyd-steinberg dithering
+    let mut errors = vec![[0.; 3]; width * height];
+    let pixels = image.get_image_data_mut();
+    for y in 0..height {
+        for x in 0..width {
+            let index = x + y * width;
+            let pixel = pixels[index];
+            if pixel[3] == 0 || selection.is_some_and(|s| !s.contains(x, y)) {
+                continue;
+            }
+            let mut color = [pixel[0] as f32, pixel[1] as f32, pixel[2] as f32];
+            match dither {
+                Dither::None => {}
+                Dither::FloydSteinberg => {
+                    for channel in 0..3 {
+                        color[channel] += errors[index][channel];
+                    }
+                }
+                Dither::Ordered => {
+                    let offset = (bayer_threshold(x, y, ORDERED_DITHER_SIZE) - 0.5) * spread;
+                    for channel in color.iter_mut() {
+                        *channel += offset;
+                    }
+                }
+            }
+            let new_color = colors[nearest_color(colors, color)];
+            pixels[index] = [new_color[0], new_color[1], new_color[2], pixel[3]];
+
+            if dither == Dither::FloydSteinberg {
+                let neighbors = [(1, 0, 7.), (-1, 1, 3.), (0, 1, 5.), (1, 1, 1.)];
+                for (offset_x, offset_y, weight) in neighbors {
+                    let (Some(x), Some(y)) =
+                        (x.checked_add_signed(offset_x), y.checked_add(offset_y))
+                    else {
+                        continue;
+                    };
+                    if x >= width || y >= height {
+                        continue;
+                    }
+                    for channel in 0..3 {
+                        errors[x + y * width][channel] +=
+                            (color[channel] - new_color[channel] as f32) * weight / 16.;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use strum::IntoEnumIterator;
+
+    fn test_palette() -> Palette {
+        Palette::new(
+            String::from("test"),
+            vec![[255, 0, 0], [0, 128, 255], [17, 34, 51]],
+        )
+    }
+
+    #[test]
+    fn formats_round_trip() {
+        let palette = test_palette();
+        for format in PaletteFormat::iter() {
+            let bytes = palette.to_bytes(format);
+            let loaded = Palette::from_bytes(&bytes, format, String::from("test")).unwrap();
+            assert_eq!(loaded.colors, palette.colors, "{}", format.extension());
+        }
+        // gpl is the only format that stores the name
+        let bytes = palette.to_bytes(PaletteFormat::Gpl);
+        let loaded = Palette::from_bytes(&bytes, PaletteFormat::Gpl, String::new()).unwrap();
+        assert_eq!(loaded.name, "test");
+    }
+
+    #[test]
+    fn ase_skips_groups_and_reads_gray() {
+        let mut bytes = b"ASEF\0\x01\0\0\0\0\0\x03".to_vec();
+        // group start named "g", with a gray color named "a", then the group end
+        bytes.extend_from_slice(b"\xc0\x01\0\0\0\x06\0\x02\0g\0\0");
+        bytes.extend_from_slice(b"\0\x01\0\0\0\x10\0\x02\0a\0\0Gray");
+        bytes.extend_from_slice(&0.5f32.to_be_bytes());
+        bytes.extend_from_slice(b"\0\x02");
+        bytes.extend_from_slice(b"\xc0\x02\0\0\0\0");
+        let palette = Palette::from_bytes(&bytes, PaletteFormat::Ase, String::new()).unwrap();
+        assert_eq!(palette.colors, [[128; 3]]);
+    }
+
+    #[test]
+    fn truncated_ase_is_an_error() {
+        let bytes = test_palette().to_bytes(PaletteFormat::Ase);
+        for length in 0..bytes.len() {
+            let result = Palette::from_bytes(&bytes[..length], PaletteFormat::Ase, String::new());
+            assert!(result.is_err(), "ase cut at {} bytes loaded", length);
+        }
+    }
+
+    #[test]
+    fn malformed_text_palettes_are_errors() {
+        let cases: [(&str, PaletteFormat); 6] = [
+            ("", PaletteFormat::Gpl),
+            ("GIMP\n255 0 0\n", PaletteFormat::Gpl),
+            ("GIMP Palette\n255 0\n", PaletteFormat::Gpl),
+            ("JASC-PAL\n0100\nmany\n", PaletteFormat::Pal),
+            ("ff0000\nff00\n", PaletteFormat::Hex),
+            ("ff0000\nzz0000\n", PaletteFormat::Hex),
+        ];
+        for (text, format) in cases {
+            let result = Palette::from_bytes(text.as_bytes(), format, String::new());
+            assert!(result.is_err(), "{:?} loaded", text);
+        }
+    }
+
+    /// 4x4 image of 16 shades from black to white, with a transparent pixel in the corner
+    fn gradient_image() -> Image {
+        let mut bytes = Vec::new();
+        for index in 0..16 {
+            let shade = index * 17;
+            let alpha = if index == 15 { 0 } else { 255 };
+            bytes.extend_from_slice(&[shade, shade / 2, 255 - shade, alpha]);
+        }
+        Image {
+            width: 4,
+            height: 4,
+            bytes,
+        }
+    }
+
+    #[test]
+    fn median_cut_keeps_few_colors() {
+        let (black, red, blue) = ([0, 0, 0], [255, 0, 0], [0, 0, 255]);
+        // repeated colors weigh more, but don't take the place of other colors
+        let mut colors = median_cut(&[black, black, black, red, blue], 3);
+        colors.sort();
+        assert_eq!(colors, [black, blue, red]);
+        let mut colors = median_cut(&[black, red, black, red], 4);
+        colors.sort();
+        assert_eq!(colors, [black, red]);
+    }
+
+    #[test]
+    fn extract_colors_respects_max_colors() {
+        let image = gradient_image();
+        assert_eq!(extract_colors(&image, 0).len(), 15);
+        assert_eq!(extract_colors(&image, 15), unique_colors(&image));
+        for max_colors in [1, 2, 5, 14] {
+            assert_eq!(extract_colors(&image, max_colors).len(), max_colors);
+        }
+    }
+
+    #[test]
+    fn remap_only_uses_palette_colors() {
+        let palette = [[0, 0, 0], [255, 128, 0], [0, 0, 255]];
+        for dither in Dither::iter() {
+            let mut image = gradient_image();
+            remap_image(&mut image, &palette, dither, None);
+            for (index, pixel) in image.get_image_data().iter().enumerate() {
+                if index == 15 {
+                    // transparent pixels are left as they were
+                    assert_eq!(*pixel, gradient_image().get_image_data()[15]);
+                } else {
+                    assert!(palette.contains(&[pixel[0], pixel[1], pixel[2]]));
+                    assert_eq!(pixel[3], 255);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,380 @@
+use image::ImageFormat;
+use std::io::{Cursor, Read, Write};
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::{
+    buffer::Image,
+    canvas::*,
+    indexed::INDEXED_MAX_COLORS,
+    palette::{parse_hex_color, to_hex_color, Palette},
+};
+
+/// Mimetype stored as the first file of a plow project, used to identify project files
+pub const PROJECT_MIMETYPE: &str = "image/x-plow";
+/// File extension of plow projects
+pub const PROJECT_EXTENSION: &str = "plow";
+/// Version of the project format. Should be increased whenever the manifest changes in an incompatible way
+const PROJECT_VERSION: u32 = 2;
+const MANIFEST_PATH: &str = "project.txt";
+
+/// Encode image as png bytes
+pub fn encode_png(image: &Image) -> Result<Vec<u8>, std::io::Error> {
+    let mut buffered_writer = Cursor::new(Vec::new());
+    image::write_buffer_with_format(
+        &mut buffered_writer,
+        &image.bytes,
+        image.width as u32,
+        image.height as u32,
+        image::ColorType::Rgba8,
+        ImageFormat::Png,
+    )
+    .map_err(std::io::Error::other)?;
+    Ok(buffered_writer.into_inner())
+}
+
+/// Decode png bytes to image
+pub fn decode_png(bytes: &[u8]) -> Result<Image, std::io::Error> {
+    let dynamic_image = image::load_from_memory_with_format(bytes, ImageFormat::Png)
+        .map_err(std::io::Error::other)?;
+    // sizes that don't fit would be cut off, leaving the bytes for a different size
+    let too_big = |_| std::io::Error::new(std::io::ErrorKind::InvalidData, "image too big");
+    let width = u16::try_from(dynamic_image.width()).map_err(too_big)?;
+    let height = u16::try_from(dynamic_image.height()).map_err(too_big)?;
+    Ok(Image {
+        width,
+        height,
+        bytes: dynamic_image.to_rgba8().into_raw(),
+    })
+}
+
+/// Read entire file from zip archive
+pub fn read_zip_file(
+    archive: &mut ZipArchive<Cursor<&[u8]>>,
+    path: &str,
+) -> Result<Vec<u8>, std::io::Error> {
+    let mut file = archive.by_name(path).map_err(std::io::Error::other)?;
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Check if bytes are a zip archive with the given mimetype as its first file (like OpenRaster and plow projects)
+pub fn has_zip_mimetype(bytes: &[u8], mimetype: &str) -> bool {
+    let Ok(mut archive) = ZipArchive::new(Cursor::new(bytes)) else {
+        return false;
+    };
+    let Ok(mut file) = archive.by_index(0) else {
+        return false;
+    };
+    if file.name() != "mimetype" {
+        return false;
+    }
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).is_ok() && contents.trim() == mimetype
+}
+
+/// Write the mimetype file, which has to be the first file of the archive and uncompressed
+pub fn write_zip_mimetype(
+    writer: &mut ZipWriter<Cursor<Vec<u8>>>,
+    mimetype: &str,
+) -> Result<(), std::io::Error> {
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    writer
+        .start_file("mimetype", options)
+        .map_err(std::io::Error::other)?;
+    writer.write_all(mimetype.as_bytes())
+}
+
+/// Check if bytes are a plow project
+pub fn is_project(bytes: &[u8]) -> bool {
+    has_zip_mimetype(bytes, PROJECT_MIMETYPE)
+}
+
+/// Serialize canvas to the plow project format.
+///
+/// A project is a zip archive containing a `mimetype` file, a `project.txt` manifest, and each layer as a png in `layers/`.
+///
+/// The manifest is line based, where the first word of each line is the key:
+/// ```text
+/// plow 2
+/// size <width> <height>
+/// camera <grid size> <x> <y>
+/// current_layer <index>
+/// current_frame <index>
+/// frame <duration in milliseconds>
+/// palette <name>
+/// palette_color <hex color>
+/// layer <visible (0 or 1)> <name>
+/// layer_opacity <opacity>
+/// layer_blend <blend mode>
+/// ```
+/// Frame lines are in the same order as [Canvas::frames], and come before layer lines. Projects without them have a single frame.
+/// `palette` is only there in indexed color mode, followed by a `palette_color` line for each color of [Canvas::palette]. Layer pngs then store indices, see [crate::indexed].
+/// Layer lines are in the same order as [Canvas::layers], and layer `n` is stored as `layers/n.png` for the first frame, and as `layers/n_f.png` for frame `f`.
+/// `layer_opacity` and `layer_blend` apply to the layer line before them, and are optional
+pub fn project_to_bytes(canvas: &Canvas) -> Result<Vec<u8>, std::io::Error> {
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    write_zip_mimetype(&mut writer, PROJECT_MIMETYPE)?;
+
+    let mut manifest = format!("plow {}\n", PROJECT_VERSION);
+    manifest += &format!("size {} {}\n", canvas.width, canvas.height);
+    manifest += &format!(
+        "camera {} {} {}\n",
+        canvas.camera_grid_size, canvas.camera_x, canvas.camera_y
+    );
+    manifest += &format!("current_layer {}\n", canvas.current_layer);
+    manifest += &format!("current_frame {}\n", canvas.current_frame);
+    for frame in &canvas.frames {
+        manifest += &format!("frame {}\n", frame.duration);
+    }
+    if let Some(palette) = &canvas.palette {
+        manifest += &format!("palette {}\n", palette.name);
+        for color in &palette.colors {
+            manifest += &format!("palette_color {}\n", to_hex_color(*color));
+        }
+    }
+    for layer in &canvas.layers {
+        manifest += &format!("layer {} {}\n", layer.visible as u8, layer.name);
+        let blend_mode: &str = layer.blend_mode.into();
+        manifest += &format!("layer_opacity {}\n", layer.opacity);
+        manifest += &format!("layer_blend {}\n", blend_mode);
+    }
+    let options = SimpleFileOptions::default();
+    writer
+        .start_file(MANIFEST_PATH, options)
+        .map_err(std::io::Error::other)?;
+    writer.write_all(manifest.as_bytes())?;
+
+    // pngs are already compressed, so store them as is
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    for index in 0..canvas.layers.len() {
+        for frame in 0..canvas.frames.len() {
+            writer
+                .start_file(layer_path(index, frame), options)
+                .map_err(std::io::Error::other)?;
+            writer.write_all(&encode_png(canvas.frame_image(index, frame))?)?;
+        }
+    }
+
+    let cursor = writer.finish().map_err(std::io::Error::other)?;
+    Ok(cursor.into_inner())
+}
+
+/// Path of the image of layer in frame
+fn layer_path(layer: usize, frame: usize) -> String {
+    if frame == 0 {
+        format!("layers/{}.png", layer)
+    } else {
+        format!("layers/{}_{}.png", layer, frame)
+    }
+}
+
+fn invalid_manifest(reason: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("invalid project manifest: {}", reason),
+    )
+}
+
+/// Parse a number from the next word of a manifest line
+fn parse_next<T: std::str::FromStr>(
+    words: &mut std::str::SplitWhitespace,
+    key: &str,
+) -> Result<T, std::io::Error> {
+    words
+        .next()
+        .and_then(|word| word.parse().ok())
+        .ok_or_else(|| invalid_manifest(key))
+}
+
+/// Load canvas from bytes of plow project. See [project_to_bytes] for the format.
+pub fn canvas_from_project(bytes: &[u8], name: String) -> Result<Canvas, std::io::Error> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes)).map_err(std::io::Error::other)?;
+    let manifest = read_zip_file(&mut archive, MANIFEST_PATH)?;
+    let manifest = String::from_utf8(manifest).map_err(|_| invalid_manifest("not utf-8"))?;
+
+    let mut size = None;
+    let mut camera = None;
+    let mut current_layer = 0;
+    let mut current_frame = 0;
+    let mut frames = Vec::new();
+    let mut palette: Option<Palette> = None;
+    let mut layers = Vec::new();
+
+    for line in manifest.lines() {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("plow") => {
+                let version: u32 = parse_next(&mut words, "plow")?;
+                if version > PROJECT_VERSION {
+                    return Err(invalid_manifest("project was made with a newer version"));
+                }
+            }
+            Some("size") => {
+                let width: u16 = parse_next(&mut words, "size")?;
+                let height: u16 = parse_next(&mut words, "size")?;
+                size = Some((width, height));
+            }
+            Some("camera") => {
+                let grid_size: f32 = parse_next(&mut words, "camera")?;
+                let x: f32 = parse_next(&mut words, "camera")?;
+                let y: f32 = parse_next(&mut words, "camera")?;
+                camera = Some((grid_size, x, y));
+            }
+            Some("current_layer") => {
+                current_layer = parse_next(&mut words, "current_layer")?;
+            }
+            Some("current_frame") => {
+                current_frame = parse_next(&mut words, "current_frame")?;
+            }
+            Some("frame") => {
+                let duration: u32 = parse_next(&mut words, "frame")?;
+                frames.push(Frame { duration });
+            }
+            Some("palette") => {
+                // the name is the rest of the line, and may contain spaces or be empty
+                let name = line.split_once(' ').map(|(_, name)| name).unwrap_or("");
+                palette = Some(Palette::new(name.to_string(), Vec::new()));
+            }
+            Some("palette_color") => {
+                let Some(palette) = &mut palette else {
+                    return Err(invalid_manifest("palette_color before palette"));
+                };
+                let color = words
+                    .next()
+                    .and_then(parse_hex_color)
+                    .ok_or_else(|| invalid_manifest("palette_color"))?;
+                // pixels store indices as a single byte
+                if palette.colors.len() >= INDEXED_MAX_COLORS {
+                    return Err(invalid_manifest("too many palette colors"));
+                }
+                palette.colors.push(color);
+            }
+            Some("layer") => {
+                // the name is the rest of the line after visibility, and may contain spaces
+                let Some((_, rest)) = line.split_once(' ') else {
+                    return Err(invalid_manifest("layer"));
+                };
+                let Some((visible, name)) = rest.split_once(' ') else {
+                    return Err(invalid_manifest("layer"));
+                };
+                let image =
+                    decode_png(&read_zip_file(&mut archive, &layer_path(layers.len(), 0))?)?;
+                let mut layer = Layer::new(image, name.to_string());
+                for frame in 1..frames.len() {
+                    let path = layer_path(layers.len(), frame);
+                    layer
+                        .frames
+                        .push(decode_png(&read_zip_file(&mut archive, &path)?)?);
+                }
+                layer.visible = visible != "0";
+                layers.push(layer);
+            }
+            Some("layer_opacity") => {
+                let opacity: f32 = parse_next(&mut words, "layer_opacity")?;
+                let Some(layer) = layers.last_mut() else {
+                    return Err(invalid_manifest("layer_opacity before layer"));
+                };
+                layer.opacity = opacity.clamp(0., 1.);
+            }
+            Some("layer_blend") => {
+                let Some(layer) = layers.last_mut() else {
+                    return Err(invalid_manifest("layer_blend before layer"));
+                };
+                // unknown blend modes fall back to normal
+                layer.blend_mode = words
+                    .next()
+                    .and_then(|mode| mode.parse().ok())
+                    .unwrap_or(BlendMode::Normal);
+            }
+            // ignore unknown keys and empty lines
+            _ => {}
+        }
+    }
+
+    let Some((width, height)) = size else {
+        return Err(invalid_manifest("missing size"));
+    };
+    if layers.is_empty() {
+        return Err(invalid_manifest("no layers"));
+    }
+    for layer in &layers {
+        let frames = layer.frames.iter().skip(1);
+        for image in std::iter::once(&layer.image).chain(frames) {
+            if image.width() != width as usize || image.height() != height as usize {
+                return Err(invalid_manifest("layer size doesn't match canvas size"));
+            }
+        }
+    }
+
+    // every pixel would be transparent with an empty palette
+    if palette
+        .as_ref()
+        .is_some_and(|palette| palette.colors.is_empty())
+    {
+        return Err(invalid_manifest("palette without colors"));
+    }
+
+    let mut canvas = Canvas::from_layers(layers, name, FileFormat::Project)?;
+    canvas.current_layer = current_layer.min(canvas.layers.len() - 1);
+    canvas.palette = palette;
+    if !frames.is_empty() {
+        canvas.frames = frames;
+        canvas.set_frame(current_frame.min(canvas.frames.len() - 1));
+    }
+    if let Some((camera_grid_size, camera_x, camera_y)) = camera {
+        canvas.camera_grid_size = camera_grid_size;
+        canvas.camera_x = camera_x;
+        canvas.camera_y = camera_y;
+    }
+    Ok(canvas)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encode png of any size, even ones images can't have
+    fn png_of_size(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = Cursor::new(Vec::new());
+        image::write_buffer_with_format(
+            &mut bytes,
+            &vec![0; width as usize * height as usize * 4],
+            width,
+            height,
+            image::ColorType::Rgba8,
+            ImageFormat::Png,
+        )
+        .unwrap();
+        bytes.into_inner()
+    }
+
+    #[test]
+    fn decode_png_rejects_too_wide_images() {
+        let image = decode_png(&png_of_size(3, 2)).unwrap();
+        assert_eq!((image.width, image.height, image.bytes.len()), (3, 2, 24));
+        let Err(err) = decode_png(&png_of_size(u16::MAX as u32 + 2, 1)) else {
+            panic!("image wider than u16::MAX decoded");
+        };
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    /// Save and load a project whose palette has colors, skipping the checks of [Canvas::set_color_mode]
+    fn load_with_palette(colors: Vec<[u8; 3]>) -> Result<Canvas, std::io::Error> {
+        let image = gen_empty_image(2, 2);
+        let mut canvas = Canvas::from_image(image, String::new(), ImageFormat::Png)?;
+        canvas.palette = Some(Palette::new(String::from("test"), colors));
+        canvas_from_project(&project_to_bytes(&canvas)?, String::new())
+    }
+
+    #[test]
+    fn palettes_must_fit_indices() {
+        let loaded = load_with_palette(vec![[1, 2, 3]; INDEXED_MAX_COLORS]).ok();
+        let colors = loaded
+            .and_then(|canvas| canvas.palette)
+            .map(|p| p.colors.len());
+        assert_eq!(colors, Some(INDEXED_MAX_COLORS));
+        assert!(load_with_palette(vec![[1, 2, 3]; INDEXED_MAX_COLORS + 1]).is_err());
+        assert!(load_with_palette(Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,229 @@
+use crate::{
+    animation::AnimationSource,
+    buffer::{Image, Rect},
+    canvas::*,
+    file::image_from_bytes,
+};
+
+/// How sprites are laid out in a spritesheet
+#[derive(Clone, Copy, PartialEq)]
+pub struct SpritesheetOptions {
+    /// Sprites per row, or 0 to make the sheet as square as possible
+    pub columns: u16,
+    /// Space between sprites, in pixels
+    pub padding: u16,
+    /// Space around the edges of the sheet, in pixels
+    pub margin: u16,
+}
+
+/// Sprite placed in a spritesheet
+pub struct Sprite {
+    pub name: String,
+    pub rect: Rect,
+    /// Duration in milliseconds
+    pub duration: u32,
+}
+
+/// Spritesheet image, and where each sprite is in it
+pub struct Spritesheet {
+    pub image: Image,
+    pub sprites: Vec<Sprite>,
+}
+
+/// Get number of columns and rows of a sheet with count sprites
+fn grid_size(count: usize, columns: u16) -> (usize, usize) {
+    let columns = match columns {
+        0 => (count as f32).sqrt().ceil() as usize,
+        columns => columns as usize,
+    }
+    .clamp(1, count.max(1));
+    (columns, count.div_ceil(columns))
+}
+
+/// Pack frames or layers of canvas into a spritesheet, from left to right and top to bottom. Layers are packed from bottom to top
+pub fn pack_spritesheet(
+    canvas: &Canvas,
+    source: AnimationSource,
+    options: &SpritesheetOptions,
+) -> Result<Spritesheet, std::io::Error> {
+    let sprites: Vec<(String, Image, u32)> = match source {
+        AnimationSource::Frames => canvas
+            .frames
+            .iter()
+            .enumerate()
+            .map(|(index, frame)| {
+                let name = format!("{} {}", canvas.name, index);
+                (name, canvas.frame_to_image(index), frame.duration)
+            })
+            .collect(),
+        AnimationSource::Layers => (0..canvas.layers.len())
+            .rev()
+            .filter(|index| canvas.layers[*index].visible)
+            .map(|index| {
+                let name = canvas.layers[index].name.clone();
+                let duration = canvas.frames[canvas.current_frame].duration;
+                (name, canvas.layer_to_image(index), duration)
+            })
+            .collect(),
+    };
+    if sprites.is_empty() {
+        return Err(std::io::Error::other("spritesheet has no sprites"));
+    }
+
+    let (columns, rows) = grid_size(sprites.len(), options.columns);
+    let (sprite_width, sprite_height) = (canvas.width as usize, canvas.height as usize);
+    let (padding, margin) = (options.padding as usize, options.margin as usize);
+    let sheet_width = margin * 2 + columns * sprite_width + (columns - 1) * padding;
+    let sheet_height = margin * 2 + rows * sprite_height + (rows - 1) * padding;
+    if sheet_width > u16::MAX as usize
+        || sheet_height > u16::MAX as usize
+        || !validate_canvas_size(sheet_width as u16, sheet_height as u16)
+    {
+        return Err(canvas_too_big_error());
+    }
+
+    let mut image = gen_empty_image(sheet_width as u16, sheet_height as u16);
+    let mut placed = Vec::new();
+    for (index, (name, mut sprite, duration)) in sprites.into_iter().enumerate() {
+        let rect = Rect::new(
+            (margin + (index % columns) * (sprite_width + padding)) as f32,
+            (margin + (index / columns) * (sprite_height + padding)) as f32,
+            sprite_width as f32,
+            sprite_height as f32,
+        );
+        update_image_region(&mut image, &rect, &mut sprite, false, false);
+        placed.push(Sprite {
+            name,
+            rect,
+            duration,
+        });
+    }
+    Ok(Spritesheet {
+        image,
+        sprites: placed,
+    })
+}
+
+fn escape_json(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '"' => escaped += "\\\"",
+            '\\' => escaped += "\\\\",
+            '\n' => escaped += "\\n",
+            c if (c as u32) < 0x20 => escaped += &format!("\\u{:04x}", c as u32),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+impl Spritesheet {
+    /// Describe where each sprite is as json, to be saved next to the sheet image. image_name is the file name of the sheet image.
+    ///
+    /// Uses the common array layout of spritesheet tools:
+    /// ```text
+    /// {
+    ///   "frames": [
+    ///     { "filename": "<name>", "frame": { "x": 0, "y": 0, "w": 16, "h": 16 }, "duration": 100 }
+    ///   ],
+    ///   "meta": { "image": "<image name>", "size": { "w": 32, "h": 16 } }
+    /// }
+    /// ```
+    pub fn to_json(&self, image_name: &str) -> String {
+        let frames: Vec<String> = self
+            .sprites
+            .iter()
+            .map(|sprite| {
+                format!(
+                    "    {{ \"filename\": \"{}\", \"frame\": {{ \"x\": {}, \"y\": {}, \"w\": {}, \"h\": {} }}, \"duration\": {} }}",
+                    escape_json(&sprite.name),
+                    sprite.rect.x,
+                    sprite.rect.y,
+                    sprite.rect.w,
+                    sprite.rect.h,
+                    sprite.duration
+                )
+            })
+            .collect();
+        format!(
+            "{{\n  \"frames\": [\n{}\n  ],\n  \"meta\": {{ \"image\": \"{}\", \"size\": {{ \"w\": {}, \"h\": {} }} }}\n}}\n",
+            frames.join(",\n"),
+            escape_json(image_name),
+            self.image.width,
+            self.image.height
+        )
+    }
+}
+
+/// How a spritesheet is sliced into sprites
+#[derive(Clone, Copy, PartialEq)]
+pub struct SliceOptions {
+    pub cell_width: u16,
+    pub cell_height: u16,
+    /// Space between cells, in pixels
+    pub padding: u16,
+    /// Space around the edges of the sheet, in pixels
+    pub margin: u16,
+    /// Whether fully transparent cells are left out
+    pub skip_empty: bool,
+}
+
+/// Slice image on a grid into cells, from left to right and top to bottom. Cells that don't fit entirely are left out
+pub fn slice_image(image: &Image, options: &SliceOptions) -> Vec<Image> {
+    let (cell_width, cell_height) = (options.cell_width as usize, options.cell_height as usize);
+    let (padding, margin) = (options.padding as usize, options.margin as usize);
+    if cell_width == 0 || cell_height == 0 {
+        return Vec::new();
+    }
+    // each cell but the last is followed by padding
+    let cells =
+        |size: usize, cell: usize| (size.saturating_sub(margin * 2) + padding) / (cell + padding);
+    let columns = cells(image.width(), cell_width);
+    let rows = cells(image.height(), cell_height);
+
+    let mut sprites = Vec::new();
+    for row in 0..rows {
+        for column in 0..columns {
+            let rect = Rect::new(
+                (margin + column * (cell_width + padding)) as f32,
+                (margin + row * (cell_height + padding)) as f32,
+                cell_width as f32,
+                cell_height as f32,
+            );
+            let sprite = image.sub_image(rect);
+            if options.skip_empty && sprite.get_image_data().iter().all(|pixel| pixel[3] == 0) {
+                continue;
+            }
+            sprites.push(sprite);
+        }
+    }
+    sprites
+}
+
+/// Load canvas from bytes of a spritesheet image, with each cell as a frame, or as a layer from bottom to top
+pub fn canvas_from_spritesheet(
+    bytes: &[u8],
+    name: String,
+    target: AnimationSource,
+    options: &SliceOptions,
+) -> Result<Canvas, std::io::Error> {
+    let (image, _) = image_from_bytes(bytes).map_err(std::io::Error::other)?;
+    let sprites = slice_image(&image, options);
+    if sprites.is_empty() {
+        return Err(std::io::Error::other("no cells fit in spritesheet"));
+    }
+    match target {
+        // the result has several frames or layers, which only projects keep
+        AnimationSource::Frames => Canvas::from_frames(sprites, name, FileFormat::Project),
+        AnimationSource::Layers => {
+            let layers = sprites
+                .into_iter()
+                .enumerate()
+                .map(|(index, sprite)| Layer::new(sprite, format!("layer {}", index + 1)))
+                .rev()
+                .collect();
+            Canvas::from_layers(layers, name, FileFormat::Project)
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use line_drawing::Bresenham;
+use strum::{EnumIter, IntoStaticStr};
+
+use crate::{consts::DIRECTIONS, palette::bayer_threshold};
+
+/// Largest size of custom stroke patterns
+pub const MAX_PATTERN_SIZE: usize = 8;
+
+/// Which pixels a stroke paints, for dithering by hand
+#[derive(Clone, Copy, PartialEq, IntoStaticStr, EnumIter)]
+pub enum StrokePattern {
+    /// Every pixel
+    #[strum(serialize = "solid")]
+    Solid,
+    #[strum(serialize = "bayer 2×2")]
+    Bayer2,
+    #[strum(serialize = "bayer 4×4")]
+    Bayer4,
+    #[strum(serialize = "bayer 8×8")]
+    Bayer8,
+    /// Pixels of [Stroke::custom_pattern]
+    #[strum(serialize = "custom")]
+    Custom,
+}
+
+impl StrokePattern {
+    /// Size of the bayer matrix of the pattern, if it uses one
+    pub fn bayer_size(&self) -> Option<usize> {
+        match self {
+            StrokePattern::Bayer2 => Some(2),
+            StrokePattern::Bayer4 => Some(4),
+            StrokePattern::Bayer8 => Some(8),
+            StrokePattern::Solid | StrokePattern::Custom => None,
+        }
+    }
+}
+
+pub struct Stroke {
+    pub size: u16,
+    pub pixels: Vec<Vec<bool>>,
+    pub pixels_offset: i16,
+    pub borders: Vec<((usize, usize), (usize, usize))>,
+    /// Patterns repeat across the canvas from its top left corner, so strokes next to each other line up
+    pub pattern: StrokePattern,
+    /// Share of pixels bayer patterns paint, from 0 to 1
+    pub pattern_density: f32,
+    /// Pixels painted by the custom pattern by row, of which the top left custom_pattern_size × custom_pattern_size are used
+    pub custom_pattern: [[bool; MAX_PATTERN_SIZE]; MAX_PATTERN_SIZE],
+    pub custom_pattern_size: usize,
+}
+impl Stroke {
+    pub fn new(size: u16) -> Self {
+        let pixels = Self::generate_pixels(size);
+        let borders = Self::generate_borders(&pixels);
+        // a checkerboard
+        let mut custom_pattern = [[false; MAX_PATTERN_SIZE]; MAX_PATTERN_SIZE];
+        for (y, row) in custom_pattern.iter_mut().enumerate() {
+            for (x, pixel) in row.iter_mut().enumerate() {
+                *pixel = (x + y) % 2 == 0;
+            }
+        }
+        Stroke {
+            size,
+            pixels,
+            pixels_offset: 0,
+            borders,
+            pattern: StrokePattern::Solid,
+            pattern_density: 0.5,
+            custom_pattern,
+            custom_pattern_size: 2,
+        }
+    }
+    /// Whether the pattern paints the pixel at x, y of the canvas
+    pub fn paints(&self, x: usize, y: usize) -> bool {
+        match self.pattern {
+            StrokePattern::Solid => true,
+            StrokePattern::Custom => {
+                let size = self.custom_pattern_size;
+                self.custom_pattern[y % size][x % size]
+            }
+            pattern => {
+                let size = pattern.bayer_size().unwrap();
+                bayer_threshold(x, y, size) < self.pattern_density
+            }
+        }
+    }
+    pub fn update(&mut self) {
+        self.pixels = Self::generate_pixels(self.size);
+        self.borders = Self::generate_borders(&self.pixels);
+        self.pixels_offset = -(self.size as i16) / 2;
+    }
+    fn generate_pixels(size: u16) -> Vec<Vec<bool>> {
+        if size > 1 {
+            let half_brush_size = size as f32 / 2.;
+            let brush_size = size as i32;
+            let mut new = Vec::new();
+            for x in -brush_size / 2..brush_size / 2 + 1 {
+                new.push(Vec::new());
+                for y in -brush_size / 2..brush_size / 2 + 1 {
+                    if ((x * x + y * y) as f32) < half_brush_size * half_brush_size + 0.5 {
+                        new.last_mut().unwrap().push(true);
+                    } else {
+                        new.last_mut().unwrap().push(false);
+                    }
+                }
+            }
+            new
+        } else {
+            vec![vec![true]]
+        }
+    }
+    fn generate_borders(pixels: &[Vec<bool>]) -> Vec<((usize, usize), (usize, usize))> {
+        let mut new = Vec::new();
+        for (x, column) in pixels.iter().enumerate() {
+            for (y, value) in column.iter().enumerate() {
+                if !value {
+                    continue;
+                }
+                let mut neighbour_above = false;
+                let mut neighbour_below = false;
+                let mut neighbour_right = false;
+                let mut neighbour_left = false;
+                let mut neighbour_bools = [
+                    &mut neighbour_above,
+                    &mut neighbour_below,
+                    &mut neighbour_right,
+                    &mut neighbour_left,
+                ];
+                for (dir, bool) in DIRECTIONS.iter().zip(neighbour_bools.iter_mut()) {
+                    let Ok(x) = (x as isize + dir[0]).try_into() else {
+                        continue;
+                    };
+                    let Ok(y) = (y as isize + dir[1]).try_into() else {
+                        continue;
+                    };
+                    if let Some(column) = pixels.get::<usize>(x) {
+                        if let Some(value) = column.get::<usize>(y) {
+                            if *value {
+                                **bool = true;
+                            }
+                        }
+                    }
+                }
+                if !neighbour_above {
+                    new.push(((x, y + 1), (x + 1, y + 1)));
+                }
+                if !neighbour_below {
+                    new.push(((x, y), (x + 1, y)));
+                }
+                if !neighbour_left {
+                    new.push(((x, y), (x, y + 1)));
+                }
+                if !neighbour_right {
+                    new.push(((x + 1, y), (x + 1, y + 1)));
+                }
+            }
+        }
+        new
+    }
+}
+
+/// Pixels a 1 pixel wide stroke went through in order, for pixel perfect mode, which removes the doubled corners lines leave when they turn
+pub struct StrokePath {
+    points: Vec<(i16, i16)>,
+}
+impl StrokePath {
+    pub fn new() -> Self {
+        StrokePath { points: Vec::new() }
+    }
+    /// Forget the path, like when a new stroke starts
+    pub fn clear(&mut self) {
+        self.points.clear();
+    }
+    /// Add the line from x1, y1 to x2, y2 to the path, and get the corner pixels that should no longer be drawn
+    pub fn extend(&mut self, x1: i16, y1: i16, x2: i16, y2: i16) -> Vec<(i16, i16)> {
+        let mut removed = Vec::new();
+        for point in Bresenham::new((x1, y1), (x2, y2)) {
+            if self.points.last() == Some(&point) {
+                continue;
+            }
+            self.points.push(point);
+            let [.., a, b, c] = self.points[..] else {
+                continue;
+            };
+            // b is a corner if it's straight next to both a and c, which are diagonal to each other
+            let is_corner = (a.0 == b.0 || a.1 == b.1)
+                && (c.0 == b.0 || c.1 == b.1)
+                && (a.0 - c.0).abs() == 1
+                && (a.1 - c.1).abs() == 1;
+            if is_corner {
+                self.points.remove(self.points.len() - 2);
+                // pixels the path crosses again stay drawn
+                if !self.points.contains(&b) {
+                    removed.push(b);
+                }
+            }
+        }
+        removed
+    }
+}
+
+impl Default for StrokePath {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn painted(stroke: &Stroke, size: usize) -> Vec<Vec<bool>> {
+        (0..size)
+            .map(|y| (0..size).map(|x| stroke.paints(x, y)).collect())
+            .collect()
+    }
+
+    #[test]
+    fn solid_paints_everything() {
+        let stroke = Stroke::new(1);
+        assert!(painted(&stroke, 8).iter().flatten().all(|&pixel| pixel));
+    }
+
+    #[test]
+    fn custom_pattern_repeats() {
+        let mut stroke = Stroke::new(1);
+        stroke.pattern = StrokePattern::Custom;
+        for y in 0..5 {
+            for x in 0..5 {
+                assert_eq!(stroke.paints(x, y), (x + y) % 2 == 0);
+            }
+        }
+        stroke.custom_pattern = [[false; MAX_PATTERN_SIZE]; MAX_PATTERN_SIZE];
+        stroke.custom_pattern[1][2] = true;
+        stroke.custom_pattern_size = 3;
+        for y in 0..9 {
+            for x in 0..9 {
+                assert_eq!(stroke.paints(x, y), x % 3 == 2 && y % 3 == 1);
+            }
+        }
+    }
+
+    #[test]
+    fn bayer_patterns_follow_density() {
+        let mut stroke = Stroke::new(1);
+        for pattern in [
+            StrokePattern::Bayer2,
+            StrokePattern::Bayer4,
+            StrokePattern::Bayer8,
+        ] {
+            stroke.pattern = pattern;
+            let size = pattern.bayer_size().unwrap();
+            for (density, count) in [(0., 0), (0.25, size * size / 4), (1., size * size)] {
+                stroke.pattern_density = density;
+                let pixels = painted(&stroke, size);
+                assert_eq!(
+                    pixels.iter().flatten().filter(|&&pixel| pixel).count(),
+                    count
+                );
+                // the pattern repeats
+                for (y, row) in pixels.iter().enumerate() {
+                    for (x, &pixel) in row.iter().enumerate() {
+                        assert_eq!(stroke.paints(x + size, y + size), pixel);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn path_removes_corners() {
+        let mut path = StrokePath::new();
+        // a staircase leaves a corner at each step
+        assert_eq!(path.extend(0, 0, 1, 0), []);
+        assert_eq!(path.extend(1, 0, 1, 1), [(1, 0)]);
+        assert_eq!(path.extend(1, 1, 2, 1), []);
+        assert_eq!(path.extend(2, 1, 2, 2), [(2, 1)]);
+    }
+
+    #[test]
+    fn path_keeps_lines() {
+        let mut path = StrokePath::new();
+        assert_eq!(path.extend(0, 0, 5, 0), []);
+        assert_eq!(path.extend(5, 0, 8, 3), []);
+        path.clear();
+        // after clearing, the turn from the old path is not a corner
+        assert_eq!(path.extend(8, 4, 9, 4), []);
+    }
+}
@@ -0,0 +1,182 @@
+//! Symmetry, which repeats everything drawn mirrored or rotated around a center.
+
+use std::f32::consts::TAU;
+use strum::{EnumIter, IntoStaticStr};
+
+/// Most copies radial symmetry can make
+pub const MAX_RADIAL_COUNT: u8 = 16;
+
+#[derive(Clone, Copy, PartialEq, IntoStaticStr, EnumIter)]
+#[strum(serialize_all = "lowercase")]
+pub enum SymmetryMode {
+    /// Only draw once
+    Off,
+    /// Mirror left and right, across a vertical axis
+    Horizontal,
+    /// Mirror top and bottom, across a horizontal axis
+    Vertical,
+    /// Mirror both left and right and top and bottom, making four copies
+    Both,
+    /// Rotate around the center, making radial_count evenly spaced copies
+    Radial,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct Symmetry {
+    pub mode: SymmetryMode,
+    /// Point axes cross at and copies are rotated around, in pixels from the top left corner of the canvas. Should be a multiple of 0.5, so it's on the edge or the center of pixels
+    pub center: (f32, f32),
+    pub radial_count: u8,
+}
+
+impl Symmetry {
+    pub fn new() -> Self {
+        Symmetry {
+            mode: SymmetryMode::Off,
+            center: (0., 0.),
+            radial_count: 4,
+        }
+    }
+    /// Number of copies drawn, including the original
+    pub fn count(&self) -> usize {
+        match self.mode {
+            SymmetryMode::Off => 1,
+            SymmetryMode::Horizontal | SymmetryMode::Vertical => 2,
+            SymmetryMode::Both => 4,
+            SymmetryMode::Radial => self.radial_count.clamp(1, MAX_RADIAL_COUNT) as usize,
+        }
+    }
+    /// Get where copy number index of pixel x, y is. Copy 0 is the pixel itself
+    pub fn transform(&self, index: usize, x: i16, y: i16) -> (i16, i16) {
+        self.map(index, x, y, false)
+    }
+    /// Get which pixel copy number index at x, y is a copy of, the opposite of [Symmetry::transform]
+    pub fn inverse(&self, index: usize, x: i16, y: i16) -> (i16, i16) {
+        self.map(index, x, y, true)
+    }
+    /// Get every copy of pixel x, y, in the order of their index
+    pub fn points(&self, x: i16, y: i16) -> Vec<(i16, i16)> {
+        (0..self.count())
+            .map(|index| self.transform(index, x, y))
+            .collect()
+    }
+    fn map(&self, index: usize, x: i16, y: i16, inverse: bool) -> (i16, i16) {
+        // pixels are moved by their centers
+        let (center_x, center_y) = self.center;
+        let offset_x = x as f32 + 0.5 - center_x;
+        let offset_y = y as f32 + 0.5 - center_y;
+        let (offset_x, offset_y) = match self.mode {
+            SymmetryMode::Off => (offset_x, offset_y),
+            SymmetryMode::Horizontal | SymmetryMode::Vertical | SymmetryMode::Both => {
+                // mirrors are their own inverse
+                let mirror_x = match self.mode {
+                    SymmetryMode::Horizontal => index == 1,
+                    SymmetryMode::Both => index % 2 == 1,
+                    _ => false,
+                };
+                let mirror_y = match self.mode {
+                    SymmetryMode::Vertical => index == 1,
+                    SymmetryMode::Both => index >= 2,
+                    _ => false,
+                };
+                (
+                    if mirror_x { -offset_x } else { offset_x },
+                    if mirror_y { -offset_y } else { offset_y },
+                )
+            }
+            SymmetryMode::Radial => {
+                let mut angle = TAU * index as f32 / self.count() as f32;
+                if inverse {
+                    angle = -angle;
+                }
+                let (sin, cos) = angle.sin_cos();
+                (
+                    offset_x * cos - offset_y * sin,
+                    offset_x * sin + offset_y * cos,
+                )
+            }
+        };
+        (
+            (center_x + offset_x).floor() as i16,
+            (center_y + offset_y).floor() as i16,
+        )
+    }
+}
+
+impl Default for Symmetry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symmetry(mode: SymmetryMode, center: (f32, f32)) -> Symmetry {
+        Symmetry {
+            mode,
+            center,
+            ..Symmetry::new()
+        }
+    }
+
+    #[test]
+    fn mirrors_around_pixel_centers_and_edges() {
+        // around the center of pixel 4, 4
+        let center = (4.5, 4.5);
+        assert_eq!(symmetry(SymmetryMode::Off, center).points(1, 2), [(1, 2)]);
+        assert_eq!(
+            symmetry(SymmetryMode::Horizontal, center).points(1, 2),
+            [(1, 2), (7, 2)]
+        );
+        assert_eq!(
+            symmetry(SymmetryMode::Vertical, center).points(1, 2),
+            [(1, 2), (1, 6)]
+        );
+        assert_eq!(
+            symmetry(SymmetryMode::Both, center).points(1, 2),
+            [(1, 2), (7, 2), (1, 6), (7, 6)]
+        );
+        // pixels on the axis stay put
+        assert_eq!(
+            symmetry(SymmetryMode::Both, center).points(4, 4),
+            [(4, 4); 4]
+        );
+        // around the corner between pixels 3, 3 and 4, 4
+        assert_eq!(
+            symmetry(SymmetryMode::Both, (4., 4.)).points(1, 2),
+            [(1, 2), (6, 2), (1, 5), (6, 5)]
+        );
+    }
+
+    #[test]
+    fn radial_rotates_around_center() {
+        let mut radial = symmetry(SymmetryMode::Radial, (4.5, 4.5));
+        assert_eq!(radial.points(6, 4), [(6, 4), (4, 6), (2, 4), (4, 2)]);
+        for count in 1..=MAX_RADIAL_COUNT {
+            radial.radial_count = count;
+            let mut points = radial.points(20, 4);
+            assert_eq!(points.len(), count as usize);
+            points.sort();
+            points.dedup();
+            assert_eq!(points.len(), count as usize);
+        }
+    }
+
+    #[test]
+    fn inverse_undoes_transform() {
+        for mode in [
+            SymmetryMode::Horizontal,
+            SymmetryMode::Vertical,
+            SymmetryMode::Both,
+            SymmetryMode::Radial,
+        ] {
+            let symmetry = symmetry(mode, (4.5, 4.5));
+            for index in 0..symmetry.count() {
+                let (x, y) = symmetry.transform(index, 6, 4);
+                assert_eq!(symmetry.inverse(index, x, y), (6, 4));
+            }
+        }
+    }
+}
@@ -0,0 +1,402 @@
+//! Pixel art upscalers, which make images bigger while keeping edges sharp and smoothing diagonals.
+//!
+//! Every upscaler only copies pixels of the source image, so upscaled images keep their palette.
+
+use strum::{EnumIter, EnumString, IntoStaticStr};
+
+use crate::{
+    buffer::Image,
+    canvas::{canvas_too_big_error, gen_empty_image, validate_canvas_size},
+};
+
+/// Method used when upscaling images by a whole factor
+#[derive(Clone, Copy, PartialEq, IntoStaticStr, EnumString, EnumIter)]
+#[strum(serialize_all = "lowercase")]
+pub enum Upscaler {
+    /// Each pixel becomes a square of factor × factor pixels
+    Nearest,
+    /// Scale2x by Andrea Mazzoleni, doubles the size
+    Scale2x,
+    /// Scale3x by Andrea Mazzoleni, triples the size
+    Scale3x,
+    /// EPX by Eric Johnston, which Scale2x was later found to be the same as
+    Epx,
+    /// Like xBR by Hyllian, doubles the size, but picks neighboring pixels instead of blending them
+    Xbr,
+}
+
+impl Upscaler {
+    /// Whether image can be upscaled by factor. Upscalers with a fixed factor are applied repeatedly for bigger factors
+    pub fn supports_factor(&self, factor: u8) -> bool {
+        match self {
+            Upscaler::Nearest => factor >= 1,
+            Upscaler::Scale2x | Upscaler::Epx | Upscaler::Xbr => factor.is_power_of_two(),
+            Upscaler::Scale3x => matches!(factor, 1 | 3 | 9 | 27 | 81 | 243),
+        }
+    }
+    /// Factor of one pass of the upscaler
+    fn pass_factor(&self) -> u8 {
+        match self {
+            Upscaler::Nearest => 1,
+            Upscaler::Scale2x | Upscaler::Epx | Upscaler::Xbr => 2,
+            Upscaler::Scale3x => 3,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct UpscaleOptions {
+    pub upscaler: Upscaler,
+    /// How many times bigger the result is, 1 to keep the size
+    pub factor: u8,
+}
+
+impl Default for UpscaleOptions {
+    fn default() -> Self {
+        UpscaleOptions {
+            upscaler: Upscaler::Nearest,
+            factor: 1,
+        }
+    }
+}
+
+/// Get size of an image of width and height upscaled with options, or an error if the upscaler doesn't support the factor or the result is too big
+pub fn upscaled_size(
+    width: u16,
+    height: u16,
+    options: UpscaleOptions,
+) -> Result<(u16, u16), std::io::Error> {
+    let UpscaleOptions { upscaler, factor } = options;
+    if !upscaler.supports_factor(factor) {
+        let name: &str = upscaler.into();
+        return Err(std::io::Error::other(format!(
+            "{} can't upscale by {}",
+            name, factor
+        )));
+    }
+    let width = width as u32 * factor as u32;
+    let height = height as u32 * factor as u32;
+    if width > u16::MAX as u32
+        || height > u16::MAX as u32
+        || !validate_canvas_size(width as u16, height as u16)
+    {
+        return Err(canvas_too_big_error());
+    }
+    Ok((width as u16, height as u16))
+}
+
+/// Upscale image by the factor of options, which must have been checked with [upscaled_size]
+pub fn upscale_image(image: &Image, options: UpscaleOptions) -> Image {
+    let UpscaleOptions { upscaler, factor } = options;
+    if upscaler == Upscaler::Nearest {
+        return nearest(image, factor as usize);
+    }
+    // transparent pixels are all the same, whatever color they hide
+    let mut upscaled = image.clone();
+    for pixel in upscaled.get_image_data_mut() {
+        if pixel[3] == 0 {
+            *pixel = [0; 4];
+        }
+    }
+    let mut factor = factor;
+    while factor > 1 {
+        upscaled = match upscaler {
+            Upscaler::Scale2x | Upscaler::Epx => scale2x(&upscaled),
+            Upscaler::Scale3x => scale3x(&upscaled),
+            Upscaler::Xbr => xbr(&upscaled),
+            Upscaler::Nearest => unreachable!(),
+        };
+        factor /= upscaler.pass_factor();
+    }
+    upscaled
+}
+
+/// Get pixel of image at x, y, using the nearest edge pixel outside of it
+fn sample(image: &Image, x: isize, y: isize) -> [u8; 4] {
+    let x = x.clamp(0, image.width as isize - 1) as usize;
+    let y = y.clamp(0, image.height as isize - 1) as usize;
+    image.get_image_data()[x + y * image.width()]
+}
+
+/// Upscale image where each source pixel becomes a square of factor × factor pixels, given in row-major order by f
+fn upscale_with(
+    image: &Image,
+    factor: usize,
+    f: impl Fn(&dyn Fn(isize, isize) -> [u8; 4], &mut [[u8; 4]]),
+) -> Image {
+    let width = image.width() * factor;
+    let mut upscaled = gen_empty_image(width as u16, (image.height() * factor) as u16);
+    let data = upscaled.get_image_data_mut();
+    let mut square = vec![[0; 4]; factor * factor];
+    for y in 0..image.height() {
+        for x in 0..image.width() {
+            let (x, y) = (x as isize, y as isize);
+            f(
+                &|offset_x, offset_y| sample(image, x + offset_x, y + offset_y),
+                &mut square,
+            );
+            for (index, pixel) in square.iter().enumerate() {
+                let target_x = x as usize * factor + index % factor;
+                let target_y = y as usize * factor + index / factor;
+                data[target_x + target_y * width] = *pixel;
+            }
+        }
+    }
+    upscaled
+}
+
+fn nearest(image: &Image, factor: usize) -> Image {
+    upscale_with(image, factor, |get, square| square.fill(get(0, 0)))
+}
+
+/// Neighbors are named like this, with e being the current pixel:
+/// ```text
+/// a b c
+/// d e f
+/// g h i
+/// ```
+fn scale2x(image: &Image) -> Image {
+    upscale_with(image, 2, |get, square| {
+        let e = get(0, 0);
+        // each corner is worked out the same way, with the neighbors mirrored towards it
+        for (index, (x, y)) in [(-1, -1), (1, -1), (-1, 1), (1, 1)].into_iter().enumerate() {
+            let (b, d, f, h) = (get(0, -y), get(-x, 0), get(x, 0), get(0, y));
+            square[index] = if h == f && d != h && b != f { f } else { e };
+        }
+    })
+}
+
+/// See [scale2x] for the names of neighbors
+fn scale3x(image: &Image) -> Image {
+    upscale_with(image, 3, |get, square| {
+        let (a, b, c) = (get(-1, -1), get(0, -1), get(1, -1));
+        let (d, e, f) = (get(-1, 0), get(0, 0), get(1, 0));
+        let (g, h, i) = (get(-1, 1), get(0, 1), get(1, 1));
+        // edges meeting at each corner
+        let top_left = d == b && b != f && d != h;
+        let top_right = b == f && b != d && f != h;
+        let bottom_left = d == h && d != b && h != f;
+        let bottom_right = h == f && d != h && b != f;
+        let pick = |condition: bool, pixel: [u8; 4]| if condition { pixel } else { e };
+        square.copy_from_slice(&[
+            pick(top_left, d),
+            pick((top_left && e != c) || (top_right && e != a), b),
+            pick(top_right, f),
+            pick((top_left && e != g) || (bottom_left && e != a), d),
+            e,
+            pick((top_right && e != i) || (bottom_right && e != c), f),
+            pick(bottom_left, d),
+            pick((bottom_left && e != i) || (bottom_right && e != g), h),
+            pick(bottom_right, f),
+        ]);
+    })
+}
+
+/// Difference between pixels as the original xBR measures it, mostly by brightness
+fn distance(first: [u8; 4], second: [u8; 4]) -> f32 {
+    // premultiplied, so transparent pixels are the same whatever color they hide
+    let yuv = |pixel: [u8; 4]| {
+        let alpha = pixel[3] as f32 / 255.;
+        let [r, g, b] = [0, 1, 2].map(|channel| pixel[channel] as f32 * alpha);
+        [
+            0.299 * r + 0.587 * g + 0.114 * b,
+            -0.169 * r - 0.331 * g + 0.5 * b,
+            0.5 * r - 0.419 * g - 0.081 * b,
+            pixel[3] as f32,
+        ]
+    };
+    let (first, second) = (yuv(first), yuv(second));
+    48. * (first[0] - second[0]).abs()
+        + 7. * (first[1] - second[1]).abs()
+        + 6. * (first[2] - second[2]).abs()
+        + 48. * (first[3] - second[3]).abs()
+}
+
+/// Get the new bottom right corner of the current pixel if an edge crosses it, or None to keep the pixel.
+///
+/// Neighbors are named like in [scale2x], with the ones further out named after the pixel next to them:
+/// ```text
+///   a  b  c
+///   d  e  f  f4
+///   g  h  i  i4
+///      h5 i5
+/// ```
+fn xbr_corner(get: impl Fn(isize, isize) -> [u8; 4]) -> Option<[u8; 4]> {
+    let e = get(0, 0);
+    let (f, h) = (get(1, 0), get(0, 1));
+    if e == f || e == h {
+        return None;
+    }
+    let (b, c, d) = (get(0, -1), get(1, -1), get(-1, 0));
+    let (g, i) = (get(-1, 1), get(1, 1));
+    let (f4, i4, h5, i5) = (get(2, 0), get(2, 1), get(0, 2), get(1, 2));
+    // how different pixels are along each diagonal, which is lowest along an edge
+    let along_fh =
+        distance(e, c) + distance(e, g) + distance(i, h5) + distance(i, f4) + 4. * distance(h, f);
+    let along_ei =
+        distance(h, d) + distance(h, i5) + distance(f, i4) + distance(f, b) + 4. * distance(e, i);
+    // like the original, corners of bigger shapes are kept
+    let is_edge = along_fh < along_ei
+        && ((f != b && h != d) || (e == i && f != i4 && h != i5) || e == g || e == c);
+    is_edge.then(|| {
+        if distance(e, f) <= distance(e, h) {
+            f
+        } else {
+            h
+        }
+    })
+}
+
+fn xbr(image: &Image) -> Image {
+    upscale_with(image, 2, |get, square| {
+        let e = get(0, 0);
+        // each corner is worked out the same way, with the neighbors mirrored towards it
+        for (index, (x, y)) in [(-1, -1), (1, -1), (-1, 1), (1, 1)].into_iter().enumerate() {
+            square[index] =
+                xbr_corner(|offset_x, offset_y| get(offset_x * x, offset_y * y)).unwrap_or(e);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use strum::IntoEnumIterator;
+
+    const BLACK: [u8; 4] = [0, 0, 0, 255];
+    const WHITE: [u8; 4] = [255; 4];
+
+    /// Image drawn with `#` for black and `.` for white, one row per line
+    fn picture(rows: &str) -> Image {
+        let rows: Vec<&str> = rows.split_whitespace().collect();
+        let bytes = rows
+            .iter()
+            .flat_map(|row| row.chars())
+            .flat_map(|pixel| if pixel == '#' { BLACK } else { WHITE })
+            .collect();
+        Image {
+            width: rows[0].len() as u16,
+            height: rows.len() as u16,
+            bytes,
+        }
+    }
+
+    /// Short diagonal line in the middle of the image
+    fn diagonal() -> Image {
+        picture(
+            "....
+             ..#.
+             .#..
+             ....",
+        )
+    }
+
+    fn upscale(image: &Image, upscaler: Upscaler, factor: u8) -> Image {
+        upscale_image(image, UpscaleOptions { upscaler, factor })
+    }
+
+    #[test]
+    fn scale2x_fills_diagonal_corners() {
+        let expected = picture(
+            "........
+             ........
+             ....##..
+             ...###..
+             ..###...
+             ..##....
+             ........
+             ........",
+        );
+        assert_eq!(
+            upscale(&diagonal(), Upscaler::Scale2x, 2).bytes,
+            expected.bytes
+        );
+        assert_eq!(upscale(&diagonal(), Upscaler::Epx, 2).bytes, expected.bytes);
+    }
+
+    #[test]
+    fn scale3x_fills_diagonal_corners() {
+        let expected = picture(
+            "............
+             ............
+             ............
+             ......###...
+             ......###...
+             .....####...
+             ...####.....
+             ...###......
+             ...###......
+             ............
+             ............
+             ............",
+        );
+        assert_eq!(
+            upscale(&diagonal(), Upscaler::Scale3x, 3).bytes,
+            expected.bytes
+        );
+    }
+
+    #[test]
+    fn xbr_smooths_diagonal() {
+        let expected = picture(
+            "........
+             ........
+             .....#..
+             ...##...
+             ...##...
+             ..#.....
+             ........
+             ........",
+        );
+        assert_eq!(upscale(&diagonal(), Upscaler::Xbr, 2).bytes, expected.bytes);
+    }
+
+    #[test]
+    fn flat_images_stay_flat() {
+        let flat = picture("### ###");
+        for upscaler in Upscaler::iter() {
+            for factor in [2, 3, 4, 9] {
+                if !upscaler.supports_factor(factor) {
+                    continue;
+                }
+                let upscaled = upscale(&flat, upscaler, factor);
+                let size = (3 * factor as u16, 2 * factor as u16);
+                assert_eq!((upscaled.width, upscaled.height), size);
+                assert!(upscaled
+                    .get_image_data()
+                    .iter()
+                    .all(|pixel| *pixel == BLACK));
+            }
+        }
+    }
+
+    #[test]
+    fn supported_factors_give_that_size() {
+        let image = picture("#.");
+        for upscaler in Upscaler::iter() {
+            let name: &str = upscaler.into();
+            for factor in 0..=u8::MAX {
+                let options = UpscaleOptions { upscaler, factor };
+                let size = upscaled_size(image.width, image.height, options);
+                if !upscaler.supports_factor(factor) {
+                    assert!(size.is_err(), "{} by {}", name, factor);
+                    continue;
+                }
+                let expected = (2 * factor as u16, factor as u16);
+                assert_eq!(size.unwrap(), expected, "{} by {}", name, factor);
+                let upscaled = upscale_image(&image, options);
+                assert_eq!(
+                    (upscaled.width, upscaled.height),
+                    expected,
+                    "{} by {}",
+                    name,
+                    factor
+                );
+            }
+        }
+        // fixed factor upscalers reject factors they can't reach by repeating
+        assert!(!Upscaler::Scale2x.supports_factor(6));
+        assert!(!Upscaler::Scale3x.supports_factor(6));
+        assert!(!Upscaler::Nearest.supports_factor(0));
+    }
+}
@@ -0,0 +1,422 @@
+use std::path::Path;
+
+use plow_core::{
+    animation::*,
+    canvas::*,
+    consts::DEFAULT_FRAME_DURATION,
+    file::canvas_from_bytes,
+    fill::{fill_region, tolerance_from_percent},
+    spritesheet::*,
+    upscale::{UpscaleOptions, Upscaler},
+    ImageFormat,
+};
+
+const USAGE: &str = "usage: plow <command> [arguments]
+
+runs the editor when no command is given. the format of outputs is chosen by their file extension
+
+commands:
+  convert <input> <output>
+      convert between formats, keeping layers if the output format supports them
+  flatten <input> <output>
+      merge all visible layers into one
+  resize <input> <output> <width> <height> [anchor]
+      resize canvas without scaling, new areas are transparent.
+      anchor is one of top-left, top, top-right, left, center (default), right, bottom-left, bottom, bottom-right
+  scale <input> <output> <width> <height> [nearest (default) | bilinear]
+      scale image to size
+  upscale <input> <output> <factor> [nearest (default) | scale2x | scale3x | epx | xbr]
+      upscale pixel art by a whole factor. scale2x, epx & xbr support powers of 2, and scale3x powers of 3
+  fill <input> <output> <x> <y> <color> [tolerance (0-100, default 0)] [--global]
+      fill area of similar color on the current layer, like the bucket tool. color is hex, like #ff0000 or #ff000080.
+      with --global, all pixels of similar color are filled instead of only the contiguous area
+  animate <input> <output> [frames (default) | layers] [plays (default 0, forever)] [layer duration (ms)]
+      export animation as gif, webp, or as apng when the output is a png. with layers, each visible layer is a frame from bottom to top
+  spritesheet <input> <output> [frames (default) | layers] [columns (default 0, square)] [padding] [margin]
+      pack frames or layers into a spritesheet, and save a json file describing where each sprite is next to it
+  slice <input> <output> <cell width> <cell height> [frames (default) | layers] [padding] [margin]
+      slice spritesheet on a grid into frames or layers, leaving out empty cells
+  help
+      show this message";
+
+/// Commands handled by [run_command]
+const COMMANDS: [&str; 9] = [
+    "convert",
+    "flatten",
+    "resize",
+    "scale",
+    "upscale",
+    "fill",
+    "animate",
+    "spritesheet",
+    "slice",
+];
+
+fn invalid_input(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, message)
+}
+
+/// Get argument at index, or an error saying it is missing
+fn arg<'a>(args: &'a [String], index: usize, name: &str) -> Result<&'a str, std::io::Error> {
+    args.get(index)
+        .map(|arg| arg.as_str())
+        .ok_or_else(|| invalid_input(format!("missing argument <{}>", name)))
+}
+
+/// Parse argument at index
+fn parse_arg<T: std::str::FromStr>(
+    args: &[String],
+    index: usize,
+    name: &str,
+) -> Result<T, std::io::Error> {
+    let value = arg(args, index, name)?;
+    value
+        .parse()
+        .map_err(|_| invalid_input(format!("invalid <{}>: {}", name, value)))
+}
+
+/// Parse hex color, like `#ff0000` or `ff000080`
+fn parse_color(text: &str) -> Result<[f32; 4], std::io::Error> {
+    let hex = text.trim_start_matches('#');
+    let invalid = || invalid_input(format!("invalid color: {}", text));
+    if (hex.len() != 6 && hex.len() != 8) || !hex.is_ascii() {
+        return Err(invalid());
+    }
+    let mut color = [1.; 4];
+    for (index, channel) in color.iter_mut().enumerate().take(hex.len() / 2) {
+        let value =
+            u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16).map_err(|_| invalid())?;
+        *channel = value as f32 / 255.;
+    }
+    Ok(color)
+}
+
+fn parse_anchor(text: &str) -> Result<(f32, f32), std::io::Error> {
+    Ok(match text {
+        "top-left" => (0., 0.),
+        "top" => (0.5, 0.),
+        "top-right" => (1., 0.),
+        "left" => (0., 0.5),
+        "center" => (0.5, 0.5),
+        "right" => (1., 0.5),
+        "bottom-left" => (0., 1.),
+        "bottom" => (0.5, 1.),
+        "bottom-right" => (1., 1.),
+        _ => return Err(invalid_input(format!("invalid anchor: {}", text))),
+    })
+}
+
+fn load(path: &str) -> Result<Canvas, std::io::Error> {
+    let bytes = std::fs::read(path)?;
+    let name = Path::new(path)
+        .file_stem()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    canvas_from_bytes(&bytes, name)
+}
+
+fn save(canvas: &Canvas, path: &str) -> Result<(), std::io::Error> {
+    let extension = Path::new(path)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let format = FileFormat::from_extension(&extension)
+        .ok_or_else(|| invalid_input(format!("unknown output format: {}", path)))?;
+    std::fs::write(path, canvas.to_bytes(format)?)
+}
+
+/// Parse frames or layers argument at index, defaulting to frames
+fn parse_source(args: &[String], index: usize) -> Result<AnimationSource, std::io::Error> {
+    match args.get(index).map(|source| source.as_str()) {
+        None | Some("frames") => Ok(AnimationSource::Frames),
+        Some("layers") => Ok(AnimationSource::Layers),
+        Some(source) => Err(invalid_input(format!("invalid source: {}", source))),
+    }
+}
+
+/// Parse optional argument at index, or use default if it is missing
+fn parse_optional_arg<T: std::str::FromStr>(
+    args: &[String],
+    index: usize,
+    name: &str,
+    default: T,
+) -> Result<T, std::io::Error> {
+    match args.get(index) {
+        Some(_) => parse_arg(args, index, name),
+        None => Ok(default),
+    }
+}
+
+fn run_command(command: &str, args: &[String]) -> Result<(), std::io::Error> {
+    let input = arg(args, 0, "input")?;
+    let output = arg(args, 1, "output")?;
+    let mut canvas = load(input)?;
+    match command {
+        "convert" => {}
+        "flatten" => {
+            canvas = Canvas::from_image(canvas.to_image(), canvas.name, ImageFormat::Png)?;
+        }
+        "resize" => {
+            let width = parse_arg(args, 2, "width")?;
+            let height = parse_arg(args, 3, "height")?;
+            let anchor = match args.get(4) {
+                Some(anchor) => parse_anchor(anchor)?,
+                None => (0.5, 0.5),
+            };
+            canvas.resize_canvas(width, height, anchor)?;
+        }
+        "scale" => {
+            let width = parse_arg(args, 2, "width")?;
+            let height = parse_arg(args, 3, "height")?;
+            let mode = match args.get(4).map(|mode| mode.as_str()) {
+                None | Some("nearest") => ScaleMode::Nearest,
+                Some("bilinear") => ScaleMode::Bilinear,
+                Some(mode) => return Err(invalid_input(format!("invalid scale mode: {}", mode))),
+            };
+            canvas.scale_image(width, height, mode)?;
+        }
+        "upscale" => {
+            let factor = parse_arg(args, 2, "factor")?;
+            let upscaler = match args.get(3) {
+                Some(upscaler) => upscaler
+                    .parse()
+                    .map_err(|_| invalid_input(format!("invalid upscaler: {}", upscaler)))?,
+                None => Upscaler::Nearest,
+            };
+            canvas.upscale(UpscaleOptions { upscaler, factor })?;
+        }
+        "animate" => {
+            let format = match Path::new(output)
+                .extension()
+                .map(|extension| extension.to_string_lossy().to_lowercase())
+                .as_deref()
+            {
+                Some("gif") => AnimationFormat::Gif,
+                Some("png" | "apng") => AnimationFormat::Apng,
+                Some("webp") => AnimationFormat::WebP,
+                _ => {
+                    return Err(invalid_input(format!(
+                        "unknown animation format: {}",
+                        output
+                    )))
+                }
+            };
+            let source = parse_source(args, 2)?;
+            let plays = parse_optional_arg(args, 3, "plays", 0)?;
+            let layer_duration =
+                parse_optional_arg(args, 4, "layer duration", DEFAULT_FRAME_DURATION)?;
+            let options = AnimationOptions {
+                format,
+                source,
+                plays,
+                layer_duration,
+            };
+            return std::fs::write(output, canvas.to_bytes(FileFormat::Animation(options))?);
+        }
+        "spritesheet" => {
+            let source = parse_source(args, 2)?;
+            let options = SpritesheetOptions {
+                columns: parse_optional_arg(args, 3, "columns", 0)?,
+                padding: parse_optional_arg(args, 4, "padding", 0)?,
+                margin: parse_optional_arg(args, 5, "margin", 0)?,
+            };
+            let spritesheet = pack_spritesheet(&canvas, source, &options)?;
+            let canvas =
+                Canvas::from_image(spritesheet.image.clone(), canvas.name, ImageFormat::Png)?;
+            save(&canvas, output)?;
+            let image_name = Path::new(output)
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            return std::fs::write(
+                Path::new(output).with_extension("json"),
+                spritesheet.to_json(&image_name),
+            );
+        }
+        "slice" => {
+            let options = SliceOptions {
+                cell_width: parse_arg(args, 2, "cell width")?,
+                cell_height: parse_arg(args, 3, "cell height")?,
+                padding: parse_optional_arg(args, 5, "padding", 0)?,
+                margin: parse_optional_arg(args, 6, "margin", 0)?,
+                skip_empty: true,
+            };
+            let target = parse_source(args, 4)?;
+            let bytes = std::fs::read(input)?;
+            canvas = canvas_from_spritesheet(&bytes, canvas.name, target, &options)?;
+        }
+        "fill" => {
+            let x: usize = parse_arg(args, 2, "x")?;
+            let y: usize = parse_arg(args, 3, "y")?;
+            let color = parse_color(arg(args, 4, "color")?)?;
+            let global = args.iter().skip(5).any(|arg| arg == "--global");
+            let tolerance = match args.iter().skip(5).find(|arg| *arg != "--global") {
+                Some(tolerance) => tolerance
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|tolerance| *tolerance <= 100)
+                    .ok_or_else(|| invalid_input(format!("invalid tolerance: {}", tolerance)))?,
+                None => 0,
+            };
+            let (width, height) = (canvas.width as usize, canvas.height as usize);
+            if x >= width || y >= height {
+                return Err(invalid_input(format!("{}, {} is outside of canvas", x, y)));
+            }
+            let tolerance = tolerance_from_percent(tolerance);
+            // in indexed color mode, fill with the index of the nearest palette color
+            let color = color.map(|channel| (channel * 255.).round() as u8);
+            let color = canvas
+                .encode_color(color)
+                .map(|channel| channel as f32 / 255.);
+            let region = canvas.find_region(x, y, tolerance, !global, None);
+            let layer = &mut canvas.layers[canvas.current_layer];
+            fill_region(width, layer.get_image_data_mut(), &region, color);
+        }
+        _ => return Err(invalid_input(format!("unknown command: {}", command))),
+    }
+    save(&canvas, output)
+}
+
+/// Run command line mode with arguments (not including the program name). Returns the exit code
+pub fn run(args: &[String]) -> i32 {
+    let command = args[0].as_str();
+    match command {
+        command if COMMANDS.contains(&command) => match run_command(command, &args[1..]) {
+            Ok(()) => 0,
+            Err(err) => {
+                eprintln!("error: {}", err);
+                1
+            }
+        },
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            0
+        }
+        _ => {
+            eprintln!("unknown command: {}\n\n{}", command, USAGE);
+            2
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    /// Input file of a command, saved in a temporary directory that is removed when the fixture is dropped
+    struct Fixture {
+        dir: std::path::PathBuf,
+        input: String,
+    }
+
+    impl Fixture {
+        /// Save canvas as file_name, in a directory named after the test
+        fn new(test: &str, file_name: &str, canvas: &Canvas) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("plow-cli-{}-{}", std::process::id(), test));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            let input = dir.join(file_name).to_string_lossy().to_string();
+            save(canvas, &input).unwrap();
+            Fixture { dir, input }
+        }
+        /// Run command on the input, saving to output in the same directory, and load the result
+        fn run(&self, command: &str, output: &str, extra: &[&str]) -> Canvas {
+            let output = self.dir.join(output).to_string_lossy().to_string();
+            let mut command_args = args(&[&self.input, &output]);
+            command_args.extend(args(extra));
+            run_command(command, &command_args).unwrap();
+            load(&output).unwrap()
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    /// 2x2 canvas with red at 0, 0 on the bottom layer and blue at 1, 0 on the top layer
+    fn layered_canvas() -> Canvas {
+        let mut canvas = Canvas::new(2, 2, String::from("fixture")).unwrap();
+        canvas.layers[0].set_pixel(0, 0, RED);
+        canvas.new_layer();
+        canvas.layers[0].set_pixel(1, 0, BLUE);
+        canvas
+    }
+
+    /// 4x1 canvas with a red wall in the second column
+    fn walled_canvas() -> Canvas {
+        let mut canvas = Canvas::new(4, 1, String::from("fixture")).unwrap();
+        canvas.layers[0].set_pixel(1, 0, RED);
+        canvas
+    }
+
+    fn pixels(canvas: &Canvas, layer: usize) -> Vec<[u8; 4]> {
+        canvas.layers[layer].image.get_image_data().to_vec()
+    }
+
+    #[test]
+    fn usage_lists_every_command() {
+        let listed: Vec<&str> = USAGE
+            .lines()
+            .filter(|line| line.starts_with("  ") && !line.starts_with("   "))
+            .filter_map(|line| line.split_whitespace().next())
+            .filter(|command| *command != "help")
+            .collect();
+        assert_eq!(listed, COMMANDS);
+    }
+
+    #[test]
+    fn every_command_is_dispatched() {
+        for command in COMMANDS {
+            // missing arguments are an error (1), not an unknown command (2)
+            assert_eq!(run(&args(&[command])), 1, "{}", command);
+        }
+        assert_eq!(run(&args(&["unknown"])), 2);
+    }
+
+    #[test]
+    fn convert_keeps_layers() {
+        let fixture = Fixture::new("convert", "fixture.plow", &layered_canvas());
+        let canvas = fixture.run("convert", "out.ora", &[]);
+        assert_eq!(canvas.layers.len(), 2);
+        assert_eq!(pixels(&canvas, 0), [[0; 4], BLUE, [0; 4], [0; 4]]);
+        assert_eq!(pixels(&canvas, 1), [RED, [0; 4], [0; 4], [0; 4]]);
+    }
+
+    #[test]
+    fn flatten_merges_layers() {
+        let fixture = Fixture::new("flatten", "fixture.plow", &layered_canvas());
+        let canvas = fixture.run("flatten", "out.plow", &[]);
+        assert_eq!(canvas.layers.len(), 1);
+        assert_eq!(pixels(&canvas, 0), [RED, BLUE, [0; 4], [0; 4]]);
+    }
+
+    #[test]
+    fn resize_anchors_image() {
+        let fixture = Fixture::new("resize", "fixture.png", &walled_canvas());
+        let canvas = fixture.run("resize", "out.png", &["6", "2", "top-right"]);
+        assert_eq!((canvas.width, canvas.height), (6, 2));
+        let mut expected = vec![[0; 4]; 12];
+        expected[3] = RED;
+        assert_eq!(pixels(&canvas, 0), expected);
+    }
+
+    #[test]
+    fn fill_stops_at_walls_unless_global() {
+        let fixture = Fixture::new("fill", "fixture.png", &walled_canvas());
+        let green = [0, 255, 0, 255];
+        let canvas = fixture.run("fill", "out.png", &["2", "0", "#00ff00"]);
+        assert_eq!(pixels(&canvas, 0), [[0; 4], RED, green, green]);
+        let canvas = fixture.run("fill", "global.png", &["2", "0", "00ff00", "--global"]);
+        assert_eq!(pixels(&canvas, 0), [green, RED, green, green]);
+    }
+}
@@ -0,0 +1,48 @@
+use plow_core::buffer::Image;
+
+/// Clipboard for images.
+///
+/// Uses the system clipboard when available, so images can be copied to and from other programs.
+/// Copied images are also kept internally, so copying between canvases works even without a system clipboard (like on web).
+pub struct Clipboard {
+    internal: Option<Image>,
+    #[cfg(not(target_arch = "wasm32"))]
+    system: Option<arboard::Clipboard>,
+}
+
+impl Clipboard {
+    pub fn new() -> Self {
+        Clipboard {
+            internal: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            system: arboard::Clipboard::new().ok(),
+        }
+    }
+    pub fn set(&mut self, image: Image) {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(system) = &mut self.system {
+            let _ = system.set_image(arboard::ImageData {
+                width: image.width as usize,
+                height: image.height as usize,
+                bytes: std::borrow::Cow::Borrowed(&image.bytes),
+            });
+        }
+        self.internal = Some(image);
+    }
+    /// Get image from clipboard. Prefers the system clipboard, and falls back to the internal one if it doesn't contain an image.
+    pub fn get(&mut self) -> Option<Image> {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(system) = &mut self.system {
+            if let Ok(data) = system.get_image() {
+                if let (Ok(width), Ok(height)) = (data.width.try_into(), data.height.try_into()) {
+                    return Some(Image {
+                        width,
+                        height,
+                        bytes: data.bytes.into_owned(),
+                    });
+                }
+            }
+        }
+        self.internal.clone()
+    }
+}
@@ -8,9 +8,46 @@ pub const DEFAULT_CANVAS_HEIGHT: u16 = 128;
 pub const DEFAULT_PRIMARY_COLOR: [f32; 4] = [0., 0., 0., 1.];
 pub const DEFAULT_SECONDARY_COLOR: [f32; 4] = [1., 1., 1., 1.];
 
-pub const DIRECTIONS: [[isize; 2]; 4] = [[0, 1], [0, -1], [1, 0], [-1, 0]];
 pub const UNTITLED_NAME: &str = "untitled";
 
+/// Colors of the palette when starting, the pico-8 palette
+pub const DEFAULT_PALETTE: [[u8; 3]; 16] = [
+    [0x00, 0x00, 0x00],
+    [0x1d, 0x2b, 0x53],
+    [0x7e, 0x25, 0x53],
+    [0x00, 0x87, 0x51],
+    [0xab, 0x52, 0x36],
+    [0x5f, 0x57, 0x4f],
+    [0xc2, 0xc3, 0xc7],
+    [0xff, 0xf1, 0xe8],
+    [0xff, 0x00, 0x4d],
+    [0xff, 0xa3, 0x00],
+    [0xff, 0xec, 0x27],
+    [0x00, 0xe4, 0x36],
+    [0x29, 0xad, 0xff],
+    [0x83, 0x76, 0x9c],
+    [0xff, 0x77, 0xa8],
+    [0xff, 0xcc, 0xaa],
+];
+/// Max colors of palettes extracted from images, when starting
+pub const DEFAULT_EXTRACT_COLORS: usize = 16;
+/// Size of palette swatches, in points
+pub const PALETTE_SWATCH_SIZE: f32 = 16.;
+/// Width of the palette window, which swatches wrap within
+pub const PALETTE_WINDOW_WIDTH: f32 = 200.;
+
+/// Length of each dash of the selection outline, in pixels
+pub const MARCHING_ANTS_DASH: f32 = 4.;
+/// How many pixels per second the dashes of the selection outline move
+pub const MARCHING_ANTS_SPEED: f32 = 16.;
+/// Color of the axes of symmetry drawn over the canvas
+pub const SYMMETRY_AXIS_COLOR: Color = color_u8!(0, 170, 255, 200);
+
+/// Frames per second animations are played back at by default
+pub const DEFAULT_PLAYBACK_FPS: u32 = 12;
+/// Opacity of onion skins of the previous and next frames
+pub const ONION_SKIN_OPACITY: f32 = 0.3;
+
 const DEFAULT_VERTEX: &str = r#"#version 100
 precision highp float;
 
@@ -0,0 +1,98 @@
+use plow_core::{
+    animation::AnimationSource, canvas::*, palette::*, project::encode_png, spritesheet::*,
+};
+
+/// Save canvas in its preferred format. If `overwrite_old_if_possible` is true and the canvas has been saved to a known path before, that file is overwritten, otherwise the user is asked where to save it.
+pub fn export(canvas: &mut Canvas, overwrite_old_if_possible: bool) {
+    let bytes = match canvas.to_bytes(canvas.preffered_file_format) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            println!("failed to export {}", err);
+            return;
+        }
+    };
+    canvas.mark_saved();
+
+    let file_ext = canvas.preffered_file_format.extension();
+
+    // if on standalone, and file has already been saved before to a known path, and `overwrite_old_if_possible` is true, then directly overwrite old path
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        if overwrite_old_if_possible {
+            if let Some(path) = &canvas.save_path {
+                let _ = std::fs::write(path, bytes);
+                return;
+            }
+        }
+    }
+
+    // download the buffer data with quad-file-download
+    let result = quad_files::download(&(canvas.name.clone() + "." + file_ext), &bytes, Some(""));
+
+    // keep track where file was saved (only for standalone)
+    if let Ok(Some(location)) = result {
+        if let Some(file_name) = location.file_stem() {
+            canvas.name = file_name.to_string_lossy().to_string();
+        }
+        canvas.save_path = Some(location);
+    }
+}
+
+/// Export canvas in a new format. Since the old save path was for another format, it is always asked for a new one.
+pub fn export_as(canvas: &mut Canvas, format: FileFormat) {
+    if canvas.preffered_file_format != format {
+        canvas.preffered_file_format = format;
+        canvas.save_path = None;
+    }
+    export(canvas, false);
+}
+
+/// Export frames or layers of canvas packed into a png spritesheet, along with a json file describing where each sprite is
+pub fn export_spritesheet(canvas: &Canvas, source: AnimationSource, options: &SpritesheetOptions) {
+    let spritesheet = match pack_spritesheet(canvas, source, options) {
+        Ok(spritesheet) => spritesheet,
+        Err(err) => {
+            println!("failed to export spritesheet {}", err);
+            return;
+        }
+    };
+    let bytes = match encode_png(&spritesheet.image) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            println!("failed to export spritesheet {}", err);
+            return;
+        }
+    };
+    let image_name = canvas.name.clone() + ".png";
+    let result = quad_files::download(&image_name, &bytes, Some(""));
+
+    // on standalone, save json next to the image, named after it
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Ok(Some(location)) = result {
+        let image_name = location
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or(image_name);
+        let json = spritesheet.to_json(&image_name);
+        if let Err(err) = std::fs::write(location.with_extension("json"), json) {
+            println!("failed to save spritesheet json {}", err);
+        }
+    }
+    // on web, download json as another file
+    #[cfg(target_arch = "wasm32")]
+    {
+        let _ = result;
+        let json = spritesheet.to_json(&image_name);
+        let _ = quad_files::download(&(canvas.name.clone() + ".json"), json.as_bytes(), Some(""));
+    }
+}
+
+/// Save palette in format
+pub fn export_palette(palette: &Palette, format: PaletteFormat) {
+    let bytes = palette.to_bytes(format);
+    let _ = quad_files::download(
+        &(palette.name.clone() + "." + format.extension()),
+        &bytes,
+        Some(""),
+    );
+}
@@ -1,11 +1,23 @@
-use canvas::*;
+use clipboard::Clipboard;
 use egui_macroquad::egui::{self, Layout, WidgetText};
+use export::*;
 use macroquad::prelude::*;
+use plow_core::{
+    animation::*, canvas::*, consts::DEFAULT_FRAME_DURATION, convolution::EdgeMode,
+    file::canvas_from_bytes, filter::*, indexed::*, palette::*, spritesheet::*, stroke::Stroke,
+    symmetry::*, upscale::*, ImageFormat,
+};
+use render::LayerTextures;
 mod consts;
 use consts::*;
 use quad_files::{FileInputResult, FilePicker};
+use strum::IntoEnumIterator;
 use tools::*;
-mod canvas;
+#[cfg(not(target_arch = "wasm32"))]
+mod cli;
+mod clipboard;
+mod export;
+mod render;
 mod tools;
 
 /// Draw line with triple width, where center is white and edges are black.
@@ -33,6 +45,95 @@ fn draw_bold_line(x1: f32, y1: f32, x2: f32, y2: f32) {
     }
 }
 
+/// Draw line with moving black and white dashes, used to draw the outline of the selection
+fn draw_marching_ants(x1: f32, y1: f32, x2: f32, y2: f32) {
+    draw_line(x1, y1, x2, y2, 1., WHITE);
+
+    let length = (x2 - x1).abs() + (y2 - y1).abs();
+    let (direction_x, direction_y) = ((x2 - x1) / length, (y2 - y1) / length);
+    let offset = (get_time() as f32 * MARCHING_ANTS_SPEED) % (MARCHING_ANTS_DASH * 2.);
+    let mut position = offset - MARCHING_ANTS_DASH * 2.;
+    while position < length {
+        let start = position.max(0.);
+        let end = (position + MARCHING_ANTS_DASH).min(length);
+        if end > start {
+            draw_line(
+                x1 + direction_x * start,
+                y1 + direction_y * start,
+                x1 + direction_x * end,
+                y1 + direction_y * end,
+                1.,
+                BLACK,
+            );
+        }
+        position += MARCHING_ANTS_DASH * 2.;
+    }
+}
+
+/// Draw the axes of symmetry over the canvas, or for radial symmetry the direction of each copy
+fn draw_symmetry_axes(canvas: &Canvas, symmetry: &Symmetry) {
+    let (center_x, center_y) = symmetry.center;
+    let (width, height) = (canvas.width as f32, canvas.height as f32);
+    let lines = match symmetry.mode {
+        SymmetryMode::Off => Vec::new(),
+        SymmetryMode::Horizontal => vec![((center_x, 0.), (center_x, height))],
+        SymmetryMode::Vertical => vec![((0., center_y), (width, center_y))],
+        SymmetryMode::Both => vec![
+            ((center_x, 0.), (center_x, height)),
+            ((0., center_y), (width, center_y)),
+        ],
+        SymmetryMode::Radial => {
+            let count = symmetry.count();
+            (0..count)
+                .map(|index| {
+                    // the first copy points up, and each one after is rotated clockwise
+                    let angle = std::f32::consts::TAU * (index as f32 / count as f32 - 0.25);
+                    let (direction_y, direction_x) = angle.sin_cos();
+                    // distance to the edge of the canvas
+                    let reach = |center: f32, direction: f32, size: f32| {
+                        if direction > 0. {
+                            (size - center) / direction
+                        } else if direction < 0. {
+                            -center / direction
+                        } else {
+                            f32::INFINITY
+                        }
+                    };
+                    let length = reach(center_x, direction_x, width)
+                        .min(reach(center_y, direction_y, height))
+                        .max(0.);
+                    (
+                        (center_x, center_y),
+                        (
+                            center_x + direction_x * length,
+                            center_y + direction_y * length,
+                        ),
+                    )
+                })
+                .collect()
+        }
+    };
+    for ((x1, y1), (x2, y2)) in lines {
+        draw_line(
+            x1 * canvas.camera_grid_size - canvas.camera_x,
+            y1 * canvas.camera_grid_size - canvas.camera_y,
+            x2 * canvas.camera_grid_size - canvas.camera_x,
+            y2 * canvas.camera_grid_size - canvas.camera_y,
+            1.,
+            SYMMETRY_AXIS_COLOR,
+        );
+    }
+}
+
+/// Set camera to show the entire canvas, centered on screen
+fn fit_camera(canvas: &mut Canvas) {
+    // make zoom to show entire canvas height
+    canvas.camera_grid_size = (screen_width() / canvas.height as f32 / 2.0).max(MIN_ZOOM);
+    // make camera position default be at center of canvas
+    canvas.camera_x = canvas.width as f32 / 2. * canvas.camera_grid_size - screen_width() / 2.;
+    canvas.camera_y = canvas.height as f32 / 2. * canvas.camera_grid_size - screen_height() / 2.;
+}
+
 fn new_general_window(title: impl Into<WidgetText>, open: &mut bool) -> egui::Window<'_> {
     egui::Window::new(title)
         .collapsible(false)
@@ -40,6 +141,106 @@ fn new_general_window(title: impl Into<WidgetText>, open: &mut bool) -> egui::Wi
         .open(open)
 }
 
+/// What the next file opened with the file picker is used for
+#[derive(Clone, Copy, PartialEq)]
+enum FileTarget {
+    /// Opened as a new canvas
+    Canvas,
+    /// Sliced as a spritesheet, after asking how
+    Spritesheet,
+    /// Loaded as the palette
+    Palette,
+}
+
+/// Convert palette color to an egui color
+fn swatch_color(color: [u8; 3]) -> egui::Color32 {
+    egui::Color32::from_rgb(color[0], color[1], color[2])
+}
+
+/// Convert palette color to a brush color
+fn palette_to_rgba(color: [u8; 3]) -> [f32; 4] {
+    [
+        color[0] as f32 / 255.,
+        color[1] as f32 / 255.,
+        color[2] as f32 / 255.,
+        1.,
+    ]
+}
+
+/// Convert brush color to a palette color, ignoring alpha
+fn rgba_to_palette(color: [f32; 4]) -> [u8; 3] {
+    [
+        (color[0] * 255.).round() as u8,
+        (color[1] * 255.).round() as u8,
+        (color[2] * 255.).round() as u8,
+    ]
+}
+
+/// Draw choice of symmetry mode, number of radial copies and center, which is kept on the edges or centers of pixels of a canvas of width and height
+fn symmetry_ui(ui: &mut egui::Ui, symmetry: &mut Symmetry, width: u16, height: u16) {
+    let was_off = symmetry.mode == SymmetryMode::Off;
+    for mode in SymmetryMode::iter() {
+        let text: &str = mode.into();
+        let clicked = ui
+            .selectable_value(&mut symmetry.mode, mode, text)
+            .clicked();
+        // start in the middle of the canvas
+        if clicked && was_off {
+            symmetry.center = (width as f32 / 2., height as f32 / 2.);
+        }
+    }
+    if symmetry.mode == SymmetryMode::Radial {
+        ui.add(
+            egui::DragValue::new(&mut symmetry.radial_count)
+                .range(2..=MAX_RADIAL_COUNT)
+                .prefix("copies "),
+        );
+    }
+    ui.add(
+        egui::DragValue::new(&mut symmetry.center.0)
+            .speed(0.5)
+            .range(0.0..=width as f32)
+            .prefix("x "),
+    );
+    ui.add(
+        egui::DragValue::new(&mut symmetry.center.1)
+            .speed(0.5)
+            .range(0.0..=height as f32)
+            .prefix("y "),
+    );
+    if ui.button("center").clicked() {
+        symmetry.center = (width as f32 / 2., height as f32 / 2.);
+    }
+    symmetry.center = (
+        (symmetry.center.0 * 2.).round() / 2.,
+        (symmetry.center.1 * 2.).round() / 2.,
+    );
+}
+
+/// Factors offered for upscaling
+const UPSCALE_FACTORS: [u8; 5] = [1, 2, 3, 4, 8];
+
+/// Draw choices of upscaler and factor. Factors the upscaler doesn't support are disabled
+fn upscale_options_ui(ui: &mut egui::Ui, options: &mut UpscaleOptions) {
+    ui.horizontal(|ui| {
+        for upscaler in Upscaler::iter() {
+            let text: &str = upscaler.into();
+            ui.selectable_value(&mut options.upscaler, upscaler, text);
+        }
+    });
+    ui.horizontal(|ui| {
+        for factor in UPSCALE_FACTORS {
+            ui.add_enabled_ui(options.upscaler.supports_factor(factor), |ui| {
+                ui.selectable_value(&mut options.factor, factor, format!("{}x", factor));
+            });
+        }
+    });
+    // switching upscaler can leave a factor it doesn't support
+    if !options.upscaler.supports_factor(options.factor) {
+        options.factor = 1;
+    }
+}
+
 fn get_new_canvas_name(canvases: &[Canvas]) -> String {
     // get a name for the new canvas (that isnt already used!!!!!)
     let mut canvas_name_index = canvases.len() + 1;
@@ -52,27 +253,19 @@ fn get_new_canvas_name(canvases: &[Canvas]) -> String {
     name
 }
 
-/// Works like macroquad's [Image::from_file_with_format], but uses our version of the `image` crate which has support enabled for image formats other than PNG
-///
-/// Also returns which [ImageFormat] was used
-fn image_from_bytes(bytes: &[u8]) -> Result<(Image, ImageFormat), image::ImageError> {
-    let format_guess = image::guess_format(bytes)?;
-    let dynamic_image = image::load_from_memory_with_format(bytes, format_guess)?;
-    let width = dynamic_image.width() as u16;
-    let height = dynamic_image.height() as u16;
-    let image_bytes = dynamic_image.to_rgba8().into_raw();
-    Ok((
-        Image {
-            bytes: image_bytes,
-            width,
-            height,
-        },
-        format_guess,
-    ))
+fn main() {
+    // with any arguments, run in command line mode without creating a window
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        if !args.is_empty() {
+            std::process::exit(cli::run(&args));
+        }
+    }
+    macroquad::Window::new("plow", editor());
 }
 
-#[macroquad::main("plow")]
-async fn main() {
+async fn editor() {
     let plow_header = format!("[plow {}]", env!("CARGO_PKG_VERSION"));
     println!("{}", plow_header);
 
@@ -92,6 +285,9 @@ async fn main() {
     // set up file picker
     let mut file_picker = FilePicker::new();
 
+    let mut clipboard = Clipboard::new();
+    let mut layer_textures = LayerTextures::new();
+
     let mut primary_color = DEFAULT_PRIMARY_COLOR;
     let mut secondary_color = DEFAULT_SECONDARY_COLOR;
 
@@ -109,37 +305,162 @@ async fn main() {
     let mut new_file_width = String::new();
     let mut new_file_height = String::new();
 
+    let mut resize_canvas_window_open = false;
+    let mut resize_canvas_width = String::new();
+    let mut resize_canvas_height = String::new();
+    // where existing image is placed when resizing, (0, 0) is top left and (2, 2) is bottom right
+    let mut resize_canvas_anchor = (1, 1);
+
+    let mut scale_image_window_open = false;
+    let mut scale_image_width = String::new();
+    let mut scale_image_height = String::new();
+    let mut scale_image_mode = ScaleMode::Nearest;
+    let mut upscale_window_open = false;
+    let mut upscale_options = UpscaleOptions {
+        upscaler: Upscaler::Scale2x,
+        factor: 2,
+    };
+
+    let mut export_animation_window_open = false;
+    let mut animation_options = AnimationOptions {
+        format: AnimationFormat::Gif,
+        source: AnimationSource::Frames,
+        plays: 0,
+        layer_duration: DEFAULT_FRAME_DURATION,
+    };
+
+    let mut export_spritesheet_window_open = false;
+    let mut spritesheet_source = AnimationSource::Frames;
+    let mut spritesheet_options = SpritesheetOptions {
+        columns: 0,
+        padding: 0,
+        margin: 0,
+    };
+
+    let mut file_target = FileTarget::Canvas;
+    // bytes and name of spritesheet to slice, while the slice window is open
+    let mut spritesheet_to_slice: Option<(Vec<u8>, String)> = None;
+    let mut slice_target = AnimationSource::Frames;
+    let mut slice_options = SliceOptions {
+        cell_width: 16,
+        cell_height: 16,
+        padding: 0,
+        margin: 0,
+        skip_empty: true,
+    };
+
     let mut rename_layer_window_open = false;
     let mut rename_layer_text = String::new();
 
+    // opacity of current layer when the opacity slider started being dragged, so the whole drag is a single undo action
+    let mut opacity_before_drag: Option<f32> = None;
+
+    // animation playback
+    let mut playing = false;
+    let mut playback_fps = DEFAULT_PLAYBACK_FPS;
+    // if true, frames are played back for their own duration instead of at playback_fps
+    let mut use_frame_durations = false;
+    // time since the current frame was shown during playback, in seconds
+    let mut playback_time = 0.;
+    let mut onion_skin = false;
+    // duration of current frame when its drag value started being dragged, so the whole drag is a single undo action
+    let mut duration_before_drag: Option<u32> = None;
+
+    let mut palette = Palette::new(String::from("pico-8"), DEFAULT_PALETTE.to_vec());
+    // index of the swatch that was clicked last, which remove and move buttons act on
+    let mut selected_swatch: Option<usize> = None;
+    // palette of indexed canvas when its selected color started being edited, so the whole edit is a single undo action
+    let mut palette_before_edit: Option<Palette> = None;
+
+    let mut extract_palette_window_open = false;
+    // if true, colors are extracted from all visible layers instead of the current layer
+    let mut extract_from_canvas = false;
+    let mut extract_max_colors = DEFAULT_EXTRACT_COLORS;
+    let mut remap_window_open = false;
+    let mut remap_dither = Dither::None;
+
+    let mut filter_window_open = false;
+    let mut active_filter = Filter::Invert;
+    let mut filter_settings = FilterSettings::default();
+    // if true, the filter is shown on the layer while its settings are changed
+    let mut filter_preview = true;
+
     let mut colors_window_open = true;
+    let mut palette_window_open = true;
     let mut tools_window_open = true;
     let mut layers_window_open = true;
     let mut history_window_open = false;
+    let mut timeline_window_open = false;
 
     loop {
         let mut typing_in_text_box = false;
         clear_background(BG_COLOR);
 
         // check if image has been loaded from file picker
-        if let FileInputResult::Data(data) = file_picker.update() {
-            println!("got data!");
-            let result = image_from_bytes(&data.bytes);
-
-            let (name_without_extension, _) = data
-                .name
-                .rsplit_once('.')
-                .unwrap_or((UNTITLED_NAME, UNTITLED_NAME));
-
-            if let Ok((image, format)) = result {
-                active_canvas = canvases.len();
-                canvases.push(
-                    Canvas::from_image(image, name_without_extension.to_string(), format).unwrap(),
-                );
-            } else {
-                let err = result.unwrap_err();
-                println!("image failed to load {}", err);
+        match file_picker.update() {
+            FileInputResult::Data(data) => {
+                println!("got data!");
+                let (name_without_extension, _) = data
+                    .name
+                    .rsplit_once('.')
+                    .unwrap_or((UNTITLED_NAME, UNTITLED_NAME));
+
+                match file_target {
+                    FileTarget::Canvas => {
+                        let result =
+                            canvas_from_bytes(&data.bytes, name_without_extension.to_string());
+
+                        match result {
+                            Ok(canvas) => {
+                                active_canvas = canvases.len();
+                                canvases.push(canvas);
+                            }
+                            Err(err) => {
+                                println!("image failed to load {}", err);
+                            }
+                        }
+                    }
+                    FileTarget::Spritesheet => {
+                        // ask how to slice it first
+                        spritesheet_to_slice =
+                            Some((data.bytes, name_without_extension.to_string()));
+                    }
+                    FileTarget::Palette => {
+                        let format = data
+                            .name
+                            .rsplit_once('.')
+                            .and_then(|(_, extension)| PaletteFormat::from_extension(extension));
+                        let result = match format {
+                            Some(format) => Palette::from_bytes(
+                                &data.bytes,
+                                format,
+                                name_without_extension.to_string(),
+                            ),
+                            None => Err(std::io::Error::other("unknown palette format")),
+                        };
+                        match result {
+                            // indexed canvases are converted to the loaded palette
+                            Ok(loaded) => {
+                                let canvas = &mut canvases[active_canvas];
+                                if canvas.palette.is_some() {
+                                    if let Err(err) = canvas.set_color_mode(Some(loaded)) {
+                                        println!("palette failed to load {}", err);
+                                    }
+                                } else {
+                                    palette = loaded;
+                                }
+                                selected_swatch = None;
+                            }
+                            Err(err) => {
+                                println!("palette failed to load {}", err);
+                            }
+                        }
+                    }
+                }
+                file_target = FileTarget::Canvas;
             }
+            FileInputResult::Canceled => file_target = FileTarget::Canvas,
+            _ => {}
         }
         // if alt was released restore previous tool from color picker'
         if is_key_released(KeyCode::LeftAlt) {
@@ -149,7 +470,10 @@ async fn main() {
             tool_before_holding_alt = None;
         }
         // if alt pressed, change tool to color picker
-        if is_key_pressed(KeyCode::LeftAlt) && tool_before_holding_alt.is_none() {
+        if is_key_pressed(KeyCode::LeftAlt)
+            && tool_before_holding_alt.is_none()
+            && !active_tool.uses_alt()
+        {
             tool_before_holding_alt = Some(active_tool);
             active_tool = &tools[3];
         }
@@ -193,6 +517,12 @@ async fn main() {
                         };
                         if ui.button("open").clicked() {
                             ui.close_menu();
+                            file_target = FileTarget::Canvas;
+                            file_picker.open_dialog();
+                        }
+                        if ui.button("import spritesheet").clicked() {
+                            ui.close_menu();
+                            file_target = FileTarget::Spritesheet;
                             file_picker.open_dialog();
                         }
                         // add "save" button only on standalone
@@ -201,20 +531,165 @@ async fn main() {
                         {
                             if ui.button("save").clicked() {
                                 ui.close_menu();
-                                canvases[active_canvas].export(true);
+                                export(&mut canvases[active_canvas], true);
                             }
                         }
                         if ui.button("save as").clicked() {
                             ui.close_menu();
-                            canvases[active_canvas].export(false);
+                            export(&mut canvases[active_canvas], false);
+                        }
+                        ui.menu_button("export as", |ui| {
+                            if ui.button("plow project").clicked() {
+                                ui.close_menu();
+                                export_as(&mut canvases[active_canvas], FileFormat::Project);
+                            }
+                            if ui.button("openraster").clicked() {
+                                ui.close_menu();
+                                export_as(&mut canvases[active_canvas], FileFormat::OpenRaster);
+                            }
+                            if ui.button("image").clicked() {
+                                ui.close_menu();
+                                // keep the current image format, or use png if canvas is layered
+                                let format = match canvases[active_canvas].preffered_file_format {
+                                    FileFormat::Image(format) => format,
+                                    _ => ImageFormat::Png,
+                                };
+                                export_as(&mut canvases[active_canvas], FileFormat::Image(format));
+                            }
+                            if ui.button("animation").clicked() {
+                                ui.close_menu();
+                                export_animation_window_open = true;
+                            }
+                            if ui.button("spritesheet").clicked() {
+                                ui.close_menu();
+                                export_spritesheet_window_open = true;
+                            }
+                        });
+                        ui.menu_button("export scale", |ui| {
+                            ui.label("images and animations are upscaled when saved");
+                            upscale_options_ui(ui, &mut canvases[active_canvas].export_upscale);
+                        });
+                    });
+                    ui.menu_button("edit", |ui| {
+                        if ui.button("cut").on_hover_text("ctrl+x").clicked() {
+                            ui.close_menu();
+                            if let Some(image) = canvases[active_canvas].cut() {
+                                clipboard.set(image);
+                            }
+                        }
+                        if ui.button("copy").on_hover_text("ctrl+c").clicked() {
+                            ui.close_menu();
+                            if let Some(image) = canvases[active_canvas].copy() {
+                                clipboard.set(image);
+                            }
+                        }
+                        if ui.button("paste").on_hover_text("ctrl+v").clicked() {
+                            ui.close_menu();
+                            if let Some(image) = clipboard.get() {
+                                canvases[active_canvas].paste(image);
+                            }
+                        }
+                    });
+                    ui.menu_button("select", |ui| {
+                        if ui.button("all").on_hover_text("ctrl+a").clicked() {
+                            ui.close_menu();
+                            canvases[active_canvas].select_all();
+                        }
+                        if ui.button("none").on_hover_text("ctrl+d").clicked() {
+                            ui.close_menu();
+                            canvases[active_canvas].deselect();
+                        }
+                        if ui.button("invert").on_hover_text("ctrl+shift+i").clicked() {
+                            ui.close_menu();
+                            canvases[active_canvas].invert_selection();
+                        }
+                    });
+                    ui.menu_button("image", |ui| {
+                        if ui.button("resize canvas").clicked() {
+                            ui.close_menu();
+                            resize_canvas_window_open = true;
+                            resize_canvas_width = canvases[active_canvas].width.to_string();
+                            resize_canvas_height = canvases[active_canvas].height.to_string();
+                        }
+                        if ui.button("scale image").clicked() {
+                            ui.close_menu();
+                            scale_image_window_open = true;
+                            scale_image_width = canvases[active_canvas].width.to_string();
+                            scale_image_height = canvases[active_canvas].height.to_string();
+                        }
+                        if ui.button("upscale").clicked() {
+                            ui.close_menu();
+                            upscale_window_open = true;
+                        }
+                        ui.separator();
+                        if ui.button("extract palette").clicked() {
+                            ui.close_menu();
+                            extract_palette_window_open = true;
+                        }
+                        let indexed = canvases[active_canvas].palette.is_some();
+                        if ui
+                            .add_enabled(!indexed, egui::Button::new("remap to palette"))
+                            .clicked()
+                        {
+                            ui.close_menu();
+                            remap_window_open = true;
+                        }
+                        ui.separator();
+                        if indexed {
+                            if ui.button("convert to rgba").clicked() {
+                                ui.close_menu();
+                                if let Err(err) = canvases[active_canvas].set_color_mode(None) {
+                                    println!("failed to convert to rgba {}", err);
+                                }
+                            }
+                        } else if ui
+                            .add_enabled(
+                                !palette.colors.is_empty(),
+                                egui::Button::new("convert to indexed"),
+                            )
+                            .on_hover_text(
+                                "store palette indices instead of colors, using the palette",
+                            )
+                            .clicked()
+                        {
+                            ui.close_menu();
+                            let result =
+                                canvases[active_canvas].set_color_mode(Some(palette.clone()));
+                            if let Err(err) = result {
+                                println!("failed to convert to indexed {}", err);
+                            }
+                        }
+                    });
+                    ui.menu_button("filters", |ui| {
+                        for filter in Filter::iter() {
+                            let text: &str = filter.into();
+                            if ui.button(text).clicked() {
+                                ui.close_menu();
+                                active_filter = filter;
+                                filter_window_open = true;
+                            }
                         }
                     });
                     ui.menu_button("view", |ui| {
                         // ugly code, ui window problem x2
                         ui.checkbox(&mut tools_window_open, "tools");
                         ui.checkbox(&mut colors_window_open, "colors");
+                        ui.checkbox(&mut palette_window_open, "palette");
                         ui.checkbox(&mut layers_window_open, "layers");
                         ui.checkbox(&mut history_window_open, "history");
+                        ui.checkbox(&mut timeline_window_open, "timeline");
+                        ui.separator();
+                        ui.checkbox(&mut canvases[active_canvas].tile_mode, "tile mode")
+                            .on_hover_text("strokes and fills wrap around the edges, and the canvas is shown repeated around itself");
+                    });
+                    ui.menu_button("symmetry", |ui| {
+                        let canvas = &canvases[active_canvas];
+                        symmetry_ui(
+                            ui,
+                            &mut tools_settings.symmetry,
+                            canvas.width,
+                            canvas.height,
+                        );
                     });
                     ui.separator();
                     active_tool.draw_buttons(ui, &mut tools_settings);
@@ -251,6 +726,141 @@ async fn main() {
                     ui.color_edit_button_rgba_unmultiplied(&mut secondary_color);
                 });
             }
+            if palette_window_open {
+                new_general_window("palette", &mut palette_window_open)
+                    .default_width(PALETTE_WINDOW_WIDTH)
+                    .show(egui_ctx, |ui| {
+                        let canvas = &mut canvases[active_canvas];
+                        // in indexed color mode, the canvas palette is shown and edited instead
+                        let indexed = canvas.palette.is_some();
+                        let shown = canvas.palette.as_mut().unwrap_or(&mut palette);
+                        let selected = selected_swatch.filter(|index| *index < shown.colors.len());
+                        ui.label(if indexed {
+                            format!("{} (indexed)", shown.name)
+                        } else {
+                            shown.name.clone()
+                        });
+                        ui.horizontal_wrapped(|ui| {
+                            ui.spacing_mut().item_spacing = egui::vec2(2., 2.);
+                            for (index, color) in shown.colors.iter().enumerate() {
+                                let mut swatch = egui::Button::new("")
+                                    .fill(swatch_color(*color))
+                                    .min_size(egui::vec2(PALETTE_SWATCH_SIZE, PALETTE_SWATCH_SIZE));
+                                // outline selected swatch
+                                if selected == Some(index) {
+                                    swatch =
+                                        swatch.stroke(egui::Stroke::new(2., egui::Color32::WHITE));
+                                }
+                                let swatch = ui
+                                    .add(swatch)
+                                    .on_hover_text(format!("#{}", to_hex_color(*color)));
+                                // left click sets primary color, right click sets secondary color
+                                if swatch.clicked() {
+                                    primary_color = palette_to_rgba(*color);
+                                    selected_swatch = Some(index);
+                                }
+                                if swatch.secondary_clicked() {
+                                    secondary_color = palette_to_rgba(*color);
+                                    selected_swatch = Some(index);
+                                }
+                            }
+                        });
+                        ui.separator();
+                        // palette before a color was added, since adding to an indexed canvas can be undone
+                        let mut added_to = None;
+                        let mut remove = None;
+                        let mut move_to = None;
+                        ui.horizontal(|ui| {
+                            if ui
+                                .add_enabled(
+                                    !indexed || shown.colors.len() < INDEXED_MAX_COLORS,
+                                    egui::Button::new("add"),
+                                )
+                                .on_hover_text("add primary color")
+                                .clicked()
+                            {
+                                added_to = Some(shown.clone());
+                                shown.colors.push(rgba_to_palette(primary_color));
+                                selected_swatch = Some(shown.colors.len() - 1);
+                            }
+                            if let Some(index) = selected {
+                                // editing a color of an indexed canvas recolors every pixel using it, as a single undo action once the color picker is closed
+                                let old_palette = shown.clone();
+                                if ui
+                                    .color_edit_button_srgb(&mut shown.colors[index])
+                                    .changed()
+                                    && palette_before_edit.is_none()
+                                {
+                                    palette_before_edit = Some(old_palette);
+                                }
+                                if ui
+                                    .add_enabled(
+                                        !indexed || shown.colors.len() > 1,
+                                        egui::Button::new("remove"),
+                                    )
+                                    .clicked()
+                                {
+                                    remove = Some(index);
+                                }
+                                if ui.add_enabled(index > 0, egui::Button::new("<")).clicked() {
+                                    move_to = Some((index, index - 1));
+                                }
+                                if ui
+                                    .add_enabled(
+                                        index + 1 < shown.colors.len(),
+                                        egui::Button::new(">"),
+                                    )
+                                    .clicked()
+                                {
+                                    move_to = Some((index, index + 1));
+                                }
+                            }
+                        });
+                        ui.horizontal(|ui| {
+                            if ui.button("load").clicked() {
+                                file_target = FileTarget::Palette;
+                                file_picker.open_dialog();
+                            }
+                            ui.menu_button("save", |ui| {
+                                for format in PaletteFormat::iter() {
+                                    if ui.button(format.extension()).clicked() {
+                                        ui.close_menu();
+                                        export_palette(shown, format);
+                                    }
+                                }
+                            });
+                        });
+                        if let Some(old_palette) = added_to {
+                            if indexed {
+                                canvas.palette_changed(old_palette);
+                            }
+                        }
+                        if !ui.memory(|memory| memory.any_popup_open()) {
+                            if let Some(old_palette) = palette_before_edit.take() {
+                                if indexed {
+                                    canvas.palette_changed(old_palette);
+                                }
+                            }
+                        }
+                        // removing and moving colors of indexed canvases changes the indices of pixels
+                        if let Some(index) = remove {
+                            if indexed {
+                                canvas.remove_palette_color(index);
+                            } else {
+                                palette.colors.remove(index);
+                            }
+                            selected_swatch = None;
+                        }
+                        if let Some((from, to)) = move_to {
+                            if indexed {
+                                canvas.move_palette_color(from, to);
+                            } else {
+                                palette.colors.swap(from, to);
+                            }
+                            selected_swatch = Some(to);
+                        }
+                    });
+            }
 
             if history_window_open {
                 new_general_window("history", &mut history_window_open)
@@ -258,8 +868,9 @@ async fn main() {
                     .anchor(egui::Align2::RIGHT_TOP, egui::vec2(0., 0.))
                     .show(egui_ctx, |ui| {
                         let mut undo_to = None;
+                        let mut redo_to = None;
                         egui::ScrollArea::new([false, true]).show(ui, |ui| {
-                            for (index, action) in
+                            for (index, (_, action)) in
                                 canvases[active_canvas].undo_history.iter().enumerate()
                             {
                                 let text: &str = action.into();
@@ -267,6 +878,19 @@ async fn main() {
                                     undo_to = Some(index);
                                 }
                             }
+                            // show redo entries greyed out, in the order they would be redone
+                            for (index, (_, action)) in canvases[active_canvas]
+                                .redo_history
+                                .iter()
+                                .rev()
+                                .enumerate()
+                            {
+                                let text: &str = action.into();
+                                let text = egui::RichText::new(text).weak();
+                                if ui.button(text).clicked() {
+                                    redo_to = Some(index);
+                                }
+                            }
                         });
                         if let Some(undo_to) = undo_to {
                             let undo_to = canvases[active_canvas].undo_history.len() - undo_to;
@@ -274,6 +898,11 @@ async fn main() {
                                 canvases[active_canvas].undo();
                             }
                         }
+                        if let Some(redo_to) = redo_to {
+                            for _ in 0..redo_to + 1 {
+                                canvases[active_canvas].redo();
+                            }
+                        }
                     });
             }
 
@@ -369,6 +998,123 @@ async fn main() {
                                 canvases[active_canvas].merge_layers_down();
                             }
                         });
+                        ui.horizontal(|ui| {
+                            let canvas = &mut canvases[active_canvas];
+                            let layer = &mut canvas.layers[canvas.current_layer];
+                            let old_opacity = layer.opacity;
+                            let old_blend_mode = layer.blend_mode;
+                            let slider = ui.add(
+                                egui::Slider::new(&mut layer.opacity, 0.0..=1.0).text("opacity"),
+                            );
+                            let blend_mode_text: &str = layer.blend_mode.into();
+                            egui::ComboBox::from_id_salt("blend mode")
+                                .selected_text(blend_mode_text)
+                                .show_ui(ui, |ui| {
+                                    for mode in BlendMode::iter() {
+                                        let text: &str = mode.into();
+                                        ui.selectable_value(&mut layer.blend_mode, mode, text);
+                                    }
+                                });
+                            if slider.drag_started() {
+                                opacity_before_drag = Some(old_opacity);
+                            }
+                            if slider.drag_stopped() {
+                                if let Some(opacity) = opacity_before_drag.take() {
+                                    canvas.layer_properties_changed(opacity, old_blend_mode);
+                                }
+                            } else if !slider.dragged() {
+                                canvas.layer_properties_changed(old_opacity, old_blend_mode);
+                            }
+                        });
+                    });
+            }
+            if timeline_window_open {
+                // timeline window
+                new_general_window("timeline", &mut timeline_window_open)
+                    .anchor(egui::Align2::CENTER_BOTTOM, egui::vec2(0., 0.))
+                    .show(egui_ctx, |ui| {
+                        let canvas = &mut canvases[active_canvas];
+                        egui::ScrollArea::horizontal().show(ui, |ui| {
+                            ui.horizontal(|ui| {
+                                for index in 0..canvas.frames.len() {
+                                    let mut button = ui.button((index + 1).to_string());
+                                    if index == canvas.current_frame {
+                                        button = button.highlight();
+                                    }
+                                    if button.clicked() {
+                                        canvas.set_frame(index);
+                                    }
+                                }
+                            });
+                        });
+                        ui.separator();
+                        ui.horizontal(|ui| {
+                            if ui.button("new frame").clicked() {
+                                canvas.new_frame();
+                            }
+                            if ui
+                                .add_enabled(
+                                    canvas.frames.len() > 1,
+                                    egui::Button::new("delete frame"),
+                                )
+                                .clicked()
+                            {
+                                canvas.delete_frame();
+                            }
+                            if ui.button("duplicate frame").clicked() {
+                                canvas.duplicate_frame();
+                            }
+                            // move current frame earlier or later
+                            if ui
+                                .add_enabled(canvas.current_frame > 0, egui::Button::new("<"))
+                                .clicked()
+                            {
+                                canvas.move_frame(canvas.current_frame - 1);
+                            }
+                            if ui
+                                .add_enabled(
+                                    canvas.current_frame < canvas.frames.len() - 1,
+                                    egui::Button::new(">"),
+                                )
+                                .clicked()
+                            {
+                                canvas.move_frame(canvas.current_frame + 1);
+                            }
+                        });
+                        ui.horizontal(|ui| {
+                            let frame = &mut canvas.frames[canvas.current_frame];
+                            let old_duration = frame.duration;
+                            let drag_value = ui.add(
+                                egui::DragValue::new(&mut frame.duration)
+                                    .range(1..=60000)
+                                    .suffix(" ms"),
+                            );
+                            if drag_value.drag_started() {
+                                duration_before_drag = Some(old_duration);
+                            }
+                            if drag_value.drag_stopped() {
+                                if let Some(duration) = duration_before_drag.take() {
+                                    canvas.frame_duration_changed(duration);
+                                }
+                            } else if !drag_value.dragged() {
+                                canvas.frame_duration_changed(old_duration);
+                            }
+                            ui.label("duration");
+                        });
+                        ui.horizontal(|ui| {
+                            if ui.button(if playing { "pause" } else { "play" }).clicked() {
+                                playing = !playing;
+                                playback_time = 0.;
+                            }
+                            ui.add_enabled(
+                                !use_frame_durations,
+                                egui::DragValue::new(&mut playback_fps)
+                                    .range(1..=60)
+                                    .suffix(" fps"),
+                            );
+                            ui.checkbox(&mut use_frame_durations, "use frame durations");
+                            ui.checkbox(&mut onion_skin, "onion skin");
+                        });
                     });
             }
             // draw rename layer window
@@ -439,21 +1185,526 @@ async fn main() {
                     });
             }
 
+            // draw resize canvas window
+            if resize_canvas_window_open {
+                egui::Window::new("resize canvas")
+                    .collapsible(false)
+                    .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0., 0.))
+                    .show(egui_ctx, |ui| {
+                        egui::Grid::new("resize canvas input")
+                            .num_columns(2)
+                            .show(ui, |ui| {
+                                ui.label("width");
+                                typing_in_text_box |=
+                                    ui.text_edit_singleline(&mut resize_canvas_width).changed();
+                                ui.end_row();
+                                ui.label("height");
+                                typing_in_text_box |=
+                                    ui.text_edit_singleline(&mut resize_canvas_height).changed();
+                                ui.end_row();
+                                ui.label("anchor");
+                                egui::Grid::new("resize canvas anchor").show(ui, |ui| {
+                                    for y in 0..3 {
+                                        for x in 0..3 {
+                                            ui.selectable_value(
+                                                &mut resize_canvas_anchor,
+                                                (x, y),
+                                                "  ",
+                                            );
+                                        }
+                                        ui.end_row();
+                                    }
+                                });
+                                ui.end_row();
+                                if ui.button("okay").clicked() {
+                                    if let (Ok(width), Ok(height)) =
+                                        (resize_canvas_width.parse(), resize_canvas_height.parse())
+                                    {
+                                        let anchor = (
+                                            resize_canvas_anchor.0 as f32 / 2.,
+                                            resize_canvas_anchor.1 as f32 / 2.,
+                                        );
+                                        match canvases[active_canvas]
+                                            .resize_canvas(width, height, anchor)
+                                        {
+                                            Ok(()) => resize_canvas_window_open = false,
+                                            Err(err) => println!("failed to resize canvas {}", err),
+                                        }
+                                    }
+                                };
+                                if ui.button("cancel").clicked() {
+                                    resize_canvas_window_open = false;
+                                };
+                            });
+                    });
+            }
+            // draw export animation window
+            if export_animation_window_open {
+                egui::Window::new("export animation")
+                    .collapsible(false)
+                    .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0., 0.))
+                    .show(egui_ctx, |ui| {
+                        egui::Grid::new("export animation input")
+                            .num_columns(2)
+                            .show(ui, |ui| {
+                                ui.label("format");
+                                ui.horizontal(|ui| {
+                                    for format in AnimationFormat::iter() {
+                                        let text: &str = format.into();
+                                        ui.selectable_value(
+                                            &mut animation_options.format,
+                                            format,
+                                            text,
+                                        );
+                                    }
+                                });
+                                ui.end_row();
+                                ui.label("frames from");
+                                ui.horizontal(|ui| {
+                                    for source in AnimationSource::iter() {
+                                        let text: &str = source.into();
+                                        ui.selectable_value(
+                                            &mut animation_options.source,
+                                            source,
+                                            text,
+                                        );
+                                    }
+                                });
+                                ui.end_row();
+                                ui.label("plays");
+                                ui.add(egui::DragValue::new(&mut animation_options.plays))
+                                    .on_hover_text("0 loops forever");
+                                ui.end_row();
+                                ui.label("layer duration");
+                                ui.add_enabled(
+                                    animation_options.source == AnimationSource::Layers,
+                                    egui::DragValue::new(&mut animation_options.layer_duration)
+                                        .range(1..=60000)
+                                        .suffix(" ms"),
+                                );
+                                ui.end_row();
+                                if ui.button("okay").clicked() {
+                                    export_animation_window_open = false;
+                                    export_as(
+                                        &mut canvases[active_canvas],
+                                        FileFormat::Animation(animation_options),
+                                    );
+                                };
+                                if ui.button("cancel").clicked() {
+                                    export_animation_window_open = false;
+                                };
+                            });
+                    });
+            }
+            // draw export spritesheet window
+            if export_spritesheet_window_open {
+                egui::Window::new("export spritesheet")
+                    .collapsible(false)
+                    .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0., 0.))
+                    .show(egui_ctx, |ui| {
+                        egui::Grid::new("export spritesheet input")
+                            .num_columns(2)
+                            .show(ui, |ui| {
+                                ui.label("sprites from");
+                                ui.horizontal(|ui| {
+                                    for source in AnimationSource::iter() {
+                                        let text: &str = source.into();
+                                        ui.selectable_value(&mut spritesheet_source, source, text);
+                                    }
+                                });
+                                ui.end_row();
+                                ui.label("columns");
+                                ui.add(egui::DragValue::new(&mut spritesheet_options.columns))
+                                    .on_hover_text("0 makes the sheet as square as possible");
+                                ui.end_row();
+                                ui.label("padding");
+                                ui.add(egui::DragValue::new(&mut spritesheet_options.padding));
+                                ui.end_row();
+                                ui.label("margin");
+                                ui.add(egui::DragValue::new(&mut spritesheet_options.margin));
+                                ui.end_row();
+                                if ui.button("okay").clicked() {
+                                    export_spritesheet_window_open = false;
+                                    export_spritesheet(
+                                        &canvases[active_canvas],
+                                        spritesheet_source,
+                                        &spritesheet_options,
+                                    );
+                                };
+                                if ui.button("cancel").clicked() {
+                                    export_spritesheet_window_open = false;
+                                };
+                            });
+                    });
+            }
+            // draw slice spritesheet window
+            if spritesheet_to_slice.is_some() {
+                egui::Window::new("import spritesheet")
+                    .collapsible(false)
+                    .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0., 0.))
+                    .show(egui_ctx, |ui| {
+                        egui::Grid::new("import spritesheet input")
+                            .num_columns(2)
+                            .show(ui, |ui| {
+                                ui.label("cell width");
+                                ui.add(
+                                    egui::DragValue::new(&mut slice_options.cell_width)
+                                        .range(1..=u16::MAX),
+                                );
+                                ui.end_row();
+                                ui.label("cell height");
+                                ui.add(
+                                    egui::DragValue::new(&mut slice_options.cell_height)
+                                        .range(1..=u16::MAX),
+                                );
+                                ui.end_row();
+                                ui.label("padding");
+                                ui.add(egui::DragValue::new(&mut slice_options.padding));
+                                ui.end_row();
+                                ui.label("margin");
+                                ui.add(egui::DragValue::new(&mut slice_options.margin));
+                                ui.end_row();
+                                ui.label("cells into");
+                                ui.horizontal(|ui| {
+                                    for target in AnimationSource::iter() {
+                                        let text: &str = target.into();
+                                        ui.selectable_value(&mut slice_target, target, text);
+                                    }
+                                });
+                                ui.end_row();
+                                ui.checkbox(&mut slice_options.skip_empty, "skip empty cells");
+                                ui.end_row();
+                                if ui.button("okay").clicked() {
+                                    let (bytes, name) = spritesheet_to_slice.as_ref().unwrap();
+                                    // keep window open on failure, so the cell size can be fixed
+                                    match canvas_from_spritesheet(
+                                        bytes,
+                                        name.clone(),
+                                        slice_target,
+                                        &slice_options,
+                                    ) {
+                                        Ok(canvas) => {
+                                            active_canvas = canvases.len();
+                                            canvases.push(canvas);
+                                            spritesheet_to_slice = None;
+                                        }
+                                        Err(err) => {
+                                            println!("spritesheet failed to load {}", err);
+                                        }
+                                    }
+                                };
+                                if ui.button("cancel").clicked() {
+                                    spritesheet_to_slice = None;
+                                };
+                            });
+                    });
+            }
+            // draw scale image window
+            if scale_image_window_open {
+                egui::Window::new("scale image")
+                    .collapsible(false)
+                    .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0., 0.))
+                    .show(egui_ctx, |ui| {
+                        egui::Grid::new("scale image input")
+                            .num_columns(2)
+                            .show(ui, |ui| {
+                                ui.label("width");
+                                typing_in_text_box |=
+                                    ui.text_edit_singleline(&mut scale_image_width).changed();
+                                ui.end_row();
+                                ui.label("height");
+                                typing_in_text_box |=
+                                    ui.text_edit_singleline(&mut scale_image_height).changed();
+                                ui.end_row();
+                                ui.label("mode");
+                                ui.horizontal(|ui| {
+                                    for mode in ScaleMode::iter() {
+                                        let text: &str = mode.into();
+                                        ui.selectable_value(&mut scale_image_mode, mode, text);
+                                    }
+                                });
+                                ui.end_row();
+                                if ui.button("okay").clicked() {
+                                    if let (Ok(width), Ok(height)) =
+                                        (scale_image_width.parse(), scale_image_height.parse())
+                                    {
+                                        match canvases[active_canvas].scale_image(
+                                            width,
+                                            height,
+                                            scale_image_mode,
+                                        ) {
+                                            Ok(()) => scale_image_window_open = false,
+                                            Err(err) => println!("failed to scale image {}", err),
+                                        }
+                                    }
+                                };
+                                if ui.button("cancel").clicked() {
+                                    scale_image_window_open = false;
+                                };
+                            });
+                    });
+            }
+            if upscale_window_open {
+                new_general_window("upscale", &mut upscale_window_open)
+                    .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0., 0.))
+                    .show(egui_ctx, |ui| {
+                        upscale_options_ui(ui, &mut upscale_options);
+                        let canvas = &canvases[active_canvas];
+                        match upscaled_size(canvas.width, canvas.height, upscale_options) {
+                            Ok((width, height)) => {
+                                ui.label(format!("new size {}×{}", width, height));
+                                if ui.button("upscale").clicked() {
+                                    if let Err(err) =
+                                        canvases[active_canvas].upscale(upscale_options)
+                                    {
+                                        println!("failed to upscale {}", err);
+                                    }
+                                }
+                            }
+                            Err(err) => {
+                                ui.label(err.to_string());
+                            }
+                        }
+                    });
+            }
+            if extract_palette_window_open {
+                new_general_window("extract palette", &mut extract_palette_window_open)
+                    .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0., 0.))
+                    .show(egui_ctx, |ui| {
+                        egui::Grid::new("extract palette input")
+                            .num_columns(2)
+                            .show(ui, |ui| {
+                                ui.label("from");
+                                ui.horizontal(|ui| {
+                                    ui.selectable_value(&mut extract_from_canvas, false, "layer");
+                                    ui.selectable_value(&mut extract_from_canvas, true, "canvas");
+                                });
+                                ui.end_row();
+                                ui.label("max colors");
+                                ui.add(egui::DragValue::new(&mut extract_max_colors))
+                                    .on_hover_text("0 keeps every color");
+                                ui.end_row();
+                            });
+                        if ui.button("extract").clicked() {
+                            let canvas = &mut canvases[active_canvas];
+                            let image = if extract_from_canvas {
+                                canvas.to_image()
+                            } else {
+                                canvas.decode_image(&canvas.layers[canvas.current_layer].image)
+                            };
+                            let extracted = Palette::new(
+                                canvas.name.clone(),
+                                extract_colors(&image, extract_max_colors),
+                            );
+                            // indexed canvases are converted to the extracted palette
+                            if canvas.palette.is_some() {
+                                if let Err(err) = canvas.set_color_mode(Some(extracted)) {
+                                    println!("failed to extract palette {}", err);
+                                }
+                            } else {
+                                palette = extracted;
+                            }
+                            selected_swatch = None;
+                            palette_window_open = true;
+                        }
+                    });
+            }
+            if filter_window_open {
+                let title: &str = active_filter.into();
+                // the window can't be closed from inside it, since it borrows filter_window_open
+                let mut close_filter_window = false;
+                new_general_window(title, &mut filter_window_open)
+                    .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0., 0.))
+                    .show(egui_ctx, |ui| {
+                        let settings = &mut filter_settings;
+                        match active_filter {
+                            Filter::Invert | Filter::Grayscale => {
+                                ui.label("no settings");
+                            }
+                            Filter::BrightnessContrast => {
+                                ui.add(
+                                    egui::Slider::new(&mut settings.brightness, -1.0..=1.0)
+                                        .text("brightness"),
+                                );
+                                ui.add(
+                                    egui::Slider::new(&mut settings.contrast, -1.0..=1.0)
+                                        .text("contrast"),
+                                );
+                            }
+                            Filter::HueSaturationLightness => {
+                                ui.add(
+                                    egui::Slider::new(&mut settings.hue, -180.0..=180.0)
+                                        .text("hue"),
+                                );
+                                ui.add(
+                                    egui::Slider::new(&mut settings.saturation, -1.0..=1.0)
+                                        .text("saturation"),
+                                );
+                                ui.add(
+                                    egui::Slider::new(&mut settings.lightness, -1.0..=1.0)
+                                        .text("lightness"),
+                                );
+                            }
+                            Filter::Posterize => {
+                                ui.add(
+                                    egui::Slider::new(&mut settings.posterize_levels, 2..=16)
+                                        .text("levels"),
+                                );
+                            }
+                            Filter::Threshold => {
+                                ui.add(
+                                    egui::Slider::new(&mut settings.threshold, 0..=255)
+                                        .text("threshold"),
+                                );
+                            }
+                            Filter::Levels => {
+                                ui.add(
+                                    egui::Slider::new(&mut settings.levels_black, 0..=255)
+                                        .text("black"),
+                                );
+                                ui.add(
+                                    egui::Slider::new(&mut settings.levels_white, 0..=255)
+                                        .text("white"),
+                                );
+                                ui.add(
+                                    egui::Slider::new(&mut settings.levels_gamma, 0.1..=10.0)
+                                        .logarithmic(true)
+                                        .text("gamma"),
+                                );
+                            }
+                            Filter::Curves => {
+                                // a vertical slider for the output of each point, from darkest to brightest input
+                                ui.horizontal(|ui| {
+                                    for point in settings.curve.iter_mut() {
+                                        ui.add(egui::Slider::new(point, 0.0..=1.0).vertical());
+                                    }
+                                });
+                            }
+                            Filter::GaussianBlur | Filter::BoxBlur => {
+                                ui.add(
+                                    egui::Slider::new(&mut settings.blur_radius, 0..=64)
+                                        .text("radius"),
+                                );
+                            }
+                            Filter::UnsharpMask => {
+                                ui.add(
+                                    egui::Slider::new(&mut settings.blur_radius, 1..=64)
+                                        .text("radius"),
+                                );
+                                ui.add(
+                                    egui::Slider::new(&mut settings.unsharp_amount, 0.0..=5.0)
+                                        .text("amount"),
+                                );
+                            }
+                            Filter::Sharpen | Filter::Emboss | Filter::EdgeDetect => {}
+                            Filter::CustomKernel => {
+                                ui.horizontal(|ui| {
+                                    ui.label("size");
+                                    for size in (1..=MAX_KERNEL_SIZE).step_by(2) {
+                                        ui.selectable_value(
+                                            &mut settings.kernel_size,
+                                            size,
+                                            format!("{size}×{size}"),
+                                        );
+                                    }
+                                });
+                                egui::Grid::new("kernel").show(ui, |ui| {
+                                    for y in 0..settings.kernel_size {
+                                        for x in 0..settings.kernel_size {
+                                            let index = settings.kernel_index(x, y);
+                                            ui.add(
+                                                egui::DragValue::new(&mut settings.kernel[index])
+                                                    .speed(0.1),
+                                            );
+                                        }
+                                        ui.end_row();
+                                    }
+                                });
+                                ui.checkbox(&mut settings.kernel_normalize, "normalize");
+                            }
+                        }
+                        if active_filter.is_spatial() {
+                            ui.horizontal(|ui| {
+                                ui.label("edges");
+                                for edge_mode in EdgeMode::iter() {
+                                    let text: &str = edge_mode.into();
+                                    ui.selectable_value(&mut settings.edge_mode, edge_mode, text);
+                                }
+                            });
+                        }
+                        if ui.button("reset").clicked() {
+                            *settings = FilterSettings::default();
+                        }
+                        ui.separator();
+                        ui.checkbox(&mut filter_preview, "preview");
+                        ui.horizontal(|ui| {
+                            if ui.button("apply").clicked() {
+                                canvases[active_canvas].apply_filter(active_filter, *settings);
+                                close_filter_window = true;
+                            }
+                            if ui.button("cancel").clicked() {
+                                close_filter_window = true;
+                            }
+                        });
+                    });
+                if close_filter_window {
+                    filter_window_open = false;
+                }
+            }
+            if remap_window_open {
+                new_general_window("remap to palette", &mut remap_window_open)
+                    .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0., 0.))
+                    .show(egui_ctx, |ui| {
+                        ui.horizontal(|ui| {
+                            ui.label("dither");
+                            for dither in Dither::iter() {
+                                let text: &str = dither.into();
+                                ui.selectable_value(&mut remap_dither, dither, text);
+                            }
+                        });
+                        let indexed = canvases[active_canvas].palette.is_some();
+                        if ui
+                            .add_enabled(
+                                !palette.colors.is_empty() && !indexed,
+                                egui::Button::new("remap"),
+                            )
+                            .on_hover_text(
+                                "replace each pixel of the layer with the nearest palette color",
+                            )
+                            .clicked()
+                        {
+                            canvases[active_canvas].remap_to_palette(&palette.colors, remap_dither);
+                        }
+                    });
+            }
+
             mouse_over_ui = egui_ctx.is_pointer_over_area() || egui_ctx.is_using_pointer();
         });
 
         // check for pressed keybinds (when the user isnt typing in a text box)
         if !typing_in_text_box {
             // check if a tool's keybind has been pressed and if so make it active
+            // (ignore when holding ctrl, so that ctrl keybinds don't also change tool)
             for tool in &tools {
-                if tool.keybind().is_some() && is_key_pressed(tool.keybind().unwrap()) {
+                if tool.keybind().is_some()
+                    && is_key_pressed(tool.keybind().unwrap())
+                    && !is_key_down(KeyCode::LeftControl)
+                {
                     active_tool = tool;
                     break;
                 }
             }
             // x => swap primary and secondary color
-            if is_key_pressed(KeyCode::X) {
+            if is_key_pressed(KeyCode::X) && !is_key_down(KeyCode::LeftControl) {
                 (primary_color, secondary_color) = (secondary_color, primary_color)
+            }
+            // enter => place pasted image
+            else if is_key_pressed(KeyCode::Enter) {
+                canvases[active_canvas].commit_floating();
+            }
+            // escape => discard pasted image
+            else if is_key_pressed(KeyCode::Escape) {
+                canvases[active_canvas].cancel_floating();
             } else if is_key_down(KeyCode::LeftControl) {
                 // check ctrl + shift keybinds first
                 if is_key_down(KeyCode::LeftShift) {
@@ -471,7 +1722,15 @@ async fn main() {
                     }
                     // ctrl + shift + s => save/export (dont overwrite)
                     else if is_key_pressed(KeyCode::S) {
-                        canvases[active_canvas].export(false);
+                        export(&mut canvases[active_canvas], false);
+                    }
+                    // ctrl + shift + z => redo
+                    else if is_key_pressed(KeyCode::Z) {
+                        canvases[active_canvas].redo();
+                    }
+                    // ctrl + shift + i => invert selection
+                    else if is_key_pressed(KeyCode::I) {
+                        canvases[active_canvas].invert_selection();
                     }
                 }
                 // otherwise check ctrl keybinds
@@ -481,10 +1740,11 @@ async fn main() {
                 }
                 // ctrl + s => save/export (do overwrite if possible)
                 else if is_key_pressed(KeyCode::S) {
-                    canvases[active_canvas].export(true);
+                    export(&mut canvases[active_canvas], true);
                 }
                 // ctrl + o => open file
                 else if is_key_pressed(KeyCode::O) {
+                    file_target = FileTarget::Canvas;
                     file_picker.open_dialog();
                 }
                 // ctrl + n => create new file
@@ -497,9 +1757,44 @@ async fn main() {
                 else if is_key_pressed(KeyCode::Z) {
                     canvases[active_canvas].undo();
                 }
+                // ctrl + y => redo
+                else if is_key_pressed(KeyCode::Y) {
+                    canvases[active_canvas].redo();
+                }
+                // ctrl + a => select all
+                else if is_key_pressed(KeyCode::A) {
+                    canvases[active_canvas].select_all();
+                }
+                // ctrl + d => deselect
+                else if is_key_pressed(KeyCode::D) {
+                    canvases[active_canvas].deselect();
+                }
+                // ctrl + c => copy
+                else if is_key_pressed(KeyCode::C) {
+                    if let Some(image) = canvases[active_canvas].copy() {
+                        clipboard.set(image);
+                    }
+                }
+                // ctrl + x => cut
+                else if is_key_pressed(KeyCode::X) {
+                    if let Some(image) = canvases[active_canvas].cut() {
+                        clipboard.set(image);
+                    }
+                }
+                // ctrl + v => paste
+                else if is_key_pressed(KeyCode::V) {
+                    if let Some(image) = clipboard.get() {
+                        canvases[active_canvas].paste(image);
+                    }
+                }
             }
         }
 
+        // new canvases don't have a camera yet, since they can be created without a window
+        if canvases[active_canvas].camera_grid_size == 0. {
+            fit_camera(&mut canvases[active_canvas]);
+        }
+
         let scroll = mouse_wheel();
         let mouse = mouse_position();
 
@@ -510,10 +1805,19 @@ async fn main() {
         let cursor_y = ((mouse.1 + canvases[active_canvas].camera_y)
             / canvases[active_canvas].camera_grid_size)
             .floor() as i16;
-        let cursor_in_canvas = cursor_x >= 0
-            && (cursor_x) < canvases[active_canvas].width as i16
-            && cursor_y >= 0
-            && (cursor_y) < canvases[active_canvas].height as i16;
+        // in tile mode the cursor is also shown over the tiles around the canvas
+        let (margin_x, margin_y) = if canvases[active_canvas].tile_mode {
+            (
+                canvases[active_canvas].width as i32,
+                canvases[active_canvas].height as i32,
+            )
+        } else {
+            (0, 0)
+        };
+        let cursor_in_canvas = cursor_x as i32 >= -margin_x
+            && (cursor_x as i32) < canvases[active_canvas].width as i32 + margin_x
+            && cursor_y as i32 >= -margin_y
+            && (cursor_y as i32) < canvases[active_canvas].height as i32 + margin_y;
 
         // handle input
         if !mouse_over_ui && is_mouse_button_down(MouseButton::Middle) {
@@ -544,35 +1848,123 @@ async fn main() {
                 old_mouse_world_y * canvases[active_canvas].camera_grid_size - mouse.1;
         }
 
-        if !mouse_over_ui {
+        // only the active canvas shows the filter being edited, and tools are disabled meanwhile so nothing is drawn on the preview
+        for (index, canvas) in canvases.iter_mut().enumerate() {
+            if filter_window_open && filter_preview && index == active_canvas {
+                canvas.preview_filter(active_filter, filter_settings);
+            } else {
+                canvas.cancel_filter_preview();
+            }
+        }
+
+        if !mouse_over_ui && !filter_window_open {
             let canvas = &mut canvases[active_canvas];
-            let layer = &canvas.layers[canvas.current_layer];
-            let cursor_in_bounds = !(cursor_x < 0
-                || cursor_y < 0
-                || cursor_x as usize >= layer.width()
-                || cursor_y as usize >= layer.height());
-            active_tool.update(ToolContext {
-                canvas,
-                cursor_x,
-                cursor_y,
-                cursor_in_bounds,
-                last_cursor_x,
-                last_cursor_y,
-                primary_color: &mut primary_color,
-                secondary_color: &mut secondary_color,
-                settings: &mut tools_settings,
-            });
+            // while there is a pasted image, dragging moves it instead of using the active tool
+            if let Some(floating) = &mut canvas.floating {
+                if is_mouse_button_down(MouseButton::Left) {
+                    if let (Some(last_cursor_x), Some(last_cursor_y)) =
+                        (last_cursor_x, last_cursor_y)
+                    {
+                        floating.x += cursor_x - last_cursor_x;
+                        floating.y += cursor_y - last_cursor_y;
+                    }
+                }
+            } else {
+                // in indexed color mode tools draw and pick palette indices, so they get the stored pixel of the nearest palette color
+                let indexed = canvas.palette.is_some();
+                let to_stored = |color: [f32; 4]| {
+                    let color = color.map(|channel| (channel * 255.).round() as u8);
+                    canvas
+                        .encode_color(color)
+                        .map(|channel| channel as f32 / 255.)
+                };
+                let (mut tool_primary, mut tool_secondary) = if indexed {
+                    (to_stored(primary_color), to_stored(secondary_color))
+                } else {
+                    (primary_color, secondary_color)
+                };
+                let (old_primary, old_secondary) = (tool_primary, tool_secondary);
+                active_tool.update(ToolContext {
+                    canvas,
+                    input: ToolInput::from_window(),
+                    cursor_x,
+                    cursor_y,
+                    last_cursor_x,
+                    last_cursor_y,
+                    primary_color: &mut tool_primary,
+                    secondary_color: &mut tool_secondary,
+                    settings: &mut tools_settings,
+                });
+                // colors only change when picked, so the picked index becomes a color and the selected swatch
+                for (tool_color, old_color, color) in [
+                    (tool_primary, old_primary, &mut primary_color),
+                    (tool_secondary, old_secondary, &mut secondary_color),
+                ] {
+                    if tool_color == old_color {
+                        continue;
+                    }
+                    if !indexed {
+                        *color = tool_color;
+                        continue;
+                    }
+                    let stored = tool_color.map(|channel| (channel * 255.).round() as u8);
+                    if let Some(index) = pixel_index(stored) {
+                        selected_swatch = Some(index as usize);
+                    }
+                    *color = canvas
+                        .decode_color(stored)
+                        .map(|channel| channel as f32 / 255.);
+                }
+            }
         }
 
+        // advance animation
+        if playing {
+            let canvas = &mut canvases[active_canvas];
+            playback_time += get_frame_time();
+            loop {
+                let frame_time = if use_frame_durations {
+                    canvas.frames[canvas.current_frame].duration as f32 / 1000.
+                } else {
+                    1. / playback_fps as f32
+                };
+                if playback_time < frame_time {
+                    break;
+                }
+                playback_time -= frame_time;
+                canvas.set_frame((canvas.current_frame + 1) % canvas.frames.len());
+            }
+        }
+
+        // in tile mode the canvas is repeated around itself, so seams between tiles can be seen while painting
+        let tiles: Vec<(f32, f32)> = {
+            let canvas = &canvases[active_canvas];
+            let width = canvas.width as f32 * canvas.camera_grid_size;
+            let height = canvas.height as f32 * canvas.camera_grid_size;
+            let range = if canvas.tile_mode { -1..=1 } else { 0..=0 };
+            range
+                .clone()
+                .flat_map(|y| range.clone().map(move |x| (x, y)))
+                .map(|(x, y)| {
+                    (
+                        x as f32 * width - canvas.camera_x,
+                        y as f32 * height - canvas.camera_y,
+                    )
+                })
+                .collect()
+        };
+
         // draw grid background behind canvas
         gl_use_material(&grid_material);
-        draw_rectangle(
-            -canvases[active_canvas].camera_x,
-            -canvases[active_canvas].camera_y,
-            canvases[active_canvas].width as f32 * canvases[active_canvas].camera_grid_size,
-            canvases[active_canvas].height as f32 * canvases[active_canvas].camera_grid_size,
-            WHITE,
-        );
+        for (x, y) in tiles.iter().copied() {
+            draw_rectangle(
+                x,
+                y,
+                canvases[active_canvas].width as f32 * canvases[active_canvas].camera_grid_size,
+                canvases[active_canvas].height as f32 * canvases[active_canvas].camera_grid_size,
+                WHITE,
+            );
+        }
         gl_use_default_material();
         // draw canvas
         let draw_params = DrawTextureParams {
@@ -584,29 +1976,92 @@ async fn main() {
             )),
             ..Default::default()
         };
-        for (index, layer) in canvases[active_canvas].layers.iter().enumerate().rev() {
-            if layer.visible {
+        // layers with opacity or blend modes can't be drawn on top of each other directly, so draw their composite instead
+        let canvas = &mut canvases[active_canvas];
+
+        // draw previous and next frames faintly behind the current one, on every tile
+        if onion_skin && !playing {
+            let color = Color::new(1., 1., 1., ONION_SKIN_OPACITY);
+            let frames = [
+                canvas.current_frame.checked_sub(1),
+                Some(canvas.current_frame + 1).filter(|frame| *frame < canvas.frames.len()),
+            ];
+            for frame in frames.into_iter().flatten() {
+                let texture = layer_textures.get(canvas.onion_skin(frame));
+                for (x, y) in tiles.iter().copied() {
+                    draw_texture_ex(texture, x, y, color, draw_params.clone());
+                }
+            }
+        }
+
+        canvas.update_composite();
+        for (x, y) in tiles.iter().copied() {
+            if let Some(composite) = &mut canvas.composite {
                 draw_texture_ex(
-                    &layer.texture,
-                    -canvases[active_canvas].camera_x,
-                    -canvases[active_canvas].camera_y,
+                    layer_textures.get(composite),
+                    x,
+                    y,
                     WHITE,
                     draw_params.clone(),
                 );
+                continue;
+            }
+            for (index, layer) in canvas.layers.iter_mut().enumerate().rev() {
+                if layer.visible {
+                    draw_texture_ex(layer_textures.get(layer), x, y, WHITE, draw_params.clone());
 
-                // if layer is current, also draw the active draw buffer (current_changes.texture)
-                if index == canvases[active_canvas].current_layer {
-                    draw_texture_ex(
-                        &canvases[active_canvas].current_changes.texture,
-                        -canvases[active_canvas].camera_x,
-                        -canvases[active_canvas].camera_y,
-                        WHITE,
-                        draw_params.clone(),
-                    );
+                    // if layer is current, also draw the active draw buffer (current_changes)
+                    if index == canvas.current_layer {
+                        draw_texture_ex(
+                            layer_textures.get(&mut canvas.current_changes),
+                            x,
+                            y,
+                            WHITE,
+                            draw_params.clone(),
+                        );
+                    }
                 }
             }
         }
 
+        // draw pasted image on top of everything, with an outline
+        if let Some(floating) = &mut canvas.floating {
+            let x = floating.x as f32 * canvas.camera_grid_size - canvas.camera_x;
+            let y = floating.y as f32 * canvas.camera_grid_size - canvas.camera_y;
+            let w = floating.layer.width() as f32 * canvas.camera_grid_size;
+            let h = floating.layer.height() as f32 * canvas.camera_grid_size;
+            draw_texture_ex(
+                layer_textures.get(&mut floating.layer),
+                x,
+                y,
+                WHITE,
+                DrawTextureParams {
+                    dest_size: Some(vec2(w.floor(), h.floor())),
+                    ..Default::default()
+                },
+            );
+            draw_marching_ants(x, y, x + w, y);
+            draw_marching_ants(x + w, y, x + w, y + h);
+            draw_marching_ants(x, y + h, x + w, y + h);
+            draw_marching_ants(x, y, x, y + h);
+        }
+        layer_textures.clean();
+
+        // draw outline of selection
+        if let Some(selection) = &canvases[active_canvas].selection {
+            let canvas = &canvases[active_canvas];
+            for ((x1, y1), (x2, y2)) in &selection.borders {
+                draw_marching_ants(
+                    *x1 as f32 * canvas.camera_grid_size - canvas.camera_x,
+                    *y1 as f32 * canvas.camera_grid_size - canvas.camera_y,
+                    *x2 as f32 * canvas.camera_grid_size - canvas.camera_x,
+                    *y2 as f32 * canvas.camera_grid_size - canvas.camera_y,
+                );
+            }
+        }
+
+        draw_symmetry_axes(&canvases[active_canvas], &tools_settings.symmetry);
+
         // draw cursor (if in bounds)
         if cursor_in_canvas && !mouse_over_ui {
             let stroke = match active_tool.cursor_type() {
@@ -0,0 +1,60 @@
+use std::collections::{hash_map::Entry, HashMap, HashSet};
+
+use macroquad::prelude::*;
+use plow_core::{buffer::Image, canvas::Layer};
+
+fn texture_from(image: &Image) -> Texture2D {
+    let texture = Texture2D::from_rgba8(image.width, image.height, &image.bytes);
+    texture.set_filter(FilterMode::Nearest);
+    texture
+}
+
+/// Textures of layers, kept separately from the layers so they don't depend on the renderer.
+///
+/// Textures are identified by layer id, and only the regions of layers that have been updated are reuploaded.
+pub struct LayerTextures {
+    textures: HashMap<u64, Texture2D>,
+    /// Ids of layers that have been drawn since last [LayerTextures::clean]
+    used: HashSet<u64>,
+}
+
+impl LayerTextures {
+    pub fn new() -> Self {
+        LayerTextures {
+            textures: HashMap::new(),
+            used: HashSet::new(),
+        }
+    }
+    /// Get texture of layer, updating it if the layer has changed since last time
+    pub fn get(&mut self, layer: &mut Layer) -> &Texture2D {
+        let region = layer.render_tracker.flush();
+        self.used.insert(layer.id);
+        let texture = match self.textures.entry(layer.id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => return entry.insert(texture_from(&layer.image)),
+        };
+        if texture.width() as usize != layer.width() || texture.height() as usize != layer.height()
+        {
+            *texture = texture_from(&layer.image);
+        } else if let Some(region) = region {
+            let sub_image = layer.image.sub_image(region);
+            texture.update_part(
+                &macroquad::texture::Image {
+                    width: sub_image.width,
+                    height: sub_image.height,
+                    bytes: sub_image.bytes,
+                },
+                region.x as i32,
+                region.y as i32,
+                region.w as i32,
+                region.h as i32,
+            );
+        }
+        texture
+    }
+    /// Remove textures of layers that haven't been drawn since the last call, like deleted layers
+    pub fn clean(&mut self) {
+        self.textures.retain(|id, _| self.used.contains(id));
+        self.used.clear();
+    }
+}
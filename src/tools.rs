@@ -1,29 +1,50 @@
-use std::ops::RangeInclusive;
+use std::{
+    cell::{Cell, RefCell},
+    ops::RangeInclusive,
+};
 
-use egui_macroquad::egui::{DragValue, Slider, Ui};
+use egui_macroquad::egui::{self, DragValue, Slider, Ui};
 use macroquad::prelude::*;
-
-use crate::{canvas::*, consts::DIRECTIONS};
+use plow_core::{
+    buffer::Rect,
+    canvas::*,
+    fill::{fill_region, tolerance_from_percent},
+    gradient::{draw_gradient, ColorSpace, Gradient as PlowGradient, GradientShape, GradientStop},
+    stroke::{Stroke, StrokePath, StrokePattern, MAX_PATTERN_SIZE},
+    symmetry::Symmetry,
+};
+use strum::IntoEnumIterator;
 
 pub fn get_tools() -> Vec<Box<dyn Tool>> {
     vec![
         // all tools
-        Box::new(Brush),
+        Box::new(Brush::new()),
         Box::new(Eraser {
-            internal_brush: Brush,
+            internal_brush: Brush::new(),
         }),
         Box::new(Bucket),
         Box::new(ColorPicker),
+        Box::new(RectangleSelect {
+            start: Cell::new(None),
+        }),
+        Box::new(MagicWand),
+        Box::new(Shape::new(ShapeKind::Line)),
+        Box::new(Shape::new(ShapeKind::Rectangle)),
+        Box::new(Shape::new(ShapeKind::Ellipse)),
+        Box::new(Gradient {
+            start: Cell::new(None),
+            region: RefCell::new(None),
+        }),
     ]
 }
 
-fn rgb_array_to_color(rgb: &[f32; 4]) -> Color {
-    Color::from_rgba(
+fn rgb_array_to_color(rgb: &[f32; 4]) -> [u8; 4] {
+    [
         (rgb[0] * 255.).floor() as u8,
         (rgb[1] * 255.).floor() as u8,
         (rgb[2] * 255.).floor() as u8,
         (rgb[3] * 255.).floor() as u8,
-    )
+    ]
 }
 
 pub enum CursorType {
@@ -31,102 +52,25 @@ pub enum CursorType {
     Point,
 }
 
-pub struct Stroke {
-    pub size: u16,
-    pub pixels: Vec<Vec<bool>>,
-    pub pixels_offset: i16,
-    pub borders: Vec<((usize, usize), (usize, usize))>,
-}
-impl Stroke {
-    pub fn new(size: u16) -> Self {
-        let pixels = Self::generate_pixels(size);
-        let borders = Self::generate_borders(&pixels);
-        Stroke {
-            size,
-            pixels,
-            pixels_offset: 0,
-            borders,
-        }
-    }
-    pub fn update(&mut self) {
-        self.pixels = Self::generate_pixels(self.size);
-        self.borders = Self::generate_borders(&self.pixels);
-        self.pixels_offset = -(self.size as i16) / 2;
-    }
-    fn generate_pixels(size: u16) -> Vec<Vec<bool>> {
-        if size > 1 {
-            let half_brush_size = size as f32 / 2.;
-            let brush_size = size as i32;
-            let mut new = Vec::new();
-            for x in -brush_size / 2..brush_size / 2 + 1 {
-                new.push(Vec::new());
-                for y in -brush_size / 2..brush_size / 2 + 1 {
-                    if ((x * x + y * y) as f32) < half_brush_size * half_brush_size + 0.5 {
-                        new.last_mut().unwrap().push(true);
-                    } else {
-                        new.last_mut().unwrap().push(false);
-                    }
-                }
-            }
-            new
-        } else {
-            vec![vec![true]]
-        }
-    }
-    fn generate_borders(pixels: &[Vec<bool>]) -> Vec<((usize, usize), (usize, usize))> {
-        let mut new = Vec::new();
-        for (x, column) in pixels.iter().enumerate() {
-            for (y, value) in column.iter().enumerate() {
-                if !value {
-                    continue;
-                }
-                let mut neighbour_above = false;
-                let mut neighbour_below = false;
-                let mut neighbour_right = false;
-                let mut neighbour_left = false;
-                let mut neighbour_bools = [
-                    &mut neighbour_above,
-                    &mut neighbour_below,
-                    &mut neighbour_right,
-                    &mut neighbour_left,
-                ];
-                for (dir, bool) in DIRECTIONS.iter().zip(neighbour_bools.iter_mut()) {
-                    let Ok(x) = (x as isize + dir[0]).try_into() else {
-                        continue;
-                    };
-                    let Ok(y) = (y as isize + dir[1]).try_into() else {
-                        continue;
-                    };
-                    if let Some(column) = pixels.get::<usize>(x) {
-                        if let Some(value) = column.get::<usize>(y) {
-                            if *value {
-                                **bool = true;
-                            }
-                        }
-                    }
-                }
-                if !neighbour_above {
-                    new.push(((x, y + 1), (x + 1, y + 1)));
-                }
-                if !neighbour_below {
-                    new.push(((x, y), (x + 1, y)));
-                }
-                if !neighbour_left {
-                    new.push(((x, y), (x, y + 1)));
-                }
-                if !neighbour_right {
-                    new.push(((x + 1, y), (x + 1, y + 1)));
-                }
-            }
-        }
-        new
-    }
-}
-
 pub struct ToolsSettings {
     pub color_tolerance: u8,
     pub flood_mode_continuous: bool,
     pub stroke: Stroke,
+    /// Whether 1 pixel wide brush strokes leave out the doubled corners of turns
+    pub pixel_perfect: bool,
+    /// Symmetry the brush, eraser, shapes and bucket repeat what they draw with
+    pub symmetry: Symmetry,
+    /// Whether rectangles and ellipses are filled, or only drawn as outlines
+    pub shape_filled: bool,
+    pub gradient_shape: GradientShape,
+    pub gradient_space: ColorSpace,
+    /// Size of the bayer matrix gradients are dithered with, or None to interpolate colors
+    pub gradient_dither: Option<usize>,
+    /// Whether gradients use gradient_stops, instead of going from primary to secondary color
+    pub gradient_custom_stops: bool,
+    pub gradient_stops: Vec<GradientStop>,
+    /// Whether gradients only fill the area of similar color the drag starts in, found like the bucket does
+    pub gradient_fill_region: bool,
 }
 
 impl ToolsSettings {
@@ -135,15 +79,71 @@ impl ToolsSettings {
             color_tolerance: 0,
             flood_mode_continuous: true,
             stroke: Stroke::new(1),
+            pixel_perfect: false,
+            symmetry: Symmetry::new(),
+            shape_filled: false,
+            gradient_shape: GradientShape::Linear,
+            gradient_space: ColorSpace::Srgb,
+            gradient_dither: None,
+            gradient_custom_stops: false,
+            gradient_stops: vec![
+                GradientStop {
+                    position: 0.,
+                    color: [0., 0., 0., 1.],
+                },
+                GradientStop {
+                    position: 1.,
+                    color: [1., 1., 1., 1.],
+                },
+            ],
+            gradient_fill_region: false,
+        }
+    }
+}
+
+/// State of a mouse button during a frame
+#[derive(Clone, Copy, Default)]
+pub struct ButtonState {
+    /// Whether button was pressed this frame
+    pub pressed: bool,
+    pub down: bool,
+    /// Whether button was released this frame
+    pub released: bool,
+}
+
+/// Input of a frame, given to tools instead of them reading it from the window, so tools can also be scripted
+#[derive(Clone, Copy, Default)]
+pub struct ToolInput {
+    pub left: ButtonState,
+    pub right: ButtonState,
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+}
+
+impl ToolInput {
+    /// Read input of the current frame from the window
+    pub fn from_window() -> Self {
+        let button_state = |button| ButtonState {
+            pressed: is_mouse_button_pressed(button),
+            down: is_mouse_button_down(button),
+            released: is_mouse_button_released(button),
+        };
+        ToolInput {
+            left: button_state(MouseButton::Left),
+            right: button_state(MouseButton::Right),
+            shift: is_key_down(KeyCode::LeftShift),
+            ctrl: is_key_down(KeyCode::LeftControl),
+            alt: is_key_down(KeyCode::LeftAlt),
         }
     }
 }
 
 pub struct ToolContext<'a> {
     pub canvas: &'a mut Canvas,
+    pub input: ToolInput,
     pub cursor_x: i16,
     pub cursor_y: i16,
-    pub cursor_in_bounds: bool,
     pub last_cursor_x: Option<i16>,
     pub last_cursor_y: Option<i16>,
     pub primary_color: &'a mut [f32; 4],
@@ -162,10 +162,22 @@ pub trait Tool {
     fn keybind(&self) -> Option<KeyCode> {
         None
     }
+    /// Whether the tool uses the alt key itself, in which case holding alt won't switch to the color picker
+    fn uses_alt(&self) -> bool {
+        false
+    }
 }
 
-pub struct Brush;
+pub struct Brush {
+    /// Path of the current stroke, in pixel perfect mode
+    path: RefCell<StrokePath>,
+}
 impl Brush {
+    pub fn new() -> Self {
+        Brush {
+            path: RefCell::new(StrokePath::new()),
+        }
+    }
     #[allow(clippy::too_many_arguments)]
     fn draw_stroke(
         &self,
@@ -175,7 +187,9 @@ impl Brush {
         last_cursor_x: Option<i16>,
         last_cursor_y: Option<i16>,
         layer: &mut Layer,
-        draw_color: Color,
+        draw_color: [u8; 4],
+        selection: Option<&Selection>,
+        wrap: bool,
     ) {
         if let Some(last_cursor_x) = last_cursor_x {
             if let Some(last_cursor_y) = last_cursor_y {
@@ -187,10 +201,27 @@ impl Brush {
                     cursor_x,
                     cursor_y,
                     stroke,
+                    selection,
+                    wrap,
                 );
             }
         } else {
-            layer.set_pixel(cursor_x as u32, cursor_y as u32, draw_color);
+            let (width, height) = (layer.width() as i32, layer.height() as i32);
+            let (x, y) = (cursor_x as i32, cursor_y as i32);
+            let (x, y) = if wrap {
+                (x.rem_euclid(width), y.rem_euclid(height))
+            } else {
+                (x, y)
+            };
+            if x >= 0
+                && y >= 0
+                && x < width
+                && y < height
+                && selection.is_none_or(|s| s.contains(x as usize, y as usize))
+                && stroke.paints(x as usize, y as usize)
+            {
+                layer.set_pixel(x as u32, y as u32, draw_color);
+            }
         }
         layer.force_update_region(layer.bounds_tracker.to_rect());
     }
@@ -206,78 +237,117 @@ impl Tool for Brush {
         CursorType::Stroke
     }
     fn draw_buttons(&self, ui: &mut Ui, settings: &mut ToolsSettings) {
-        let brush_size_label = ui.label("brush size");
-        let drag_value = DragValue::new(&mut settings.stroke.size)
-            .update_while_editing(false)
-            .range(RangeInclusive::new(1, i32::MAX));
-        let resp = ui.add(drag_value).labelled_by(brush_size_label.id);
-        if resp.drag_stopped() || resp.lost_focus() {
-            settings.stroke.update();
-        }
+        draw_stroke_buttons(ui, &mut settings.stroke);
+        ui.add_enabled(
+            settings.stroke.size == 1,
+            egui::Checkbox::new(&mut settings.pixel_perfect, "pixel perfect"),
+        );
     }
     fn update(&self, ctx: ToolContext) {
-        let draw_color = if is_mouse_button_down(MouseButton::Left) {
+        let draw_color = if ctx.input.left.down {
             Some(rgb_array_to_color(ctx.primary_color))
-        } else if is_mouse_button_down(MouseButton::Right) {
+        } else if ctx.input.right.down {
             Some(rgb_array_to_color(ctx.secondary_color))
         } else {
             None
         };
 
         if let Some(draw_color) = draw_color {
-            self.draw_stroke(
-                &ctx.settings.stroke,
-                ctx.cursor_x,
-                ctx.cursor_y,
-                ctx.last_cursor_x,
-                ctx.last_cursor_y,
-                &mut ctx.canvas.current_changes,
-                draw_color,
-            );
+            let symmetry = ctx.settings.symmetry;
+            for index in 0..symmetry.count() {
+                let (cursor_x, cursor_y) = symmetry.transform(index, ctx.cursor_x, ctx.cursor_y);
+                let last_cursor = ctx
+                    .last_cursor_x
+                    .zip(ctx.last_cursor_y)
+                    .map(|(x, y)| symmetry.transform(index, x, y));
+                self.draw_stroke(
+                    &ctx.settings.stroke,
+                    cursor_x,
+                    cursor_y,
+                    last_cursor.map(|(x, _)| x),
+                    last_cursor.map(|(_, y)| y),
+                    &mut ctx.canvas.current_changes,
+                    draw_color,
+                    ctx.canvas.selection.as_ref(),
+                    ctx.canvas.tile_mode,
+                );
+            }
+            if ctx.settings.pixel_perfect && ctx.settings.stroke.size == 1 {
+                let mut path = self.path.borrow_mut();
+                if ctx.input.left.pressed || ctx.input.right.pressed {
+                    path.clear();
+                }
+                let from_x = ctx.last_cursor_x.unwrap_or(ctx.cursor_x);
+                let from_y = ctx.last_cursor_y.unwrap_or(ctx.cursor_y);
+                let corners: Vec<(usize, usize)> = path
+                    .extend(from_x, from_y, ctx.cursor_x, ctx.cursor_y)
+                    .into_iter()
+                    .flat_map(|(x, y)| symmetry.points(x, y))
+                    .filter_map(|(x, y)| ctx.canvas.wrap_position(x, y))
+                    .collect();
+                // corners are cleared from current_changes, so the layer below shows through again
+                let layer = &mut ctx.canvas.current_changes;
+                for (x, y) in corners {
+                    layer.set_pixel(x as u32, y as u32, [0; 4]);
+                }
+                layer.force_update_region(layer.bounds_tracker.to_rect());
+            }
         }
 
         // on release, flush the current_stroke image to the current layer's image
-        if is_mouse_button_released(MouseButton::Left)
-            || is_mouse_button_released(MouseButton::Right)
-        {
-            let bounds = ctx.canvas.current_changes.bounds_tracker.flush();
-            if let Some(bounds) = bounds {
-                // save old image in history
-
-                let sub_image = ctx.canvas.layers[ctx.canvas.current_layer]
-                    .image
-                    .sub_image(bounds);
-                ctx.canvas.undo_history.push(UndoAction::LayerRegion(
-                    ctx.canvas.current_layer,
-                    bounds,
-                    sub_image,
-                ));
-                let mut sub_image_stroke = ctx.canvas.current_changes.image.sub_image(bounds);
-
-                // write brush stroke data on image
-                update_image_region(
-                    &mut ctx.canvas.layers[ctx.canvas.current_layer].image,
-                    &bounds,
-                    &mut sub_image_stroke,
-                    true,
-                    true,
-                );
-
-                // update current_changes to reset it
-                update_image_region(
-                    &mut ctx.canvas.current_changes.image,
-                    &bounds,
-                    &mut sub_image_stroke,
-                    false,
-                    false,
-                );
-
-                ctx.canvas.layers[ctx.canvas.current_layer].force_update_region(Some(bounds));
-                ctx.canvas.current_changes.force_update_region(Some(bounds));
-            }
+        if ctx.input.left.released || ctx.input.right.released {
+            ctx.canvas.commit_current_changes();
         }
     }
 }
+/// Draw brush size and stroke pattern settings
+fn draw_stroke_buttons(ui: &mut Ui, stroke: &mut Stroke) {
+    let brush_size_label = ui.label("brush size");
+    let drag_value = DragValue::new(&mut stroke.size)
+        .update_while_editing(false)
+        .range(RangeInclusive::new(1, i32::MAX));
+    let resp = ui.add(drag_value).labelled_by(brush_size_label.id);
+    if resp.drag_stopped() || resp.lost_focus() {
+        stroke.update();
+    }
+    draw_pattern_buttons(ui, stroke);
+}
+/// Draw choice of stroke pattern, with its density or custom pixels
+fn draw_pattern_buttons(ui: &mut Ui, stroke: &mut Stroke) {
+    let pattern_text: &str = stroke.pattern.into();
+    egui::ComboBox::from_id_salt("stroke pattern")
+        .selected_text(pattern_text)
+        .show_ui(ui, |ui| {
+            for pattern in StrokePattern::iter() {
+                let text: &str = pattern.into();
+                ui.selectable_value(&mut stroke.pattern, pattern, text);
+            }
+        });
+    if stroke.pattern.bayer_size().is_some() {
+        ui.add(Slider::new(&mut stroke.pattern_density, 0.0..=1.0).text("density"));
+    } else if stroke.pattern == StrokePattern::Custom {
+        ui.menu_button("edit pattern", |ui| {
+            ui.add(
+                DragValue::new(&mut stroke.custom_pattern_size)
+                    .range(RangeInclusive::new(1, MAX_PATTERN_SIZE))
+                    .prefix("size "),
+            );
+            let size = stroke.custom_pattern_size;
+            egui::Grid::new("custom pattern").show(ui, |ui| {
+                for row in stroke.custom_pattern[..size].iter_mut() {
+                    for pixel in row[..size].iter_mut() {
+                        let text = if *pixel { "■" } else { "□" };
+                        if ui.selectable_label(*pixel, text).clicked() {
+                            *pixel = !*pixel;
+                        }
+                    }
+                    ui.end_row();
+                }
+            });
+        });
+    }
+}
+
 pub struct Eraser {
     // the eraser actually just delegates all its tasks to an internal brush
     // but with color set to transparent
@@ -294,20 +364,18 @@ impl Tool for Eraser {
         CursorType::Stroke
     }
     fn update(&self, ctx: ToolContext) {
-        if is_mouse_button_down(MouseButton::Left) || is_mouse_button_down(MouseButton::Right) {
+        if ctx.input.left.down || ctx.input.right.down {
             let mut ctx = ctx;
             let mut primary_color = [1., 0., 0., 1.];
             let mut secondary_color = primary_color;
             ctx.primary_color = &mut primary_color;
             ctx.secondary_color = &mut secondary_color;
             self.internal_brush.update(ctx);
-        } else if is_mouse_button_released(MouseButton::Left)
-            || is_mouse_button_released(MouseButton::Right)
-        {
+        } else if ctx.input.left.released || ctx.input.right.released {
             let region = ctx.canvas.current_changes.bounds_tracker.flush();
 
             if let Some(region) = region {
-                ctx.canvas.undo_history.push(UndoAction::LayerRegion(
+                ctx.canvas.push_undo_action(UndoAction::LayerRegion(
                     ctx.canvas.current_layer,
                     region.clone(),
                     ctx.canvas.layers[ctx.canvas.current_layer]
@@ -358,13 +426,14 @@ impl Tool for ColorPicker {
         Some(KeyCode::K)
     }
     fn update(&self, ctx: ToolContext) {
-        // return early if not cursor in bounds
-        if !ctx.cursor_in_bounds {
+        // return early if not cursor in bounds, which in tile mode repeat around the canvas
+        let Some((cursor_x, cursor_y)) = ctx.canvas.wrap_position(ctx.cursor_x, ctx.cursor_y)
+        else {
             return;
-        }
-        let color_slot = if is_mouse_button_pressed(MouseButton::Left) {
+        };
+        let color_slot = if ctx.input.left.pressed {
             Some(ctx.primary_color)
-        } else if is_mouse_button_pressed(MouseButton::Right) {
+        } else if ctx.input.right.pressed {
             Some(ctx.secondary_color)
         } else {
             None
@@ -372,204 +441,739 @@ impl Tool for ColorPicker {
         if let Some(color_slot) = color_slot {
             let color = ctx.canvas.layers[ctx.canvas.current_layer]
                 .image
-                .get_pixel(ctx.cursor_x as u32, ctx.cursor_y as u32);
-            let color = [color.r, color.g, color.b, color.a];
+                .get_pixel(cursor_x as u32, cursor_y as u32);
+            let color = color.map(|channel| channel as f32 / 255.);
             *color_slot = color;
         }
     }
 }
 
-fn compare_colors(color_a: [u8; 4], color_b: [u8; 4]) -> u16 {
-    let mut diffs = 0;
-    for part in 0..4 {
-        diffs += (color_a[part] as i16 - color_b[part] as i16).unsigned_abs()
+/// Get tolerance of the settings, see [tolerance_from_percent]
+fn get_tolerance(settings: &ToolsSettings) -> u16 {
+    tolerance_from_percent(settings.color_tolerance)
+}
+
+/// Draw settings for tools that find areas of similar color (bucket and magic wand)
+fn draw_fill_buttons(ui: &mut Ui, settings: &mut ToolsSettings, tool_name: &str) {
+    ui.checkbox(&mut settings.flood_mode_continuous, "continuous");
+    let tolerance_label = ui.label("tolerance");
+    let slider = Slider::new(&mut settings.color_tolerance, RangeInclusive::new(0, 100));
+    ui.add(slider)
+        .labelled_by(tolerance_label.id)
+        .on_hover_text(format!(
+            "color tolerance. 0 means {} will only find pixels that are exactly equal",
+            tool_name
+        ));
+}
+pub struct Bucket;
+impl Tool for Bucket {
+    fn name(&self) -> String {
+        String::from("bucket")
+    }
+    fn keybind(&self) -> Option<KeyCode> {
+        Some(KeyCode::F)
+    }
+    fn draw_buttons(&self, ui: &mut Ui, settings: &mut ToolsSettings) {
+        draw_fill_buttons(ui, settings, "bucket");
+    }
+    fn update(&self, ctx: ToolContext) {
+        // return early if mouse not in canvas, which in tile mode repeats around it
+        let Some((cursor_x, cursor_y)) = ctx.canvas.wrap_position(ctx.cursor_x, ctx.cursor_y)
+        else {
+            return;
+        };
+        // return early if cursor outside of selection
+        if let Some(selection) = &ctx.canvas.selection {
+            if !selection.contains(cursor_x, cursor_y) {
+                return;
+            }
+        }
+        let draw_color = if ctx.input.left.pressed {
+            Some(ctx.primary_color)
+        } else if ctx.input.right.pressed {
+            Some(ctx.secondary_color)
+        } else {
+            None
+        };
+        if let Some(draw_color) = draw_color {
+            // every copy of the cursor is filled from, in the same undo step
+            let mut positions: Vec<(usize, usize)> = Vec::new();
+            for (x, y) in ctx
+                .settings
+                .symmetry
+                .points(cursor_x as i16, cursor_y as i16)
+            {
+                if let Some((x, y)) = ctx.canvas.wrap_position(x, y) {
+                    if ctx
+                        .canvas
+                        .selection
+                        .as_ref()
+                        .is_none_or(|s| s.contains(x, y))
+                        && !positions.contains(&(x, y))
+                    {
+                        positions.push((x, y));
+                    }
+                }
+            }
+
+            let old_image = ctx.canvas.layers[ctx.canvas.current_layer].image.clone();
+            ctx.canvas
+                .push_undo_action(UndoAction::LayerFull(ctx.canvas.current_layer, old_image));
+
+            let width = ctx.canvas.layers[ctx.canvas.current_layer].width();
+            let tolerance = get_tolerance(ctx.settings);
+            let continuous = ctx.settings.flood_mode_continuous;
+
+            let pixel_at = |canvas: &Canvas, x: usize, y: usize| {
+                canvas.layers[canvas.current_layer].image.get_image_data()[x + y * width]
+            };
+            let starts: Vec<(usize, usize, [u8; 4])> = positions
+                .into_iter()
+                .map(|(x, y)| (x, y, pixel_at(ctx.canvas, x, y)))
+                .collect();
+            for (x, y, color) in starts {
+                // skip copies that an earlier fill already covered
+                if pixel_at(ctx.canvas, x, y) != color {
+                    continue;
+                }
+                let selection = ctx.canvas.selection.as_ref();
+                let region = ctx
+                    .canvas
+                    .find_region(x, y, tolerance, continuous, selection);
+                let layer = &mut ctx.canvas.layers[ctx.canvas.current_layer];
+                let mut bounds =
+                    fill_region(width, layer.get_image_data_mut(), &region, *draw_color);
+                layer.force_update_region(bounds.flush());
+            }
+        }
     }
-    diffs
 }
 
-pub fn overlay_colors(c1: [f32; 4], c2: [f32; 4]) -> [f32; 4] {
-    [
-        f32::min(c1[0] + (1. - c1[3]) * c2[0], 1.),
-        f32::min(c1[1] + (1. - c1[3]) * c2[1], 1.),
-        f32::min(c1[2] + (1. - c1[3]) * c2[2], 1.),
-        f32::min(c1[3] + (1. - c1[3]) * c2[3], 1.),
-    ]
+pub struct RectangleSelect {
+    /// Position where the current drag started
+    start: Cell<Option<(i32, i32)>>,
 }
+impl Tool for RectangleSelect {
+    fn name(&self) -> String {
+        String::from("rectangle select")
+    }
+    fn keybind(&self) -> Option<KeyCode> {
+        Some(KeyCode::M)
+    }
+    fn update(&self, ctx: ToolContext) {
+        // in i32, since the width of the biggest canvases doesn't fit in i16
+        let width = ctx.canvas.width as i32;
+        let height = ctx.canvas.height as i32;
+        // clamp cursor to canvas, so selection can be dragged from outside of it
+        let cursor_x = (ctx.cursor_x as i32).clamp(0, width - 1);
+        let cursor_y = (ctx.cursor_y as i32).clamp(0, height - 1);
+
+        if ctx.input.left.pressed {
+            self.start.set(Some((cursor_x, cursor_y)));
+        }
+        let Some((start_x, start_y)) = self.start.get() else {
+            return;
+        };
 
-pub fn overlay_images(source: &mut Image, other: &mut Image, empty_other: bool) {
-    for i in 0..source.bytes.len() / 4 {
-        let c1 = [
-            other.bytes[i * 4] as f32 / 255.,
-            other.bytes[i * 4 + 1] as f32 / 255.,
-            other.bytes[i * 4 + 2] as f32 / 255.,
-            other.bytes[i * 4 + 3] as f32 / 255.,
-        ];
-        let c2 = [
-            source.bytes[i * 4] as f32 / 255.,
-            source.bytes[i * 4 + 1] as f32 / 255.,
-            source.bytes[i * 4 + 2] as f32 / 255.,
-            source.bytes[i * 4 + 3] as f32 / 255.,
-        ];
-        let new_color = overlay_colors(c1, c2);
-
-        source.bytes[i * 4] = (new_color[0] * 255.) as u8;
-        source.bytes[i * 4 + 1] = (new_color[1] * 255.) as u8;
-        source.bytes[i * 4 + 2] = (new_color[2] * 255.) as u8;
-        source.bytes[i * 4 + 3] = (new_color[3] * 255.) as u8;
-
-        if empty_other {
-            other.bytes[i * 4] = 0;
-            other.bytes[i * 4 + 1] = 0;
-            other.bytes[i * 4 + 2] = 0;
-            other.bytes[i * 4 + 3] = 0;
+        if ctx.input.left.down {
+            let rect = Rect::new(
+                start_x.min(cursor_x) as f32,
+                start_y.min(cursor_y) as f32,
+                ((start_x - cursor_x).abs() + 1) as f32,
+                ((start_y - cursor_y).abs() + 1) as f32,
+            );
+            ctx.canvas.selection =
+                Some(Selection::from_rect(width as usize, height as usize, rect));
+        } else if ctx.input.left.released {
+            // clicking without dragging removes the selection
+            if (start_x, start_y) == (cursor_x, cursor_y) {
+                ctx.canvas.deselect();
+            }
+            self.start.set(None);
         }
     }
 }
 
-fn flood_fill(
-    width: usize,
-    height: usize,
-    pixels: &mut [[u8; 4]],
-    x: usize,
-    y: usize,
-    target_color: [f32; 4],
-    tolerance: u16,
-) -> BoundsTracker {
-    // convert target_color to u8
-    let target_color: [u8; 4] = [
-        (target_color[0] * 255.).floor() as u8,
-        (target_color[1] * 255.).floor() as u8,
-        (target_color[2] * 255.).floor() as u8,
-        (target_color[3] * 255.).floor() as u8,
-    ];
-
-    let mut bounds_tracker = BoundsTracker::new();
-    let mut visited = vec![false; width * height];
-    let mut buf: Vec<(usize, usize)> = vec![(x, y)];
-    visited[x + y * width] = true;
-    while let Some(item) = buf.pop() {
-        let x = item.0;
-        let y = item.1;
-        let old_color = pixels[x + y * width];
-        pixels[x + y * width] = target_color;
-        bounds_tracker.track(x as u32, y as u32);
-        for dir in DIRECTIONS {
-            let x = (x as isize + dir[0]).try_into();
-            let y = (y as isize + dir[1]).try_into();
-            let valid = x.is_ok() && y.is_ok() && x.unwrap() < width && y.unwrap() < height;
-            if valid {
-                let x: usize = x.unwrap();
-                let y: usize = y.unwrap();
-                let has_been_visited = visited[x + y * width];
-                if !has_been_visited
-                    && compare_colors(pixels[x + y * width], old_color) <= tolerance
-                {
-                    buf.push((x, y));
-                    visited[x + y * width] = true;
+pub struct MagicWand;
+impl Tool for MagicWand {
+    fn name(&self) -> String {
+        String::from("magic wand")
+    }
+    fn keybind(&self) -> Option<KeyCode> {
+        Some(KeyCode::W)
+    }
+    fn uses_alt(&self) -> bool {
+        true
+    }
+    fn draw_buttons(&self, ui: &mut Ui, settings: &mut ToolsSettings) {
+        draw_fill_buttons(ui, settings, "magic wand");
+        ui.label("shift: add, alt: subtract, ctrl: intersect");
+    }
+    fn update(&self, ctx: ToolContext) {
+        if !ctx.input.left.pressed {
+            return;
+        }
+        // in tile mode, clicks on the tiles around the canvas wrap around to it
+        let Some((cursor_x, cursor_y)) = ctx.canvas.wrap_position(ctx.cursor_x, ctx.cursor_y)
+        else {
+            return;
+        };
+        let mode = if ctx.input.shift {
+            SelectionMode::Add
+        } else if ctx.input.alt {
+            SelectionMode::Subtract
+        } else if ctx.input.ctrl {
+            SelectionMode::Intersect
+        } else {
+            SelectionMode::Replace
+        };
+
+        let width = ctx.canvas.width as usize;
+        let height = ctx.canvas.height as usize;
+        let tolerance = get_tolerance(ctx.settings);
+
+        // the current selection isn't passed on, since the new selection may extend outside of it
+        let region = ctx.canvas.find_region(
+            cursor_x,
+            cursor_y,
+            tolerance,
+            ctx.settings.flood_mode_continuous,
+            None,
+        );
+        ctx.canvas
+            .combine_selection(Selection::from_mask(width, height, region), mode);
+    }
+}
+
+/// Horizontal span of pixels (y, start x, end x), inclusive
+type Span = (i32, i32, i32);
+
+/// Get the spans of each row of a rectangle from x0, y0 to x1, y1 (inclusive)
+fn rectangle_spans(x0: i32, y0: i32, x1: i32, y1: i32) -> Vec<Span> {
+    (y0.min(y1)..=y0.max(y1))
+        .map(|y| (y, x0.min(x1), x0.max(x1)))
+        .collect()
+}
+
+/// Get the spans of each row of an ellipse fitting within the rectangle from x0, y0 to x1, y1 (inclusive)
+fn ellipse_spans(x0: i32, y0: i32, x1: i32, y1: i32) -> Vec<Span> {
+    let (x0, x1) = (x0.min(x1), x0.max(x1));
+    let (y0, y1) = (y0.min(y1), y0.max(y1));
+    let center_y = (y0 + y1) as f32 / 2.;
+    // radii reach the outer edges of the outermost pixels
+    let radius_x = (x1 - x0) as f32 / 2. + 0.5;
+    let radius_y = (y1 - y0) as f32 / 2. + 0.5;
+    (y0..=y1)
+        .map(|y| {
+            let dy = (y as f32 - center_y) / radius_y;
+            let half_width = radius_x * (1. - dy * dy).max(0.).sqrt();
+            // inset from both sides equally, so the ellipse stays symmetric
+            let inset = ((radius_x - half_width).round() as i32).min((x1 - x0) / 2);
+            (y, x0 + inset, x1 - inset)
+        })
+        .collect()
+}
+
+/// Get the outline of a shape made up of spans (one per row, in order), as horizontal line segments
+fn spans_outline(spans: &[Span]) -> Vec<((i32, i32), (i32, i32))> {
+    let mut segments = Vec::new();
+    for (index, (y, start, end)) in spans.iter().copied().enumerate() {
+        // pixels are inside if both neighbouring rows cover them, and they aren't at the ends of the row
+        let mut inner_start = start + 1;
+        let mut inner_end = end - 1;
+        for neighbour in [index.checked_sub(1), Some(index + 1)] {
+            match neighbour.and_then(|neighbour| spans.get(neighbour)) {
+                Some((_, neighbour_start, neighbour_end)) => {
+                    inner_start = inner_start.max(*neighbour_start);
+                    inner_end = inner_end.min(*neighbour_end);
+                }
+                None => {
+                    inner_start = end + 1;
+                    inner_end = start - 1;
                 }
             }
         }
+        if inner_start > inner_end {
+            segments.push(((start, y), (end, y)));
+        } else {
+            if inner_start > start {
+                segments.push(((start, y), (inner_start - 1, y)));
+            }
+            if inner_end < end {
+                segments.push(((inner_end + 1, y), (end, y)));
+            }
+        }
+    }
+    segments
+}
+
+/// Fill copy number index of symmetry of spans (one per row, in order) on layer with color, painting only pixels of the stroke's pattern. If selection is Some, only pixels within it are filled.
+///
+/// Each pixel is filled if the pixel it's a copy of is in the spans, so rotated copies have no gaps. If wrap is true, pixels outside of the layer wrap around to the other side.
+#[allow(clippy::too_many_arguments)]
+fn fill_spans(
+    layer: &mut Layer,
+    spans: &[Span],
+    color: [u8; 4],
+    stroke: &Stroke,
+    selection: Option<&Selection>,
+    symmetry: &Symmetry,
+    index: usize,
+    wrap: bool,
+) {
+    let Some((first_y, _, _)) = spans.first().copied() else {
+        return;
+    };
+    // bounds of the copy, which contains the copies of the ends of every span
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (i32::MAX, i32::MAX, i32::MIN, i32::MIN);
+    for (y, start, end) in spans.iter().copied() {
+        for x in [start, end] {
+            let (x, y) = symmetry.transform(index, x as i16, y as i16);
+            (min_x, min_y) = (min_x.min(x as i32), min_y.min(y as i32));
+            (max_x, max_y) = (max_x.max(x as i32), max_y.max(y as i32));
+        }
     }
-    bounds_tracker
-}
-
-fn global_fill(
-    width: usize,
-    pixels: &mut [[u8; 4]],
-    x: usize,
-    y: usize,
-    target_color: [f32; 4],
-    tolerance: u16,
-) -> BoundsTracker {
-    // start color
-    let start_color = pixels[x + y * width];
-
-    // convert target_color to u8
-    let target_color: [u8; 4] = [
-        (target_color[0] * 255.).floor() as u8,
-        (target_color[1] * 255.).floor() as u8,
-        (target_color[2] * 255.).floor() as u8,
-        (target_color[3] * 255.).floor() as u8,
-    ];
-    let mut bounds_tracker = BoundsTracker::new();
-    for (index, pixel) in pixels.iter_mut().enumerate() {
-        if compare_colors(*pixel, start_color) <= tolerance {
-            *pixel = target_color;
-            bounds_tracker.track((index % width) as u32, (index / width) as u32);
+    let width = layer.width() as i32;
+    let height = layer.height() as i32;
+    if !wrap {
+        (min_x, min_y) = (min_x.max(0), min_y.max(0));
+        (max_x, max_y) = (max_x.min(width - 1), max_y.min(height - 1));
+    }
+    for y in min_y..=max_y {
+        for x in min_x..=max_x {
+            let (source_x, source_y) = symmetry.inverse(index, x as i16, y as i16);
+            let inside = usize::try_from(source_y as i32 - first_y)
+                .ok()
+                .and_then(|row| spans.get(row))
+                .is_some_and(|(_, start, end)| (*start..=*end).contains(&(source_x as i32)));
+            let (x, y) = (x.rem_euclid(width), y.rem_euclid(height));
+            if inside
+                && selection.is_none_or(|s| s.contains(x as usize, y as usize))
+                && stroke.paints(x as usize, y as usize)
+            {
+                layer.set_pixel(x as u32, y as u32, color);
+            }
         }
     }
-    bounds_tracker
 }
-pub struct Bucket;
-impl Tool for Bucket {
+
+pub enum ShapeKind {
+    Line,
+    Rectangle,
+    Ellipse,
+}
+
+/// Tool for drawing shapes by dragging from one corner to the other. Shape is previewed in current_changes while dragging.
+pub struct Shape {
+    kind: ShapeKind,
+    /// Position where the current drag started, and whether it was with the left (primary) mouse button
+    start: Cell<Option<(i16, i16, bool)>>,
+}
+impl Shape {
+    pub fn new(kind: ShapeKind) -> Self {
+        Shape {
+            kind,
+            start: Cell::new(None),
+        }
+    }
+    /// Constrain end position when holding shift. Lines snap to 45 degree angles, and rectangles and ellipses become squares and circles.
+    fn constrain(&self, start_x: i16, start_y: i16, end_x: i16, end_y: i16) -> (i16, i16) {
+        let dx = end_x - start_x;
+        let dy = end_y - start_y;
+        let size = dx.abs().max(dy.abs());
+        let sign = |d: i16| if d < 0 { -1 } else { 1 };
+        match self.kind {
+            ShapeKind::Line if dx.abs() > dy.abs() * 2 => (end_x, start_y),
+            ShapeKind::Line if dy.abs() > dx.abs() * 2 => (start_x, end_y),
+            _ => (start_x + size * sign(dx), start_y + size * sign(dy)),
+        }
+    }
+    fn draw_shape(
+        &self,
+        ctx: &mut ToolContext,
+        start: (i16, i16),
+        end: (i16, i16),
+        draw_color: [u8; 4],
+    ) {
+        let layer = &mut ctx.canvas.current_changes;
+        let selection = ctx.canvas.selection.as_ref();
+        let stroke = &ctx.settings.stroke;
+        let symmetry = &ctx.settings.symmetry;
+        let wrap = ctx.canvas.tile_mode;
+        let (x0, y0, x1, y1) = (start.0 as i32, start.1 as i32, end.0 as i32, end.1 as i32);
+
+        let spans = match self.kind {
+            ShapeKind::Line => Vec::new(),
+            ShapeKind::Rectangle => rectangle_spans(x0, y0, x1, y1),
+            ShapeKind::Ellipse => ellipse_spans(x0, y0, x1, y1),
+        };
+        // lines are drawn between the copies of their ends
+        let lines = match self.kind {
+            ShapeKind::Line => vec![((x0, y0), (x1, y1))],
+            _ if ctx.settings.shape_filled => Vec::new(),
+            _ => spans_outline(&spans),
+        };
+        for index in 0..symmetry.count() {
+            if ctx.settings.shape_filled {
+                fill_spans(
+                    layer, &spans, draw_color, stroke, selection, symmetry, index, wrap,
+                );
+            }
+            for ((x1, y1), (x2, y2)) in lines.iter().copied() {
+                let (x1, y1) = symmetry.transform(index, x1 as i16, y1 as i16);
+                let (x2, y2) = symmetry.transform(index, x2 as i16, y2 as i16);
+                draw_line_image(layer, draw_color, x1, y1, x2, y2, stroke, selection, wrap);
+            }
+        }
+    }
+}
+impl Tool for Shape {
     fn name(&self) -> String {
-        String::from("bucket")
+        String::from(match self.kind {
+            ShapeKind::Line => "line",
+            ShapeKind::Rectangle => "rectangle",
+            ShapeKind::Ellipse => "ellipse",
+        })
     }
     fn keybind(&self) -> Option<KeyCode> {
-        Some(KeyCode::F)
+        Some(match self.kind {
+            ShapeKind::Line => KeyCode::L,
+            ShapeKind::Rectangle => KeyCode::R,
+            ShapeKind::Ellipse => KeyCode::O,
+        })
+    }
+    fn cursor_type(&self) -> CursorType {
+        CursorType::Stroke
     }
     fn draw_buttons(&self, ui: &mut Ui, settings: &mut ToolsSettings) {
-        ui.checkbox(&mut settings.flood_mode_continuous, "continuous");
-        let tolerance_label = ui.label("tolerance");
-        let slider = Slider::new(&mut settings.color_tolerance, RangeInclusive::new(0, 100));
-        ui.add(slider)
-            .labelled_by(tolerance_label.id)
-            .on_hover_text(
-                "color tolerance. 0 means bucket will only fill pixels that are exactly equal",
-            );
+        draw_stroke_buttons(ui, &mut settings.stroke);
+        if !matches!(self.kind, ShapeKind::Line) {
+            ui.checkbox(&mut settings.shape_filled, "filled");
+        }
+        ui.label("shift: constrain");
     }
-    fn update(&self, ctx: ToolContext) {
-        // return early if mouse not in canvas
-        //ctx.cursor_x < 0 || ctx.cursor_y < 0 || ctx.cursor_x >= ctx.canvas.layers[ctx.canvas.current_layer].width() || ctx.cursor_y >= ctx.canvas.layers[ctx.canvas.current_layer].height()
-        if !ctx.cursor_in_bounds {
+    fn update(&self, mut ctx: ToolContext) {
+        if ctx.input.left.pressed {
+            self.start.set(Some((ctx.cursor_x, ctx.cursor_y, true)));
+        } else if ctx.input.right.pressed {
+            self.start.set(Some((ctx.cursor_x, ctx.cursor_y, false)));
+        }
+        let Some((start_x, start_y, primary)) = self.start.get() else {
             return;
+        };
+        let button = if primary {
+            ctx.input.left
+        } else {
+            ctx.input.right
+        };
+
+        if button.down {
+            let end = if ctx.input.shift {
+                self.constrain(start_x, start_y, ctx.cursor_x, ctx.cursor_y)
+            } else {
+                (ctx.cursor_x, ctx.cursor_y)
+            };
+            let draw_color = if primary {
+                rgb_array_to_color(ctx.primary_color)
+            } else {
+                rgb_array_to_color(ctx.secondary_color)
+            };
+
+            // redraw preview
+            ctx.canvas.clear_current_changes();
+            self.draw_shape(&mut ctx, (start_x, start_y), end, draw_color);
+            let layer = &mut ctx.canvas.current_changes;
+            layer.force_update_region(layer.bounds_tracker.to_rect());
+        } else if button.released {
+            // on release, flush the preview to the current layer's image
+            ctx.canvas.commit_current_changes();
+            self.start.set(None);
         }
-        let draw_color = if is_mouse_button_pressed(MouseButton::Left) {
-            Some(ctx.primary_color)
-        } else if is_mouse_button_pressed(MouseButton::Right) {
-            Some(ctx.secondary_color)
+    }
+}
+
+/// Tool for filling the selection, or the whole canvas, with a gradient by dragging from its start to its end, optionally only inside the area a bucket fill from the start would cover. Gradient is previewed in current_changes while dragging.
+pub struct Gradient {
+    /// Position where the current drag started, and whether it was with the left (primary) mouse button
+    start: Cell<Option<(i16, i16, bool)>>,
+    /// Mask of the area found where the drag started, when filling only that area
+    region: RefCell<Option<Vec<bool>>>,
+}
+impl Tool for Gradient {
+    fn name(&self) -> String {
+        String::from("gradient")
+    }
+    fn keybind(&self) -> Option<KeyCode> {
+        Some(KeyCode::G)
+    }
+    fn draw_buttons(&self, ui: &mut Ui, settings: &mut ToolsSettings) {
+        let shape_text: &str = settings.gradient_shape.into();
+        egui::ComboBox::from_id_salt("gradient shape")
+            .selected_text(shape_text)
+            .show_ui(ui, |ui| {
+                for shape in GradientShape::iter() {
+                    let text: &str = shape.into();
+                    ui.selectable_value(&mut settings.gradient_shape, shape, text);
+                }
+            });
+        let dither_text = |dither: Option<usize>| match dither {
+            Some(size) => format!("bayer {}×{}", size, size),
+            None => String::from("smooth"),
+        };
+        egui::ComboBox::from_id_salt("gradient dither")
+            .selected_text(dither_text(settings.gradient_dither))
+            .show_ui(ui, |ui| {
+                for dither in [None, Some(2), Some(4), Some(8)] {
+                    ui.selectable_value(&mut settings.gradient_dither, dither, dither_text(dither));
+                }
+            });
+        if settings.gradient_dither.is_none() {
+            let space_text: &str = settings.gradient_space.into();
+            egui::ComboBox::from_id_salt("gradient color space")
+                .selected_text(space_text)
+                .show_ui(ui, |ui| {
+                    for space in ColorSpace::iter() {
+                        let text: &str = space.into();
+                        ui.selectable_value(&mut settings.gradient_space, space, text);
+                    }
+                });
+        }
+        ui.checkbox(&mut settings.gradient_custom_stops, "custom stops");
+        if settings.gradient_custom_stops {
+            ui.menu_button("edit stops", |ui| draw_gradient_stops(ui, settings));
+        }
+        ui.checkbox(&mut settings.gradient_fill_region, "fill region")
+            .on_hover_text("only fill the area the drag starts in, like the bucket");
+        if settings.gradient_fill_region {
+            draw_fill_buttons(ui, settings, "gradient");
+        }
+    }
+    fn update(&self, ctx: ToolContext) {
+        if ctx.input.left.pressed || ctx.input.right.pressed {
+            self.start
+                .set(Some((ctx.cursor_x, ctx.cursor_y, ctx.input.left.pressed)));
+            *self.region.borrow_mut() = None;
+            if ctx.settings.gradient_fill_region {
+                // in tile mode, drags starting on the tiles around the canvas find the area they wrap around to
+                let Some((x, y)) = ctx.canvas.wrap_position(ctx.cursor_x, ctx.cursor_y) else {
+                    self.start.set(None);
+                    return;
+                };
+                let tolerance = get_tolerance(ctx.settings);
+                let continuous = ctx.settings.flood_mode_continuous;
+                let region = ctx.canvas.find_region(x, y, tolerance, continuous, None);
+                *self.region.borrow_mut() = Some(region);
+            }
+        }
+        let Some((start_x, start_y, primary)) = self.start.get() else {
+            return;
+        };
+        let button = if primary {
+            ctx.input.left
         } else {
-            None
+            ctx.input.right
         };
-        if let Some(draw_color) = draw_color {
-            let old_image = ctx.canvas.layers[ctx.canvas.current_layer].image.clone();
-            ctx.canvas
-                .undo_history
-                .push(UndoAction::LayerFull(ctx.canvas.current_layer, old_image));
 
-            let width = ctx.canvas.layers[ctx.canvas.current_layer].width();
-            let height = ctx.canvas.layers[ctx.canvas.current_layer].height();
-            let pixels: &mut [[u8; 4]] =
-                ctx.canvas.layers[ctx.canvas.current_layer].get_image_data_mut();
-
-            // idek
-            // convert the 0-100 scale tolerance to a 0-1020 scale tolerance (1020=255*4)
-            // but not in a linear function
-            // i hate this but idk how to actually do this sort of color comparison
-            let tolerance = (1.04_f32.powf(ctx.settings.color_tolerance as f32)
-                / (4. / ctx.settings.color_tolerance as f32)) as u16;
-
-            let mut bounds = if ctx.settings.flood_mode_continuous {
-                flood_fill(
-                    width,
-                    height,
-                    pixels,
-                    ctx.cursor_x as usize,
-                    ctx.cursor_y as usize,
-                    *draw_color,
-                    tolerance,
-                )
+        if button.down {
+            let stops = if ctx.settings.gradient_custom_stops {
+                let mut stops = ctx.settings.gradient_stops.clone();
+                stops.sort_by(|a, b| a.position.total_cmp(&b.position));
+                stops
             } else {
-                global_fill(
-                    width,
-                    pixels,
-                    ctx.cursor_x as usize,
-                    ctx.cursor_y as usize,
-                    *draw_color,
-                    tolerance,
-                )
+                // in indexed color mode, tools are given stored pixels, so they are decoded to get colors to mix
+                let color = |color: &[f32; 4]| {
+                    ctx.canvas
+                        .decode_color(rgb_array_to_color(color))
+                        .map(|channel| channel as f32 / 255.)
+                };
+                vec![
+                    GradientStop {
+                        position: 0.,
+                        color: color(ctx.primary_color),
+                    },
+                    GradientStop {
+                        position: 1.,
+                        color: color(ctx.secondary_color),
+                    },
+                ]
             };
-            ctx.canvas.layers[ctx.canvas.current_layer].force_update_region(bounds.flush());
+            let mut gradient = PlowGradient {
+                shape: ctx.settings.gradient_shape,
+                space: ctx.settings.gradient_space,
+                dither: ctx.settings.gradient_dither,
+                stops,
+                start: (start_x as f32, start_y as f32),
+                end: (ctx.cursor_x as f32, ctx.cursor_y as f32),
+            };
+            // dragging with the right mouse button goes the other way
+            if !primary {
+                for stop in gradient.stops.iter_mut() {
+                    stop.position = 1. - stop.position;
+                }
+                gradient.stops.reverse();
+            }
+
+            // redraw preview
+            ctx.canvas.clear_current_changes();
+            let selection = ctx.canvas.selection.as_ref();
+            let colors = ctx.canvas.palette.as_ref().map(|p| p.colors.as_slice());
+            let region = self.region.borrow();
+            let layer = &mut ctx.canvas.current_changes;
+            let width = layer.width();
+            draw_gradient(layer, &gradient, colors, |x, y| {
+                selection.is_none_or(|s| s.contains(x, y))
+                    && region.as_ref().is_none_or(|region| region[x + y * width])
+            });
+            layer.force_update_region(layer.bounds_tracker.to_rect());
+        } else if button.released {
+            // on release, flush the preview to the current layer's image
+            ctx.canvas.commit_current_changes();
+            self.start.set(None);
+            *self.region.borrow_mut() = None;
         }
     }
 }
+
+/// Draw stops of custom gradients, which can be added, removed, moved and recolored
+fn draw_gradient_stops(ui: &mut Ui, settings: &mut ToolsSettings) {
+    let stops = &mut settings.gradient_stops;
+    // a gradient needs at least one stop
+    let removable = stops.len() > 1;
+    let mut removed = None;
+    for (index, stop) in stops.iter_mut().enumerate() {
+        ui.horizontal(|ui| {
+            ui.color_edit_button_rgba_unmultiplied(&mut stop.color);
+            ui.add(Slider::new(&mut stop.position, 0.0..=1.0));
+            if ui
+                .add_enabled(removable, egui::Button::new("remove"))
+                .clicked()
+            {
+                removed = Some(index);
+            }
+        });
+    }
+    if let Some(index) = removed {
+        stops.remove(index);
+    }
+    if ui.button("add").clicked() {
+        let color = stops
+            .last()
+            .map(|stop| stop.color)
+            .unwrap_or([0., 0., 0., 1.]);
+        stops.push(GradientStop {
+            position: 0.5,
+            color,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRESSED: ButtonState = ButtonState {
+        pressed: true,
+        down: true,
+        released: false,
+    };
+    const DOWN: ButtonState = ButtonState {
+        pressed: false,
+        down: true,
+        released: false,
+    };
+
+    /// Update tool with the cursor at x, y and the left mouse button in state left
+    fn use_tool(
+        tool: &dyn Tool,
+        canvas: &mut Canvas,
+        x: i16,
+        y: i16,
+        left: ButtonState,
+        color: [f32; 4],
+    ) {
+        let mut settings = ToolsSettings::new();
+        let (mut primary_color, mut secondary_color) = (color, [0.; 4]);
+        tool.update(ToolContext {
+            canvas,
+            input: ToolInput {
+                left,
+                ..Default::default()
+            },
+            cursor_x: x,
+            cursor_y: y,
+            last_cursor_x: None,
+            last_cursor_y: None,
+            primary_color: &mut primary_color,
+            secondary_color: &mut secondary_color,
+            settings: &mut settings,
+        });
+    }
+
+    /// 4x1 canvas with a black wall in the second column
+    fn walled_canvas() -> Canvas {
+        let mut canvas = Canvas::new(4, 1, String::from("test")).unwrap();
+        canvas.layers[0].set_pixel(1, 0, [0, 0, 0, 255]);
+        canvas
+    }
+
+    fn pixels(canvas: &Canvas) -> Vec<[u8; 4]> {
+        canvas.layers[0].image.get_image_data().to_vec()
+    }
+
+    #[test]
+    fn bucket_fills_and_undoes() {
+        let mut canvas = walled_canvas();
+        use_tool(&Bucket, &mut canvas, 2, 0, PRESSED, [1., 0., 0., 1.]);
+        let red = [255, 0, 0, 255];
+        assert_eq!(pixels(&canvas), [[0; 4], [0, 0, 0, 255], red, red]);
+        canvas.undo();
+        assert_eq!(pixels(&canvas), [[0; 4], [0, 0, 0, 255], [0; 4], [0; 4]]);
+    }
+
+    #[test]
+    fn magic_wand_selects_region() {
+        let mut canvas = walled_canvas();
+        use_tool(&MagicWand, &mut canvas, 3, 0, PRESSED, [0.; 4]);
+        let selection = canvas.selection.as_ref().unwrap();
+        let selected: Vec<bool> = (0..4).map(|x| selection.contains(x, 0)).collect();
+        assert_eq!(selected, [false, false, true, true]);
+    }
+
+    #[test]
+    fn rectangle_select_on_widest_canvas() {
+        let mut canvas = Canvas::new(32768, 2, String::from("test")).unwrap();
+        let tool = RectangleSelect {
+            start: Cell::new(None),
+        };
+        // dragged from outside of the canvas, so the start is clamped to it
+        use_tool(&tool, &mut canvas, -5, 0, PRESSED, [0.; 4]);
+        use_tool(&tool, &mut canvas, i16::MAX, 0, DOWN, [0.; 4]);
+        let selection = canvas.selection.as_ref().unwrap();
+        assert!(selection.contains(0, 0) && selection.contains(32767, 0));
+        assert!(!selection.contains(0, 1));
+    }
+
+    #[test]
+    fn region_tools_wrap_in_tile_mode() {
+        let mut canvas = walled_canvas();
+        // outside of the canvas nothing happens
+        use_tool(&MagicWand, &mut canvas, -2, 0, PRESSED, [0.; 4]);
+        assert!(canvas.selection.is_none());
+
+        canvas.tile_mode = true;
+        // on the tile to the left, which wraps around to the fourth column
+        use_tool(&MagicWand, &mut canvas, -1, 0, PRESSED, [0.; 4]);
+        let selection = canvas.selection.as_ref().unwrap();
+        let selected: Vec<bool> = (0..4).map(|x| selection.contains(x, 0)).collect();
+        // the area also spreads across the edge to the first column
+        assert_eq!(selected, [true, false, true, true]);
+    }
+
+    #[test]
+    fn brush_wraps_on_widest_canvas() {
+        let mut canvas = Canvas::new(32768, 1, String::from("test")).unwrap();
+        canvas.tile_mode = true;
+        use_tool(&Brush::new(), &mut canvas, -1, 0, DOWN, [1.; 4]);
+        assert_eq!(canvas.current_changes.image.get_pixel(32767, 0), [255; 4]);
+    }
+}